@@ -0,0 +1,103 @@
+//! Compares the RGB565 → RGBA8 converter with the one it replaced, on a GBA sized frame.
+//!
+//! Run with `cargo bench --bench convert`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)]
+#[path = "../src/emulator/convert.rs"]
+mod convert;
+
+use convert::{convert_pixel_from_rgb565, Converter, PixelFormat};
+
+const WIDTH: usize = 240;
+const HEIGHT: usize = 160;
+/// mGBA pads its rows to 256 pixels.
+const PITCH: usize = 256 * 2;
+const ITERATIONS: u32 = 2000;
+
+/// The converter as it was, allocating a new frame every time and converting pixel by pixel.
+fn allocating(frame: &[u8], width: usize, height: usize, pitch: usize) -> Vec<u32> {
+    let mut converted_frame = vec![0u32; width * height].into_boxed_slice();
+
+    for y in 0..height {
+        let row_bytes = &frame[y * pitch..y * pitch + width * 2];
+
+        for x in 0..width {
+            let pixel = ((row_bytes[x * 2 + 1] as u16) << 8) | row_bytes[x * 2] as u16;
+            converted_frame[y * width + x] =
+                PixelFormat::Rgba8.pack(convert_pixel_from_rgb565(pixel));
+        }
+    }
+
+    Vec::from(converted_frame)
+}
+
+fn measure(name: &str, mut run: impl FnMut()) -> Duration {
+    for _ in 0..ITERATIONS / 10 {
+        run();
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        run();
+    }
+    let per_frame = start.elapsed() / ITERATIONS;
+
+    println!(
+        "{:<24} {:>8.2} µs/frame",
+        name,
+        per_frame.as_secs_f64() * 1e6
+    );
+
+    per_frame
+}
+
+fn main() {
+    // A frame going through every RGB565 color, in a buffer with the core's pitch.
+    let frame: Vec<u8> = (0..PITCH * HEIGHT / 2)
+        .flat_map(|index| ((index * 7919) as u16).to_le_bytes())
+        .collect();
+
+    let plain = Converter::new(PixelFormat::Rgba8, None);
+    let colors: Vec<[u8; 3]> = (0..=u16::MAX)
+        .map(|color| [(color >> 8) as u8, color as u8, 0x42])
+        .collect();
+    let lookup = Converter::new(PixelFormat::Rgba8, Some(&colors));
+
+    let expected = allocating(&frame, WIDTH, HEIGHT, PITCH);
+    let mut out = Vec::new();
+    plain.convert(&frame, WIDTH, HEIGHT, PITCH, &mut out);
+    assert_eq!(
+        out, expected,
+        "the vector paths differ from the plain conversion"
+    );
+
+    lookup.convert(&frame, WIDTH, HEIGHT, PITCH, &mut out);
+    let looked_up: Vec<u32> = (0..HEIGHT)
+        .flat_map(|y| frame[y * PITCH..y * PITCH + WIDTH * 2].chunks_exact(2))
+        .map(|bytes| {
+            PixelFormat::Rgba8.pack(colors[u16::from_le_bytes([bytes[0], bytes[1]]) as usize])
+        })
+        .collect();
+    assert_eq!(out, looked_up, "the vector paths differ from the table");
+
+    let before = measure("allocating, per pixel", || {
+        black_box(allocating(black_box(&frame), WIDTH, HEIGHT, PITCH));
+    });
+    let after = measure("reused buffer, plain", || {
+        plain.convert(black_box(&frame), WIDTH, HEIGHT, PITCH, &mut out);
+        black_box(&out);
+    });
+    let profile = measure("reused buffer, profile", || {
+        lookup.convert(black_box(&frame), WIDTH, HEIGHT, PITCH, &mut out);
+        black_box(&out);
+    });
+
+    println!(
+        "speedup: {:.1}x plain, {:.1}x with a color profile",
+        before.as_secs_f64() / after.as_secs_f64(),
+        before.as_secs_f64() / profile.as_secs_f64()
+    );
+}
@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+
+use crate::emulator;
+
+/// A button of the joypad, with the keys and the gamepad button that press it.
+pub struct Binding {
+    pub name: &'static str,
+    pub id: i16,
+    pub keys: Vec<KeyCode>,
+    pub gamepad: GamepadButtonType,
+}
+
+/// Keyboard and gamepad bindings of the joypad, changed from the pause menu.
+#[derive(Resource)]
+pub struct Bindings {
+    pub buttons: Vec<Binding>,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        let binding = |name, id, keys: &[KeyCode], gamepad| Binding {
+            name,
+            id,
+            keys: keys.to_vec(),
+            gamepad,
+        };
+
+        // The arrows also have their vim keys.
+        Bindings {
+            buttons: vec![
+                binding(
+                    "A",
+                    emulator::DEVICE_ID_JOYPAD_A,
+                    &[KeyCode::A],
+                    GamepadButtonType::East,
+                ),
+                binding(
+                    "B",
+                    emulator::DEVICE_ID_JOYPAD_B,
+                    &[KeyCode::S],
+                    GamepadButtonType::South,
+                ),
+                binding(
+                    "Start",
+                    emulator::DEVICE_ID_JOYPAD_START,
+                    &[KeyCode::Return],
+                    GamepadButtonType::Start,
+                ),
+                binding(
+                    "Select",
+                    emulator::DEVICE_ID_JOYPAD_SELECT,
+                    &[KeyCode::Space],
+                    GamepadButtonType::Select,
+                ),
+                binding(
+                    "Up",
+                    emulator::DEVICE_ID_JOYPAD_UP,
+                    &[KeyCode::Up, KeyCode::K],
+                    GamepadButtonType::DPadUp,
+                ),
+                binding(
+                    "Down",
+                    emulator::DEVICE_ID_JOYPAD_DOWN,
+                    &[KeyCode::Down, KeyCode::J],
+                    GamepadButtonType::DPadDown,
+                ),
+                binding(
+                    "Left",
+                    emulator::DEVICE_ID_JOYPAD_LEFT,
+                    &[KeyCode::Left, KeyCode::H],
+                    GamepadButtonType::DPadLeft,
+                ),
+                binding(
+                    "Right",
+                    emulator::DEVICE_ID_JOYPAD_RIGHT,
+                    &[KeyCode::Right, KeyCode::L],
+                    GamepadButtonType::DPadRight,
+                ),
+            ],
+        }
+    }
+}
+
+impl Binding {
+    /// The keys and the gamepad button, as the menu lists them.
+    pub fn describe(&self) -> String {
+        let keys: Vec<String> = self.keys.iter().map(|key| format!("{:?}", key)).collect();
+
+        format!("{} / {:?}", keys.join(" "), self.gamepad)
+    }
+}
+
+/// Whether `button` was just pressed on any of the connected gamepads.
+pub fn gamepad_just_pressed(
+    gamepads: &Gamepads,
+    buttons: &Input<GamepadButton>,
+    button: GamepadButtonType,
+) -> bool {
+    gamepads
+        .iter()
+        .any(|gamepad| buttons.just_pressed(GamepadButton::new(gamepad, button)))
+}
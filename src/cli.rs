@@ -0,0 +1,282 @@
+use crate::emulator::color::ColorProfile;
+use crate::emulator::rotation::Rotation;
+use crate::emulator::search::Width;
+
+const USAGE: &str = "usage: advanced [OPTIONS] [ROM]
+
+Options:
+  --core FILE             run the ROM with the core in the shared library FILE instead
+                          of the built-in one
+  --patch FILE            apply an IPS/UPS/BPS patch to the ROM, can be repeated
+  --cheats FILE           add the cheats of a RetroArch .cht file to the game
+  --cheat CODE            add and enable a GameShark/Action Replay/CodeBreaker code
+  --movie FILE            play an input movie back, read-only
+  --record FILE           record an input movie from power-on
+  --record-video FILE     record the video and audio of the game, with ffmpeg when
+                          it's installed
+  --filter LIST           post-process the frames with a comma separated chain of
+                          filters, see `help` in the terminal for the list
+  --color PROFILE         correct the colors as the screen of a gba, gba-sp, gbc or nds
+                          showed them
+  --gamma G               gamma of the display used with --color, 2.2 by default
+  --rotation DEGREES      rotate the screen 0, 90, 180 or 270 degrees counter-clockwise,
+                          whatever the core asks for
+  --screenshots DIR       directory of the screenshots, `screenshots` by default
+  --screenshot-scale N    scale screenshots taken with --screenshot N times
+  --no-auto-state         don't write the auto state on exit nor resume from it
+  --auto-state-every MIN  also write the auto state every MIN minutes
+  --run-ahead N           run N frames ahead of the one shown to cut the input lag
+  --run-ahead-instance    run ahead on a second instance of the core, for cores whose
+                          sound skips when loading a state, needs --core
+  -h, --help              print this help
+
+Library:
+  --library DIR           look for games in DIR, can be repeated. Without a ROM, the
+                          games are listed to pick one
+  --dat FILE              name the games after a No-Intro DAT file
+  --thumbnails DIR        directory of the box art, `thumbnails` by default
+  --cores DIR             directory of the installed cores, `cores` by default
+  --playlist FILE         list the games of a JSON playlist, can be repeated. Games
+                          are added to the first one from the library
+
+Headless mode:
+  --headless              run the core without a window
+  --frames N              stop after N frames
+  --until ADDRESS=VALUE   stop once the byte at ADDRESS holds VALUE, use ADDRESS:16 or
+                          ADDRESS:32 to compare wider values
+  --dump-frames DIR       write the frames into DIR as PPM images
+  --dump-every N          only write one frame out of N
+  --wav FILE              write the audio to a WAV file
+  --save-state FILE       write the state of the core to FILE at the end
+  --screenshot            take a screenshot at the end
+
+Without --frames nor --until, a headless run stops at the end of the movie
+given with --movie.";
+
+/// Command line options.
+pub struct Args {
+    /// Path of the ROM to load, `test.gba` when not given.
+    pub rom: String,
+    /// Shared library of the core, the linked one when not given.
+    pub core: Option<String>,
+    /// Patches passed with `--patch`, in the order they should be applied.
+    pub patches: Vec<String>,
+    /// `.cht` files passed with `--cheats`.
+    pub cheat_files: Vec<String>,
+    /// Codes passed with `--cheat`.
+    pub cheat_codes: Vec<String>,
+    /// Movie to play back, passed with `--movie`.
+    pub movie: Option<String>,
+    /// Movie to record, passed with `--record`.
+    pub record: Option<String>,
+    /// Run without a window, with the options below.
+    pub headless: bool,
+    /// How many frames to run in headless mode.
+    pub frames: Option<u64>,
+    /// Stop the headless run once the value of `width` at `address` is `value`.
+    pub until: Option<(usize, Width, u32)>,
+    /// Directory to dump frames into.
+    pub dump_frames: Option<String>,
+    pub dump_every: u64,
+    /// File to write the audio into.
+    pub wav: Option<String>,
+    /// File to write the final state into.
+    pub save_state: Option<String>,
+    /// Take a screenshot at the end of the headless run.
+    pub screenshot: bool,
+    /// File to record the game's video into.
+    pub record_video: Option<String>,
+    /// Chain of filters passed with `--filter`.
+    pub filter: String,
+    /// Color profile passed with `--color`.
+    pub color: ColorProfile,
+    pub gamma: f32,
+    /// Rotation passed with `--rotation`, instead of the core's.
+    pub rotation: Option<Rotation>,
+    /// Directory screenshots are saved into.
+    pub screenshots: String,
+    pub screenshot_scale: usize,
+    /// Whether the auto state is written on exit and restored on launch.
+    pub auto_state: bool,
+    /// Minutes between auto states, given with `--auto-state-every`.
+    pub auto_state_every: Option<u64>,
+    /// Frames run ahead, given with `--run-ahead`.
+    pub run_ahead: u32,
+    /// Run ahead on a second instance of the core.
+    pub run_ahead_instance: bool,
+    /// Directories passed with `--library`.
+    pub libraries: Vec<String>,
+    pub dat: Option<String>,
+    pub thumbnails: String,
+    pub cores: String,
+    /// Playlists passed with `--playlist`.
+    pub playlists: Vec<String>,
+    /// Show the library instead of loading a ROM, when given `--library` or `--playlist`
+    /// without a ROM.
+    pub launcher: bool,
+}
+
+impl Args {
+    pub fn parse() -> Args {
+        let mut args = Args {
+            rom: String::from("test.gba"),
+            core: None,
+            patches: Vec::new(),
+            cheat_files: Vec::new(),
+            cheat_codes: Vec::new(),
+            movie: None,
+            record: None,
+            headless: false,
+            frames: None,
+            until: None,
+            dump_frames: None,
+            dump_every: 1,
+            wav: None,
+            save_state: None,
+            screenshot: false,
+            record_video: None,
+            filter: String::new(),
+            color: ColorProfile::Off,
+            gamma: 2.2,
+            rotation: None,
+            screenshots: String::from("screenshots"),
+            screenshot_scale: 1,
+            auto_state: true,
+            auto_state_every: None,
+            run_ahead: 0,
+            run_ahead_instance: false,
+            libraries: Vec::new(),
+            dat: None,
+            thumbnails: String::from("thumbnails"),
+            cores: String::from("cores"),
+            playlists: Vec::new(),
+            launcher: false,
+        };
+        let mut rom_given = false;
+
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--patch" => args.patches.push(value(&mut iter, &arg)),
+                "--cheats" => args.cheat_files.push(value(&mut iter, &arg)),
+                "--cheat" => args.cheat_codes.push(value(&mut iter, &arg)),
+                "--movie" => args.movie = Some(value(&mut iter, &arg)),
+                "--record" => args.record = Some(value(&mut iter, &arg)),
+                "--headless" => args.headless = true,
+                "--frames" => args.frames = Some(number(&value(&mut iter, &arg), &arg) as u64),
+                "--until" => args.until = Some(condition(&value(&mut iter, &arg))),
+                "--dump-frames" => args.dump_frames = Some(value(&mut iter, &arg)),
+                "--dump-every" => {
+                    args.dump_every = (number(&value(&mut iter, &arg), &arg) as u64).max(1)
+                }
+                "--wav" => args.wav = Some(value(&mut iter, &arg)),
+                "--save-state" => args.save_state = Some(value(&mut iter, &arg)),
+                "--record-video" => args.record_video = Some(value(&mut iter, &arg)),
+                "--filter" => args.filter = value(&mut iter, &arg),
+                "--color" => {
+                    let name = value(&mut iter, &arg);
+                    args.color = ColorProfile::parse(&name)
+                        .unwrap_or_else(|| usage_error(&format!("unknown color profile {}", name)));
+                }
+                "--gamma" => {
+                    args.gamma = match value(&mut iter, &arg).parse() {
+                        Ok(gamma) if gamma > 0.0 => gamma,
+                        _ => usage_error("--gamma expects a positive number"),
+                    }
+                }
+                "--rotation" => {
+                    args.rotation = match Rotation::parse(&value(&mut iter, &arg)) {
+                        Some(rotation) => Some(rotation),
+                        None => usage_error("--rotation expects 0, 90, 180 or 270"),
+                    }
+                }
+                "--screenshot" => args.screenshot = true,
+                "--screenshots" => args.screenshots = value(&mut iter, &arg),
+                "--screenshot-scale" => {
+                    args.screenshot_scale = number(&value(&mut iter, &arg), &arg).max(1)
+                }
+                "--core" => args.core = Some(value(&mut iter, &arg)),
+                "--no-auto-state" => args.auto_state = false,
+                "--auto-state-every" => {
+                    args.auto_state_every =
+                        Some(number(&value(&mut iter, &arg), &arg).max(1) as u64)
+                }
+                "--run-ahead" => args.run_ahead = number(&value(&mut iter, &arg), &arg) as u32,
+                "--run-ahead-instance" => args.run_ahead_instance = true,
+                "--library" => args.libraries.push(value(&mut iter, &arg)),
+                "--dat" => args.dat = Some(value(&mut iter, &arg)),
+                "--thumbnails" => args.thumbnails = value(&mut iter, &arg),
+                "--cores" => args.cores = value(&mut iter, &arg),
+                "--playlist" => args.playlists.push(value(&mut iter, &arg)),
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ if arg.starts_with('-') => usage_error(&format!("unknown option {}", arg)),
+                _ => {
+                    args.rom = arg;
+                    rom_given = true;
+                }
+            }
+        }
+
+        args.launcher = (!args.libraries.is_empty() || !args.playlists.is_empty()) && !rom_given;
+        if args.launcher && args.headless {
+            usage_error("--headless needs a ROM");
+        }
+        // The built-in core is linked in once, it can't have a second instance. The library
+        // switches to shared cores, which can, so it's only known there once a game launches.
+        if args.run_ahead_instance && args.core.is_none() && !args.launcher {
+            usage_error("--run-ahead-instance needs a core given with --core");
+        }
+
+        args
+    }
+}
+
+/// Take the value of an option, exiting when it is missing.
+fn value(iter: &mut impl Iterator<Item = String>, option: &str) -> String {
+    match iter.next() {
+        Some(value) => value,
+        None => usage_error(&format!("{} expects a value", option)),
+    }
+}
+
+/// Parse a decimal or `0x` hexadecimal number, exiting when it isn't one.
+fn number(text: &str, option: &str) -> usize {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+
+    match parsed {
+        Ok(number) => number,
+        Err(_) => usage_error(&format!("{} expects a number, not {}", option, text)),
+    }
+}
+
+/// Parse the `ADDRESS[:BITS]=VALUE` condition of `--until`.
+fn condition(text: &str) -> (usize, Width, u32) {
+    let Some((address, value)) = text.split_once('=') else {
+        usage_error("--until expects ADDRESS=VALUE");
+    };
+
+    let (address, width) = match address.split_once(':') {
+        Some((address, bits)) => match Width::from_bits(bits) {
+            Some(width) => (address, width),
+            None => usage_error("--until compares 8, 16 or 32 bits"),
+        },
+        None => (address, Width::U8),
+    };
+
+    (
+        number(address, "--until"),
+        width,
+        number(value, "--until") as u32,
+    )
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    std::process::exit(1);
+}
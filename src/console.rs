@@ -0,0 +1,316 @@
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+
+use bevy::prelude::*;
+
+use crate::emulator::color::ColorProfile;
+use crate::emulator::movie::MovieMode;
+use crate::emulator::search::{Compare, Endian, Freeze, Operand, RamSearch, Width};
+use crate::filter::Filters;
+use crate::watch::{Watch, Watches};
+use crate::EMU;
+
+const HELP: &str = "commands:
+  search new [8|16|32] [le|be]   snapshot RAM and start a new search
+  search eq|ne|gt|lt [VALUE]     keep candidates equal/not equal/greater/less than VALUE,
+                                 or than their previous value when VALUE is omitted
+  search list                    print the remaining candidates
+  freeze ADDRESS VALUE           write VALUE to ADDRESS on every frame
+  unfreeze ADDRESS               stop freezing ADDRESS
+  freezes                        print the frozen addresses
+  poke ADDRESS VALUE             write VALUE to ADDRESS once
+  peek ADDRESS [LEN]             print LEN bytes (16 by default) starting at ADDRESS
+  value ADDRESS [8|16|32]        print the value at ADDRESS, as the guest CPU reads it
+  watch ADDRESS TYPE FORMAT LABEL
+                                 show ADDRESS on the watch panel (F2), TYPE is u8, u16,
+                                 u32, s8, s16 or s32 and FORMAT is dec or hex
+  unwatch INDEX                  remove a watch from the panel
+  watches                        print the watches
+  movie record FILE [state]      record an input movie from power-on, or from the current
+                                 state with `state`
+  movie play FILE [ro|rw]        play a movie back, read-only unless `rw` is given
+  movie readonly on|off          switch the movie being played to read-only or read-write
+  movie stop                     stop the movie, saving it unless it was played read-only
+  movie                          print the state of the movie
+  state save|load                save or load the game's state (F5 and F9)
+  color [PROFILE] [GAMMA]        correct the colors as the screen of a gba, gba-sp, gbc or
+                                 nds did, for a display of GAMMA (2.2), `off` to disable
+                                 and without arguments print the current profile (F4)
+  filter LIST                    post-process the frames with a comma separated chain of
+                                 filters, applied in order, or `none`:
+                                   2x..6x             nearest neighbour scaling
+                                   sharp-bilinear:S   sharp scaling to a fractional S
+                                   scale2x, scale3x   pixel-art scalers
+                                   scanlines          darken every other row
+                                   lcd[:N]            scale by N (3) with an LCD grid
+                                   ghosting           blend with the previous frames
+  help                           print this help
+
+freeze and poke use the width and endianness of the current search, 8 bits
+little endian without one. Numbers are decimal or hexadecimal with 0x.";
+
+/// How many candidates `search list` prints at most.
+const LIST_LIMIT: usize = 32;
+
+/// Commands typed on the terminal while the game runs.
+///
+/// Stdin is read on its own thread so the game never waits for a line, and commands run in a
+/// system between frames, when the core isn't touching its memory.
+#[derive(Resource)]
+pub struct Console {
+    lines: Mutex<Receiver<String>>,
+    search: Option<RamSearch>,
+}
+
+impl Console {
+    pub fn spawn() -> Console {
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Console {
+            lines: Mutex::new(receiver),
+            search: None,
+        }
+    }
+}
+
+pub fn console(
+    mut console: ResMut<Console>,
+    mut watches: ResMut<Watches>,
+    mut filters: ResMut<Filters>,
+) {
+    let lines: Vec<String> = console.lines.lock().unwrap().try_iter().collect();
+
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        if let Err(message) = run(&mut console, &mut watches, &mut filters, &words) {
+            println!("{}", message);
+        }
+    }
+}
+
+fn run(
+    console: &mut Console,
+    watches: &mut Watches,
+    filters: &mut Filters,
+    words: &[&str],
+) -> Result<(), String> {
+    match words {
+        ["search", "new", options @ ..] => {
+            let mut width = Width::U8;
+            let mut endian = Endian::Little;
+
+            for option in options {
+                match *option {
+                    "le" => endian = Endian::Little,
+                    "be" => endian = Endian::Big,
+                    bits => width = Width::from_bits(bits).ok_or("width is 8, 16 or 32")?,
+                }
+            }
+
+            let search = RamSearch::new(width, endian);
+            println!("{} candidates", search.candidates().len());
+            console.search = Some(search);
+        }
+        ["search", "list"] => {
+            let search = console
+                .search
+                .as_ref()
+                .ok_or("no search, use `search new`")?;
+
+            for candidate in search.candidates().iter().take(LIST_LIMIT) {
+                let current = search.current(candidate.address).unwrap_or(candidate.value);
+                println!(
+                    "{:#010x}  {} (was {})",
+                    candidate.address, current, candidate.value
+                );
+            }
+
+            if search.candidates().len() > LIST_LIMIT {
+                println!("... {} more", search.candidates().len() - LIST_LIMIT);
+            }
+        }
+        ["search", comparison, value @ ..] => {
+            let compare = match *comparison {
+                "eq" => Compare::Equal,
+                "ne" => Compare::NotEqual,
+                "gt" => Compare::Greater,
+                "lt" => Compare::Less,
+                _ => return Err(HELP.to_string()),
+            };
+
+            let operand = match value {
+                [] => Operand::Previous,
+                [value] => Operand::Value(number(value)? as u32),
+                _ => return Err(HELP.to_string()),
+            };
+
+            let search = console
+                .search
+                .as_mut()
+                .ok_or("no search, use `search new`")?;
+            search.filter(compare, operand);
+            println!("{} candidates", search.candidates().len());
+        }
+        ["freeze", address, value] => {
+            let (width, endian) = format(console);
+
+            EMU.freeze(Freeze {
+                address: number(address)?,
+                value: number(value)? as u32,
+                width,
+                endian,
+            });
+        }
+        ["unfreeze", address] => EMU.unfreeze(number(address)?),
+        ["freezes"] => {
+            for freeze in EMU.freezes() {
+                println!(
+                    "{:#010x}  {} ({:?}, {:?})",
+                    freeze.address, freeze.value, freeze.width, freeze.endian
+                );
+            }
+        }
+        ["poke", address, value] => {
+            let (width, endian) = format(console);
+
+            EMU.poke(number(address)?, number(value)? as u32, width, endian)
+                .map_err(|err| err.to_string())?;
+        }
+        ["peek", address, len @ ..] => {
+            let address = number(address)?;
+            let len = match len {
+                [] => 16,
+                [len] => number(len)?,
+                _ => return Err(HELP.to_string()),
+            };
+
+            let bytes = EMU
+                .read_memory(address, len)
+                .map_err(|err| err.to_string())?;
+            for (row, chunk) in bytes.chunks(16).enumerate() {
+                let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+                println!("{:#010x}  {}", address + row * 16, hex.join(" "));
+            }
+        }
+        ["value", address, width @ ..] => {
+            let width = match width {
+                [] => Width::U8,
+                [bits] => Width::from_bits(bits).ok_or("width is 8, 16 or 32")?,
+                _ => return Err(HELP.to_string()),
+            };
+
+            let value = EMU
+                .read_value(number(address)?, width)
+                .map_err(|err| err.to_string())?;
+            println!("{} ({:#x})", value, value);
+        }
+        ["watch", definition @ ..] => watches.add(Watch::parse(&definition.join(" "))?),
+        ["unwatch", index] => {
+            if watches.remove(number(index)?).is_none() {
+                return Err(format!("there is no watch {}", index));
+            }
+        }
+        ["watches"] => {
+            for (index, watch) in watches.entries().iter().enumerate() {
+                println!(
+                    "{}  {:#010x}  {:?} {:?}  {}",
+                    index, watch.address, watch.kind, watch.format, watch.label
+                );
+            }
+        }
+        ["movie", "record", path, from @ ..] => {
+            let from_state = match from {
+                [] => false,
+                ["state"] => true,
+                _ => return Err(HELP.to_string()),
+            };
+
+            EMU.record_movie(path, from_state)?;
+        }
+        ["movie", "play", path, mode @ ..] => {
+            let read_only = match mode {
+                [] | ["ro"] => true,
+                ["rw"] => false,
+                _ => return Err(HELP.to_string()),
+            };
+
+            EMU.play_movie(path, read_only)?;
+        }
+        ["movie", "readonly", toggle] => match *toggle {
+            "on" => EMU.set_movie_read_only(true),
+            "off" => EMU.set_movie_read_only(false),
+            _ => return Err(HELP.to_string()),
+        },
+        ["movie", "stop"] => EMU.stop_movie(),
+        ["movie"] => match EMU.movie_status() {
+            Some((mode, frame, len, rerecords)) => {
+                let mode = match mode {
+                    MovieMode::Recording => "recording",
+                    MovieMode::Playing { read_only: true } => "playing (read-only)",
+                    MovieMode::Playing { read_only: false } => "playing (read-write)",
+                };
+
+                println!("{}, frame {}/{}, {} rerecords", mode, frame, len, rerecords);
+            }
+            None => println!("no movie"),
+        },
+        ["color"] => {
+            let (profile, gamma) = EMU.color_profile();
+            println!("{} (gamma {})", profile.name(), gamma);
+        }
+        ["color", profile, gamma @ ..] => {
+            let profile = ColorProfile::parse(profile).ok_or("unknown color profile")?;
+            let gamma = match gamma {
+                [] => EMU.color_profile().1,
+                [gamma] => gamma
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|gamma| *gamma > 0.0)
+                    .ok_or("gamma is a positive number")?,
+                _ => return Err(HELP.to_string()),
+            };
+
+            EMU.set_color_profile(profile, gamma);
+        }
+        ["filter", list] => *filters = Filters::parse(list)?,
+        ["state", "save"] => EMU.save_state(),
+        ["state", "load"] => EMU.load_state(),
+        _ => return Err(HELP.to_string()),
+    }
+
+    Ok(())
+}
+
+/// Width and endianness of the current search, used by `freeze` and `poke`.
+fn format(console: &Console) -> (Width, Endian) {
+    match &console.search {
+        Some(search) => (search.width, search.endian),
+        None => (Width::U8, Endian::Little),
+    }
+}
+
+fn number(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+
+    parsed.map_err(|_| format!("{} is not a number", text))
+}
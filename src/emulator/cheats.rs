@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::Path;
+
+/// Code formats the GBA cores accept through `retro_cheat_set`.
+///
+/// The core does the decoding; the front-end only tells them apart to show them to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    /// GameShark and Action Replay codes, `XXXXXXXX YYYYYYYY` per line. Both share the same
+    /// shape, so they can't be told apart by looking at them.
+    GameShark,
+    /// CodeBreaker codes, `XXXXXXXX YYYY` per line.
+    CodeBreaker,
+    /// Anything else, handed to the core as is.
+    Unknown,
+}
+
+impl CheatKind {
+    pub fn detect(code: &str) -> CheatKind {
+        let shapes: Vec<(usize, usize)> = code
+            .split(['+', '\n'])
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| match line.split_once([' ', ':']) {
+                Some((address, value)) if is_hex(address) && is_hex(value.trim()) => {
+                    (address.len(), value.trim().len())
+                }
+                _ => (0, 0),
+            })
+            .collect();
+
+        if shapes.is_empty() {
+            CheatKind::Unknown
+        } else if shapes.iter().all(|shape| *shape == (8, 8)) {
+            CheatKind::GameShark
+        } else if shapes.iter().all(|shape| *shape == (8, 4)) {
+            CheatKind::CodeBreaker
+        } else {
+            CheatKind::Unknown
+        }
+    }
+}
+
+fn is_hex(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[derive(Debug, Clone)]
+pub struct Cheat {
+    pub description: String,
+    /// Lines of the code joined by `+`, as in RetroArch's `.cht` files.
+    pub code: String,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    pub fn new(description: &str, code: &str, enabled: bool) -> Cheat {
+        let code = code
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<&str>>()
+            .join("+");
+
+        Cheat {
+            description: description.to_string(),
+            code,
+            enabled,
+        }
+    }
+
+    pub fn kind(&self) -> CheatKind {
+        CheatKind::detect(&self.code)
+    }
+}
+
+/// Parse a RetroArch `.cht` file.
+///
+/// ```text
+/// cheats = 1
+///
+/// cheat0_desc = "Infinite health"
+/// cheat0_code = "82003BE4 0063"
+/// cheat0_enable = false
+/// ```
+pub fn parse_cht(text: &str) -> Vec<Cheat> {
+    let mut count = 0usize;
+    let mut entries: HashMap<String, String> = HashMap::new();
+
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        let key = key.trim();
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value)
+            .replace("\\\"", "\"");
+
+        if key == "cheats" {
+            count = value.parse().unwrap_or(0);
+        } else {
+            entries.insert(key.to_string(), value);
+        }
+    }
+
+    // The cheats are the codes the file holds, `cheats` only tells how many of them count.
+    let mut indices: Vec<usize> = entries
+        .keys()
+        .filter_map(|key| {
+            key.strip_prefix("cheat")?
+                .strip_suffix("_code")?
+                .parse()
+                .ok()
+        })
+        .filter(|index| *index < count)
+        .collect();
+    indices.sort_unstable();
+
+    let lookup = |key: String| entries.get(&key).map(String::as_str);
+
+    indices
+        .into_iter()
+        .filter_map(|index| {
+            let code = lookup(format!("cheat{}_code", index))?;
+            let description = lookup(format!("cheat{}_desc", index)).unwrap_or("");
+            let enabled = lookup(format!("cheat{}_enable", index)) == Some("true");
+
+            Some(Cheat::new(description, &code.replace('+', "\n"), enabled))
+        })
+        .collect()
+}
+
+/// Write cheats in RetroArch's `.cht` format. Quotes in the values are escaped with a backslash.
+pub fn to_cht(cheats: &[Cheat]) -> String {
+    let escape = |value: &str| value.replace('"', "\\\"");
+
+    let mut text = format!("cheats = {}\n", cheats.len());
+
+    for (index, cheat) in cheats.iter().enumerate() {
+        text.push_str(&format!(
+            "\ncheat{0}_desc = \"{1}\"\ncheat{0}_code = \"{2}\"\ncheat{0}_enable = {3}\n",
+            index,
+            escape(&cheat.description),
+            escape(&cheat.code),
+            cheat.enabled
+        ));
+    }
+
+    text
+}
+
+pub fn load_cht(path: &Path) -> std::io::Result<Vec<Cheat>> {
+    Ok(parse_cht(&std::fs::read_to_string(path)?))
+}
+
+pub fn save_cht(path: &Path, cheats: &[Cheat]) -> std::io::Result<()> {
+    std::fs::write(path, to_cht(cheats))
+}
+
+/// Hand the cheats over to the core, replacing whatever it had before.
+///
+/// Cores forget their cheats on `retro_reset` and when a state is loaded, so this has to run
+/// again after those.
+pub fn apply(cheats: &[Cheat]) {
+    let Ok(api) = super::core::api() else {
+        return;
+    };
+
+    unsafe {
+        (api.retro_cheat_reset)();
+
+        for (index, cheat) in cheats.iter().enumerate() {
+            let Ok(code) = CString::new(cheat.code.as_str()) else {
+                continue;
+            };
+
+            (api.retro_cheat_set)(index as std::os::raw::c_uint, cheat.enabled, code.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_the_formats_apart() {
+        assert_eq!(
+            CheatKind::detect("82003BE4 0063+82003BE6 0000"),
+            CheatKind::CodeBreaker
+        );
+        assert_eq!(
+            CheatKind::detect("1A2B3C4D 5E6F7081\n1A2B3C4D:5E6F7081"),
+            CheatKind::GameShark
+        );
+        assert_eq!(
+            CheatKind::detect("82003BE4 0063+1A2B3C4D 5E6F7081"),
+            CheatKind::Unknown
+        );
+        assert_eq!(CheatKind::detect(""), CheatKind::Unknown);
+        assert_eq!(CheatKind::detect("not a code"), CheatKind::Unknown);
+    }
+
+    #[test]
+    fn cht_round_trips() {
+        let cheats = vec![
+            Cheat::new("Infinite health", "82003BE4 0063\n82003BE6 0000\n", true),
+            Cheat::new("Say \"hi\"", "1A2B3C4D 5E6F7081", false),
+            Cheat::new("", "a \"quoted\" code", true),
+        ];
+
+        let parsed = parse_cht(&to_cht(&cheats));
+        assert_eq!(parsed.len(), cheats.len());
+
+        for (parsed, cheat) in parsed.iter().zip(&cheats) {
+            assert_eq!(parsed.code, cheat.code);
+            assert_eq!(parsed.enabled, cheat.enabled);
+        }
+        assert_eq!(parsed[0].code, "82003BE4 0063+82003BE6 0000");
+        assert_eq!(parsed[1].description, "Say \"hi\"");
+        assert_eq!(parsed[2].code, "a \"quoted\" code");
+    }
+
+    #[test]
+    fn skips_what_it_cannot_read() {
+        let text = "cheats = 4\n\
+                    garbage without an equal sign\n\
+                    cheat0_desc = \"No code\"\n\
+                    cheat1_code = \"82003BE4 0063\"\n\
+                    cheat1_enable = yes\n\
+                    cheat3_code = \"1A2B3C4D 5E6F7081\"\n\
+                    cheat3_enable = true\n";
+
+        let cheats = parse_cht(text);
+        assert_eq!(cheats.len(), 2);
+        assert_eq!(cheats[0].description, "");
+        assert!(!cheats[0].enabled);
+        assert!(cheats[1].enabled);
+
+        assert!(parse_cht("cheats = lots\ncheat0_code = \"0\"").is_empty());
+        assert!(parse_cht("").is_empty());
+
+        // A huge count only goes through the codes that are there.
+        let cheats = parse_cht("cheats = 4000000000\ncheat7_code = \"0\"\ncheat12_code = \"1\"");
+        let codes: Vec<&str> = cheats.iter().map(|cheat| cheat.code.as_str()).collect();
+        assert_eq!(codes, ["0", "1"]);
+    }
+}
@@ -0,0 +1,168 @@
+/// Color correction profiles, mimicking how the screens of the handhelds showed colors.
+///
+/// The matrices and gammas follow Pokefan531's color shaders. Every profile is precomputed into
+/// a table with the output of each of the 65536 RGB565 colors, so correcting a frame costs no
+/// more than converting it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorProfile {
+    /// Colors as the core outputs them.
+    Off,
+    /// The original GBA's unlit reflective LCD.
+    Gba,
+    /// The frontlit screen of the GBA SP, AGS-001.
+    GbaSp,
+    /// The Game Boy Color's screen.
+    Gbc,
+    /// A Nintendo DS running GBA games.
+    Nds,
+}
+
+/// How a screen mixes colors.
+///
+/// The rows are scaled to keep white as it is: a profile changes how the colors bleed into each
+/// other, the brightness is left to the display the game is shown on.
+struct Screen {
+    /// Contribution of the red, green and blue of the game to each channel of the screen.
+    matrix: [[f32; 3]; 3],
+    /// Gamma of the screen, to bring the game's colors to linear light.
+    gamma: f32,
+}
+
+pub const PROFILES: [ColorProfile; 5] = [
+    ColorProfile::Off,
+    ColorProfile::Gba,
+    ColorProfile::GbaSp,
+    ColorProfile::Gbc,
+    ColorProfile::Nds,
+];
+
+impl ColorProfile {
+    pub fn parse(text: &str) -> Option<ColorProfile> {
+        PROFILES.into_iter().find(|profile| profile.name() == text)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorProfile::Off => "off",
+            ColorProfile::Gba => "gba",
+            ColorProfile::GbaSp => "gba-sp",
+            ColorProfile::Gbc => "gbc",
+            ColorProfile::Nds => "nds",
+        }
+    }
+
+    /// The profile after this one, going back to `Off` after the last.
+    pub fn next(&self) -> ColorProfile {
+        let index = PROFILES
+            .iter()
+            .position(|profile| profile == self)
+            .unwrap_or(0);
+
+        PROFILES[(index + 1) % PROFILES.len()]
+    }
+
+    fn screen(&self) -> Option<Screen> {
+        let (matrix, gamma) = match self {
+            ColorProfile::Off => return None,
+            ColorProfile::Gba => (
+                [
+                    [0.82, 0.24, -0.06],
+                    [0.125, 0.665, 0.21],
+                    [0.195, 0.075, 0.73],
+                ],
+                3.2,
+            ),
+            ColorProfile::GbaSp => (
+                [
+                    [0.86, 0.19, -0.05],
+                    [0.11, 0.66, 0.23],
+                    [0.1325, 0.0575, 0.81],
+                ],
+                2.2,
+            ),
+            ColorProfile::Gbc => (
+                [
+                    [0.78824, 0.12157, 0.0],
+                    [0.025, 0.72941, 0.275],
+                    [0.12039, 0.12157, 0.82],
+                ],
+                2.2,
+            ),
+            ColorProfile::Nds => (
+                [
+                    [0.83, 0.14, -0.03],
+                    [0.115, 0.65, 0.22],
+                    [0.105, 0.025, 0.82],
+                ],
+                2.2,
+            ),
+        };
+
+        Some(Screen { matrix, gamma })
+    }
+
+    /// The red, green and blue output of every RGB565 color, or `None` when colors are left
+    /// untouched.
+    ///
+    /// `gamma` is the gamma of the display the game is shown on, 2.2 for most of them.
+    pub fn table(&self, gamma: f32) -> Option<Box<[[u8; 3]]>> {
+        let screen = self.screen()?;
+        let mut table = vec![[0u8; 3]; 1 << 16].into_boxed_slice();
+        let matrix = screen.matrix.map(|row| {
+            let white: f32 = row.iter().sum();
+            row.map(|weight| weight / white)
+        });
+
+        for (color, out) in table.iter_mut().enumerate() {
+            let input = [
+                ((color >> 11) & 0x1F) as f32 / 31.0,
+                ((color >> 5) & 0x3F) as f32 / 63.0,
+                (color & 0x1F) as f32 / 31.0,
+            ]
+            .map(|channel| channel.powf(screen.gamma));
+
+            *out = matrix.map(|row| {
+                let linear = row[0] * input[0] + row[1] * input[1] + row[2] * input[2];
+
+                (linear.clamp(0.0, 1.0).powf(1.0 / gamma) * 255.0).round() as u8
+            });
+        }
+
+        Some(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn off_leaves_colors_alone() {
+        assert!(ColorProfile::Off.table(2.2).is_none());
+    }
+
+    #[test]
+    fn keeps_white_and_black() {
+        for profile in PROFILES.into_iter().skip(1) {
+            let table = profile.table(2.2).unwrap();
+
+            assert_eq!(table[0xFFFF], [255, 255, 255], "{}", profile.name());
+            assert_eq!(table[0], [0, 0, 0], "{}", profile.name());
+        }
+    }
+
+    #[test]
+    fn names_parse_back() {
+        for profile in PROFILES {
+            assert_eq!(ColorProfile::parse(profile.name()), Some(profile));
+        }
+        assert_eq!(ColorProfile::parse("sepia"), None);
+
+        // `next` goes through every profile once before coming back.
+        let mut profile = ColorProfile::Off;
+        for expected in PROFILES.into_iter().skip(1).chain([ColorProfile::Off]) {
+            profile = profile.next();
+            assert_eq!(profile, expected);
+        }
+    }
+}
@@ -0,0 +1,452 @@
+/// Layouts the converted frames can have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Red, green, blue and alpha bytes in that order in memory, what pixels expects.
+    Rgba8,
+    /// libretro's XRGB8888, `0xFFRRGGBB` as a native `u32`, for captures.
+    Xrgb8888,
+}
+
+impl PixelFormat {
+    pub fn pack(&self, [r, g, b]: [u8; 3]) -> u32 {
+        match self {
+            PixelFormat::Rgba8 => u32::from_ne_bytes([r, g, b, 0xFF]),
+            PixelFormat::Xrgb8888 => {
+                (0xFF << 24) | ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+            }
+        }
+    }
+
+    pub fn rgb(&self, pixel: u32) -> [u8; 3] {
+        match self {
+            PixelFormat::Rgba8 => {
+                let [r, g, b, _] = pixel.to_ne_bytes();
+                [r, g, b]
+            }
+            PixelFormat::Xrgb8888 => [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8],
+        }
+    }
+}
+
+/// Conversion of the core's RGB565 frames to 32-bit pixels.
+///
+/// Frames are converted into a buffer that is reused from one frame to the next. Every RGB565
+/// color is looked up in a 65536 entry table, which is where color profiles plug in. Without a
+/// profile, the conversion is plain arithmetic and runs on SSE2, AVX2 or NEON when the CPU has
+/// them.
+pub struct Converter {
+    format: PixelFormat,
+    table: Box<[u32]>,
+    /// Whether `table` holds the plain conversion, which the vector paths compute instead.
+    identity: bool,
+}
+
+impl Converter {
+    /// A converter to `format`, using `colors` as the output of every RGB565 color, or the plain
+    /// conversion without them.
+    ///
+    /// Panics when `colors` doesn't hold exactly one color per RGB565 value, the vector paths
+    /// look any of them up unchecked.
+    pub fn new(format: PixelFormat, colors: Option<&[[u8; 3]]>) -> Converter {
+        if let Some(colors) = colors {
+            assert_eq!(colors.len(), 1 << 16, "a color table has 65536 entries");
+        }
+
+        let table = match colors {
+            Some(colors) => colors.iter().map(|color| format.pack(*color)).collect(),
+            None => (0..=u16::MAX)
+                .map(|pixel| format.pack(convert_pixel_from_rgb565(pixel)))
+                .collect(),
+        };
+
+        Converter {
+            format,
+            table,
+            identity: colors.is_none(),
+        }
+    }
+
+    /// Convert a frame of `width` by `height` pixels, with `pitch` bytes between rows, into
+    /// `out`, which only grows when the frame does.
+    pub fn convert(
+        &self,
+        frame: &[u8],
+        width: usize,
+        height: usize,
+        pitch: usize,
+        out: &mut Vec<u32>,
+    ) {
+        out.resize(width * height, 0);
+
+        for (y, row) in out.chunks_exact_mut(width).enumerate() {
+            let row_offset = y * pitch;
+            let row_bytes = &frame[row_offset..row_offset + (width * 2)];
+
+            self.convert_row(row_bytes, row);
+        }
+    }
+
+    fn convert_row(&self, row_bytes: &[u8], out: &mut [u32]) {
+        // The vector paths build the pixels as two 16-bit halves, which only gives the right
+        // bytes on little endian CPUs. `swap` puts blue in the low half, for XRGB8888.
+        let swap = self.format == PixelFormat::Xrgb8888;
+
+        #[cfg(all(target_arch = "x86_64", target_endian = "little"))]
+        {
+            if std::is_x86_feature_detected!("avx2") {
+                let done = unsafe {
+                    if self.identity {
+                        x86::convert_avx2(row_bytes, out, swap)
+                    } else {
+                        x86::lookup_avx2(&self.table, row_bytes, out)
+                    }
+                };
+
+                return self.convert_scalar(&row_bytes[done * 2..], &mut out[done..]);
+            }
+
+            if self.identity && std::is_x86_feature_detected!("sse2") {
+                let done = unsafe { x86::convert_sse2(row_bytes, out, swap) };
+
+                return self.convert_scalar(&row_bytes[done * 2..], &mut out[done..]);
+            }
+        }
+
+        #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+        {
+            if self.identity && std::arch::is_aarch64_feature_detected!("neon") {
+                let done = unsafe { aarch64::convert_neon(row_bytes, out, swap) };
+
+                return self.convert_scalar(&row_bytes[done * 2..], &mut out[done..]);
+            }
+        }
+
+        let _ = swap;
+        self.convert_scalar(row_bytes, out)
+    }
+
+    fn convert_scalar(&self, row_bytes: &[u8], out: &mut [u32]) {
+        for (bytes, pixel) in row_bytes.chunks_exact(2).zip(out.iter_mut()) {
+            // One pixel is two bytes or 16 bits, little endian.
+            *pixel = self.table[u16::from_le_bytes([bytes[0], bytes[1]]) as usize];
+        }
+    }
+}
+
+/// Rewrite a frame of libretro's 0RGB1555, the format cores start in, as packed RGB565 rows for
+/// the converter. Green takes its high bit as its sixth, so a full green stays full.
+pub fn rgb565_from_0rgb1555(
+    frame: &[u8],
+    width: usize,
+    height: usize,
+    pitch: usize,
+    out: &mut Vec<u8>,
+) {
+    out.clear();
+    out.reserve(width * height * 2);
+
+    for y in 0..height {
+        let row = &frame[y * pitch..y * pitch + width * 2];
+
+        for bytes in row.chunks_exact(2) {
+            let pixel = u16::from_le_bytes([bytes[0], bytes[1]]);
+            let pixel = ((pixel & 0x7FE0) << 1) | ((pixel >> 4) & 0x20) | (pixel & 0x1F);
+            out.extend_from_slice(&pixel.to_le_bytes());
+        }
+    }
+}
+
+/// Convert an RGB565 pixel to 8-bit red, green and blue.
+///
+/// The mGBA core outputs pixels in RGB565 format, but pixels requires 8 bits per channel.
+///
+/// ### RETRO_PIXEL_FORMAT_RGB565
+/// - 5 bits for red   (RRRRR)
+/// - 6 bits for green (GGGGGG) -> Humans perceive more shades of green.
+/// - 5 bits for blue  (BBBBB)
+/// - 16 bits total (2 bytes per pixel).
+///
+/// Channels are widened by replicating their high bits into the low ones, so 0 stays 0 and the
+/// maximum of 5 or 6 bits becomes 0xFF, with even steps in between.
+pub fn convert_pixel_from_rgb565(pixel: u16) -> [u8; 3] {
+    // Extract the color components from the 16-bit RGB565 format:
+    //
+    // RGB565 bit layout:
+    //  RRRRR GGGGGG BBBBB
+    //
+    // Extract red (5 bits) -> shift right 11 places.
+    //
+    //  RRRRR GGGGGG BBBBB   (Original)
+    //  00000 000000 RRRRR   (After `>> 11`)
+    //
+    let r = (pixel >> 11) & 0x1F;
+
+    // Extract green (6 bits) -> shift right 5 places, mask to keep 6 bits.
+    //
+    // RRRRR GGGGGG BBBBB    (Original)
+    // 00000 000000 GGGGGG   (After `>> 5`)
+    //
+    let g = (pixel >> 5) & 0x3F;
+    // Extract blue (5 bits) -> mask to keep 5 bits.
+    let b = pixel & 0x1F;
+
+    // Scale to 8 bits:
+    //
+    //  RRRRR      (5 bits)
+    //  RRRRRRRR   (After `<< 3 | >> 2`, the top 3 bits repeated at the bottom)
+    //
+    [
+        ((r << 3) | (r >> 2)) as u8,
+        ((g << 2) | (g >> 4)) as u8,
+        ((b << 3) | (b >> 2)) as u8,
+    ]
+}
+
+/// The vector paths convert as many whole blocks of pixels as the row has, and return how many
+/// pixels they did; the scalar path does the rest.
+#[cfg(all(target_arch = "x86_64", target_endian = "little"))]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn convert_sse2(row_bytes: &[u8], out: &mut [u32], swap: bool) -> usize {
+        let blocks = out.len().min(row_bytes.len() / 2) / 8;
+
+        for block in 0..blocks {
+            let v = _mm_loadu_si128(row_bytes.as_ptr().add(block * 16) as *const __m128i);
+
+            let r = _mm_srli_epi16(v, 11);
+            let g = _mm_and_si128(_mm_srli_epi16(v, 5), _mm_set1_epi16(0x3F));
+            let b = _mm_and_si128(v, _mm_set1_epi16(0x1F));
+
+            let r = _mm_or_si128(_mm_slli_epi16(r, 3), _mm_srli_epi16(r, 2));
+            let g = _mm_or_si128(_mm_slli_epi16(g, 2), _mm_srli_epi16(g, 4));
+            let b = _mm_or_si128(_mm_slli_epi16(b, 3), _mm_srli_epi16(b, 2));
+
+            let (low, high) = if swap { (b, r) } else { (r, b) };
+            let low = _mm_or_si128(low, _mm_slli_epi16(g, 8));
+            let high = _mm_or_si128(high, _mm_set1_epi16(0xFF00u16 as i16));
+
+            let dst = out.as_mut_ptr().add(block * 8) as *mut __m128i;
+            _mm_storeu_si128(dst, _mm_unpacklo_epi16(low, high));
+            _mm_storeu_si128(dst.add(1), _mm_unpackhi_epi16(low, high));
+        }
+
+        blocks * 8
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn convert_avx2(row_bytes: &[u8], out: &mut [u32], swap: bool) -> usize {
+        let blocks = out.len().min(row_bytes.len() / 2) / 16;
+
+        for block in 0..blocks {
+            let v = _mm256_loadu_si256(row_bytes.as_ptr().add(block * 32) as *const __m256i);
+
+            let r = _mm256_srli_epi16(v, 11);
+            let g = _mm256_and_si256(_mm256_srli_epi16(v, 5), _mm256_set1_epi16(0x3F));
+            let b = _mm256_and_si256(v, _mm256_set1_epi16(0x1F));
+
+            let r = _mm256_or_si256(_mm256_slli_epi16(r, 3), _mm256_srli_epi16(r, 2));
+            let g = _mm256_or_si256(_mm256_slli_epi16(g, 2), _mm256_srli_epi16(g, 4));
+            let b = _mm256_or_si256(_mm256_slli_epi16(b, 3), _mm256_srli_epi16(b, 2));
+
+            let (low, high) = if swap { (b, r) } else { (r, b) };
+            let low = _mm256_or_si256(low, _mm256_slli_epi16(g, 8));
+            let high = _mm256_or_si256(high, _mm256_set1_epi16(0xFF00u16 as i16));
+
+            // Unpacking works within each 128-bit lane, giving pixels 0-3 and 8-11 in `first`
+            // and 4-7 and 12-15 in `second`.
+            let first = _mm256_unpacklo_epi16(low, high);
+            let second = _mm256_unpackhi_epi16(low, high);
+
+            let dst = out.as_mut_ptr().add(block * 16) as *mut __m256i;
+            _mm256_storeu_si256(dst, _mm256_permute2x128_si256(first, second, 0x20));
+            _mm256_storeu_si256(dst.add(1), _mm256_permute2x128_si256(first, second, 0x31));
+        }
+
+        blocks * 16
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn lookup_avx2(table: &[u32], row_bytes: &[u8], out: &mut [u32]) -> usize {
+        // The gather reads any 16-bit index unchecked.
+        debug_assert!(table.len() == 1 << 16);
+
+        let blocks = out.len().min(row_bytes.len() / 2) / 8;
+
+        for block in 0..blocks {
+            let v = _mm_loadu_si128(row_bytes.as_ptr().add(block * 16) as *const __m128i);
+            let indices = _mm256_cvtepu16_epi32(v);
+            let pixels = _mm256_i32gather_epi32::<4>(table.as_ptr() as *const i32, indices);
+
+            _mm256_storeu_si256(out.as_mut_ptr().add(block * 8) as *mut __m256i, pixels);
+        }
+
+        blocks * 8
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+mod aarch64 {
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn convert_neon(row_bytes: &[u8], out: &mut [u32], swap: bool) -> usize {
+        let blocks = out.len().min(row_bytes.len() / 2) / 8;
+
+        for block in 0..blocks {
+            let v = vreinterpretq_u16_u8(vld1q_u8(row_bytes.as_ptr().add(block * 16)));
+
+            let r = vshrq_n_u16::<11>(v);
+            let g = vandq_u16(vshrq_n_u16::<5>(v), vdupq_n_u16(0x3F));
+            let b = vandq_u16(v, vdupq_n_u16(0x1F));
+
+            let r = vorrq_u16(vshlq_n_u16::<3>(r), vshrq_n_u16::<2>(r));
+            let g = vorrq_u16(vshlq_n_u16::<2>(g), vshrq_n_u16::<4>(g));
+            let b = vorrq_u16(vshlq_n_u16::<3>(b), vshrq_n_u16::<2>(b));
+
+            let (low, high) = if swap { (b, r) } else { (r, b) };
+            let low = vorrq_u16(low, vshlq_n_u16::<8>(g));
+            let high = vorrq_u16(high, vdupq_n_u16(0xFF00));
+
+            let dst = out.as_mut_ptr().add(block * 8) as *mut u8;
+            vst1q_u8(dst, vreinterpretq_u8_u16(vzip1q_u16(low, high)));
+            vst1q_u8(dst.add(16), vreinterpretq_u8_u16(vzip2q_u16(low, high)));
+        }
+
+        blocks * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RGB565 colors and the 8-bit channels they should come out as.
+    const KNOWN: [(u16, [u8; 3]); 8] = [
+        (0x0000, [0x00, 0x00, 0x00]),
+        (0xFFFF, [0xFF, 0xFF, 0xFF]),
+        (0xF800, [0xFF, 0x00, 0x00]),
+        (0x07E0, [0x00, 0xFF, 0x00]),
+        (0x001F, [0x00, 0x00, 0xFF]),
+        (0x8410, [0x84, 0x82, 0x84]),
+        (0x0821, [0x08, 0x04, 0x08]),
+        (0x7BEF, [0x7B, 0x7D, 0x7B]),
+    ];
+
+    /// A frame of `width` pixels per row, with a few bytes of padding after each row.
+    fn frame(pixels: &[u16], width: usize) -> (Vec<u8>, usize) {
+        let pitch = width * 2 + 6;
+        let mut bytes = Vec::new();
+
+        for row in pixels.chunks(width) {
+            for pixel in row {
+                bytes.extend_from_slice(&pixel.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0xAB; 6]);
+        }
+
+        (bytes, pitch)
+    }
+
+    #[test]
+    fn replicates_the_high_bits() {
+        for (pixel, rgb) in KNOWN {
+            assert_eq!(convert_pixel_from_rgb565(pixel), rgb, "{:#06x}", pixel);
+        }
+    }
+
+    #[test]
+    fn widens_0rgb1555_to_rgb565() {
+        let pixels = [
+            0x0000, 0x7FFF, 0x7C00, 0x03E0, 0x001F, 0x0200, 0x8000, 0x4210,
+        ];
+        let (bytes, pitch) = frame(&pixels, 4);
+
+        let mut out = Vec::new();
+        rgb565_from_0rgb1555(&bytes, 4, 2, pitch, &mut out);
+
+        let out: Vec<u16> = out
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        assert_eq!(
+            out,
+            [0x0000, 0xFFFF, 0xF800, 0x07E0, 0x001F, 0x0420, 0x0000, 0x8430]
+        );
+    }
+
+    #[test]
+    fn packs_rgba8_as_bytes() {
+        let converter = Converter::new(PixelFormat::Rgba8, None);
+        let pixels: Vec<u16> = KNOWN.iter().map(|(pixel, _)| *pixel).collect();
+        let (bytes, pitch) = frame(&pixels, pixels.len());
+
+        let mut out = Vec::new();
+        converter.convert(&bytes, pixels.len(), 1, pitch, &mut out);
+
+        for (pixel, (_, [r, g, b])) in out.iter().zip(KNOWN) {
+            assert_eq!(pixel.to_ne_bytes(), [r, g, b, 0xFF]);
+        }
+    }
+
+    #[test]
+    fn packs_xrgb8888_as_a_native_u32() {
+        assert_eq!(PixelFormat::Xrgb8888.pack([0xFF, 0xFF, 0xFF]), 0xFFFFFFFF);
+        assert_eq!(PixelFormat::Xrgb8888.pack([0xFF, 0x00, 0x00]), 0xFFFF0000);
+        assert_eq!(PixelFormat::Xrgb8888.pack([0x00, 0xFF, 0x00]), 0xFF00FF00);
+        assert_eq!(PixelFormat::Xrgb8888.pack([0x00, 0x00, 0xFF]), 0xFF0000FF);
+        assert_eq!(PixelFormat::Xrgb8888.rgb(0xFF123456), [0x12, 0x34, 0x56]);
+    }
+
+    #[test]
+    fn every_path_matches_the_table() {
+        // Every RGB565 color, in rows long enough for the vector paths and a few leftovers.
+        let pixels: Vec<u16> = (0..=u16::MAX).collect();
+        let width = 256 + 13;
+        let height = pixels.len() / width;
+        let (bytes, pitch) = frame(&pixels[..width * height], width);
+
+        for format in [PixelFormat::Rgba8, PixelFormat::Xrgb8888] {
+            let converter = Converter::new(format, None);
+
+            let mut out = Vec::new();
+            converter.convert(&bytes, width, height, pitch, &mut out);
+
+            for (pixel, color) in out.iter().zip(&pixels) {
+                assert_eq!(
+                    format.rgb(*pixel),
+                    convert_pixel_from_rgb565(*color),
+                    "{:?} {:#06x}",
+                    format,
+                    color
+                );
+                assert_eq!(pixel >> 24, 0xFF);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn refuses_short_color_tables() {
+        Converter::new(PixelFormat::Rgba8, Some(&[[0, 0, 0]; 256]));
+    }
+
+    #[test]
+    fn uses_the_colors_of_a_profile() {
+        let colors: Vec<[u8; 3]> = (0..=u16::MAX)
+            .map(|pixel| [(pixel >> 8) as u8, pixel as u8, 0x42])
+            .collect();
+        let converter = Converter::new(PixelFormat::Rgba8, Some(&colors));
+
+        let pixels: Vec<u16> = (0..64).map(|index| index * 1021).collect();
+        let (bytes, pitch) = frame(&pixels, 32);
+
+        let mut out = Vec::new();
+        converter.convert(&bytes, 32, 2, pitch, &mut out);
+
+        for (pixel, color) in out.iter().zip(&pixels) {
+            assert_eq!(PixelFormat::Rgba8.rgb(*pixel), colors[*color as usize]);
+        }
+    }
+}
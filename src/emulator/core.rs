@@ -0,0 +1,242 @@
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+
+use super::libretro;
+
+/// The core running, `None` before the first one is loaded and after it is unloaded.
+static mut CORE: Cell<Option<Core>> = Cell::new(None);
+/// A second instance of the core, which run-ahead runs the frames ahead on.
+static mut SECONDARY: Cell<Option<Core>> = Cell::new(None);
+
+struct Core {
+    api: Api,
+    /// Handle of the shared library, `None` for the core linked into the binary.
+    library: Option<*mut libc::c_void>,
+    path: Option<PathBuf>,
+    /// Copy of the library opened for a second instance, removed with it.
+    copy: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum CoreError {
+    /// No core is loaded, or it was unloaded.
+    NotLoaded,
+    /// The shared library could not be opened.
+    Open(String),
+    /// The library lacks a function every core has.
+    MissingSymbol(&'static str),
+    /// The core linked into the binary can't be loaded a second time.
+    Builtin,
+}
+
+impl std::fmt::Display for CoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoreError::NotLoaded => write!(f, "no core is loaded"),
+            CoreError::Open(message) => write!(f, "could not open the core: {}", message),
+            CoreError::MissingSymbol(name) => write!(f, "the core has no {}", name),
+            CoreError::Builtin => write!(f, "the built-in core only runs once"),
+        }
+    }
+}
+
+/// Declare the functions of a core, which come either from the linked core or from `dlsym`.
+macro_rules! api {
+    ($($name:ident: fn($($arg:ty),*) $(-> $ret:ty)?;)*) => {
+        /// The functions of a core, copied out for every call so that none outlives the core.
+        #[derive(Clone, Copy)]
+        pub struct Api {
+            $(pub $name: unsafe extern "C" fn($($arg),*) $(-> $ret)?,)*
+        }
+
+        impl Api {
+            fn builtin() -> Api {
+                Api {
+                    $($name: libretro::$name,)*
+                }
+            }
+
+            #[cfg(unix)]
+            unsafe fn open(library: *mut libc::c_void) -> Result<Api, CoreError> {
+                Ok(Api {
+                    $($name: {
+                        let name = concat!(stringify!($name), "\0");
+                        let symbol = libc::dlsym(library, name.as_ptr() as *const libc::c_char);
+                        if symbol.is_null() {
+                            return Err(CoreError::MissingSymbol(stringify!($name)));
+                        }
+
+                        std::mem::transmute(symbol)
+                    },)*
+                })
+            }
+        }
+    };
+}
+
+api! {
+    retro_set_environment: fn(libretro::retro_environment_t);
+    retro_set_video_refresh: fn(libretro::retro_video_refresh_t);
+    retro_set_audio_sample: fn(libretro::retro_audio_sample_t);
+    retro_set_audio_sample_batch: fn(libretro::retro_audio_sample_batch_t);
+    retro_set_input_poll: fn(libretro::retro_input_poll_t);
+    retro_set_input_state: fn(libretro::retro_input_state_t);
+    retro_init: fn();
+    retro_deinit: fn();
+    retro_get_system_info: fn(*mut libretro::retro_system_info);
+    retro_get_system_av_info: fn(*mut libretro::retro_system_av_info);
+    retro_reset: fn();
+    retro_run: fn();
+    retro_serialize_size: fn() -> usize;
+    retro_serialize: fn(*mut libc::c_void, usize) -> bool;
+    retro_unserialize: fn(*const libc::c_void, usize) -> bool;
+    retro_cheat_reset: fn();
+    retro_cheat_set: fn(std::os::raw::c_uint, bool, *const libc::c_char);
+    retro_load_game: fn(*const libretro::retro_game_info) -> bool;
+    retro_unload_game: fn();
+    retro_get_memory_data: fn(std::os::raw::c_uint) -> *mut libc::c_void;
+    retro_get_memory_size: fn(std::os::raw::c_uint) -> usize;
+}
+
+/// The functions of the loaded core.
+pub fn api() -> Result<Api, CoreError> {
+    unsafe {
+        CORE.get_mut()
+            .as_ref()
+            .map(|core| core.api)
+            .ok_or(CoreError::NotLoaded)
+    }
+}
+
+/// Shared library of the loaded core, `None` for the linked one or when none is loaded.
+pub fn path() -> Option<PathBuf> {
+    unsafe { CORE.get_mut().as_ref().and_then(|core| core.path.clone()) }
+}
+
+/// Make the core at `path`, or the linked one with `None`, the loaded core.
+///
+/// Only its functions are looked up, setting its callbacks and `retro_init` are up to the caller.
+pub fn load(path: Option<&Path>) -> Result<(), CoreError> {
+    let core = match path {
+        None => Core {
+            api: Api::builtin(),
+            library: None,
+            path: None,
+            copy: None,
+        },
+        Some(path) => open(path)?,
+    };
+
+    unsafe { CORE.set(Some(core)) };
+    Ok(())
+}
+
+#[cfg(unix)]
+fn open(path: &Path) -> Result<Core, CoreError> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| CoreError::Open(String::from("the path contains a nul byte")))?;
+
+    unsafe {
+        let library = libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if library.is_null() {
+            let error = libc::dlerror();
+            return Err(CoreError::Open(if error.is_null() {
+                String::from("unknown error")
+            } else {
+                std::ffi::CStr::from_ptr(error)
+                    .to_string_lossy()
+                    .into_owned()
+            }));
+        }
+
+        match Api::open(library) {
+            Ok(api) => Ok(Core {
+                api,
+                library: Some(library),
+                path: Some(path.to_path_buf()),
+                copy: None,
+            }),
+            Err(err) => {
+                libc::dlclose(library);
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn open(_path: &Path) -> Result<Core, CoreError> {
+    Err(CoreError::Open(String::from(
+        "loading cores is only supported on unix",
+    )))
+}
+
+/// Forget the loaded core and close its shared library. `retro_deinit` must have run already.
+pub fn unload() {
+    unload_secondary();
+
+    if let Some(core) = unsafe { CORE.take() } {
+        close(core);
+    }
+}
+
+/// The functions of the second instance of the core.
+pub fn secondary_api() -> Result<Api, CoreError> {
+    unsafe {
+        SECONDARY
+            .get_mut()
+            .as_ref()
+            .map(|core| core.api)
+            .ok_or(CoreError::NotLoaded)
+    }
+}
+
+/// Open a second instance of the loaded core, which shares nothing with the first one.
+///
+/// Opening the same library twice hands back the same instance, so a copy of it is opened
+/// instead. Setting its callbacks and `retro_init` are up to the caller.
+pub fn load_secondary() -> Result<(), CoreError> {
+    unload_secondary();
+
+    let path = path().ok_or(if api().is_ok() {
+        CoreError::Builtin
+    } else {
+        CoreError::NotLoaded
+    })?;
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let copy = std::env::temp_dir().join(format!("advanced-{}-{}", std::process::id(), name));
+    std::fs::copy(&path, &copy).map_err(|err| CoreError::Open(err.to_string()))?;
+
+    match open(&copy) {
+        Ok(mut core) => {
+            core.copy = Some(copy);
+            unsafe { SECONDARY.set(Some(core)) };
+            Ok(())
+        }
+        Err(err) => {
+            let _ = std::fs::remove_file(&copy);
+            Err(err)
+        }
+    }
+}
+
+/// Close the second instance of the core. `retro_deinit` must have run on it already.
+pub fn unload_secondary() {
+    if let Some(core) = unsafe { SECONDARY.take() } {
+        close(core);
+    }
+}
+
+fn close(core: Core) {
+    #[cfg(unix)]
+    if let Some(library) = core.library {
+        unsafe { libc::dlclose(library) };
+    }
+
+    if let Some(copy) = core.copy {
+        let _ = std::fs::remove_file(copy);
+    }
+}
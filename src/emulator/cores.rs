@@ -0,0 +1,134 @@
+use std::ffi::CStr;
+use std::path::{Path, PathBuf};
+
+use super::libretro;
+
+/// What a core tells about itself with `retro_get_system_info`.
+#[derive(Debug, Clone)]
+pub struct CoreInfo {
+    pub name: String,
+    pub version: String,
+    /// Extensions of the content it loads, lowercase and without the dot.
+    pub extensions: Vec<String>,
+    /// Shared library of the core, `None` for the core linked into the binary.
+    pub path: Option<PathBuf>,
+}
+
+impl CoreInfo {
+    unsafe fn read(info: &libretro::retro_system_info, path: Option<PathBuf>) -> CoreInfo {
+        let text = |ptr: *const libc::c_char| {
+            if ptr.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(ptr).to_string_lossy().into_owned()
+            }
+        };
+
+        CoreInfo {
+            name: text(info.library_name),
+            version: text(info.library_version),
+            extensions: text(info.valid_extensions)
+                .split('|')
+                .filter(|extension| !extension.is_empty())
+                .map(|extension| extension.to_ascii_lowercase())
+                .collect(),
+            path,
+        }
+    }
+
+    /// Whether the core lists the extension of `path` as one it loads.
+    pub fn supports(&self, path: &Path) -> bool {
+        path.extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .is_some_and(|extension| self.extensions.contains(&extension))
+    }
+}
+
+/// The core linked into the binary.
+pub fn builtin() -> CoreInfo {
+    unsafe {
+        let mut info: libretro::retro_system_info = std::mem::zeroed();
+        libretro::retro_get_system_info(&mut info);
+
+        CoreInfo::read(&info, None)
+    }
+}
+
+/// Ask the shared library at `path` for its info, without initializing it.
+#[cfg(unix)]
+pub fn probe(path: &Path) -> Result<CoreInfo, String> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| String::from("the path contains a nul byte"))?;
+
+    unsafe {
+        let library = libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if library.is_null() {
+            return Err(dl_error());
+        }
+
+        let symbol = libc::dlsym(library, b"retro_get_system_info\0".as_ptr() as *const _);
+        if symbol.is_null() {
+            libc::dlclose(library);
+            return Err(String::from("not a libretro core"));
+        }
+
+        let get_system_info: unsafe extern "C" fn(*mut libretro::retro_system_info) =
+            std::mem::transmute(symbol);
+        let mut info: libretro::retro_system_info = std::mem::zeroed();
+        get_system_info(&mut info);
+
+        // The strings belong to the library, they're copied before closing it.
+        let core = CoreInfo::read(&info, Some(path.to_path_buf()));
+        libc::dlclose(library);
+
+        Ok(core)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn probe(_path: &Path) -> Result<CoreInfo, String> {
+    Err(String::from("loading cores is only supported on unix"))
+}
+
+#[cfg(unix)]
+unsafe fn dl_error() -> String {
+    let error = libc::dlerror();
+
+    if error.is_null() {
+        String::from("unknown error")
+    } else {
+        CStr::from_ptr(error).to_string_lossy().into_owned()
+    }
+}
+
+/// The linked core, followed by the cores installed in `dir`.
+///
+/// Files that fail to load are skipped with a message, they're often other libraries.
+pub fn installed(dir: &Path) -> Vec<CoreInfo> {
+    let mut cores = vec![builtin()];
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return cores;
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|extension| {
+                ["so", "dylib", "dll"].contains(&&*extension.to_string_lossy())
+            })
+        })
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        match probe(&path) {
+            Ok(core) => cores.push(core),
+            Err(message) => println!("skipping the core {}: {}", path.display(), message),
+        }
+    }
+
+    cores
+}
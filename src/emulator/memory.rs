@@ -0,0 +1,651 @@
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::fmt;
+use std::rc::Rc;
+
+use super::libretro;
+
+/// A memory descriptor from `RETRO_ENVIRONMENT_SET_MEMORY_MAPS`, with `select`, `len` and
+/// `disconnect` filled in so that every guest address can be translated the same way.
+#[derive(Debug, Clone)]
+struct Descriptor {
+    flags: u64,
+    ptr: *mut u8,
+    offset: usize,
+    start: usize,
+    select: usize,
+    disconnect: usize,
+    len: usize,
+    addrspace: String,
+}
+
+impl Descriptor {
+    fn claims(&self, address: usize) -> bool {
+        (address ^ self.start) & self.select == 0
+    }
+
+    /// Translate a guest address claimed by this descriptor into an offset from `ptr`.
+    ///
+    /// As libretro.h puts it: subtract `start`, pick off `disconnect`, apply `len`, add `offset`.
+    fn translate(&self, address: usize) -> usize {
+        let mut physical = reduce(address - self.start, self.disconnect);
+
+        // Past the end of the chip, clear the highest bits until the address fits: that's how
+        // mirrors of memory smaller than its window are wired.
+        while physical >= self.len {
+            physical &= !highest_bit(physical);
+        }
+
+        physical + self.offset
+    }
+
+    fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag as u64 != 0
+    }
+
+    /// Alignment of multi-byte accesses, from the `RETRO_MEMDESC_ALIGN_*` bits.
+    fn align(&self) -> usize {
+        1 << ((self.flags >> 16) & 3)
+    }
+
+    /// Narrowest access the memory supports, from the `RETRO_MEMDESC_MINSIZE_*` bits.
+    fn min_size(&self) -> usize {
+        1 << ((self.flags >> 24) & 3)
+    }
+}
+
+/// Set every bit below the highest set bit.
+fn add_bits_down(mut n: usize) -> usize {
+    let mut shift = 1;
+    while shift < usize::BITS {
+        n |= n >> shift;
+        shift <<= 1;
+    }
+
+    n
+}
+
+fn highest_bit(n: usize) -> usize {
+    let n = add_bits_down(n);
+    n ^ (n >> 1)
+}
+
+/// Insert a zero bit into `address` for every bit set in `mask`, the inverse of `reduce`.
+fn inflate(mut address: usize, mut mask: usize) -> usize {
+    while mask != 0 {
+        let below = (mask - 1) & !mask;
+        address = ((address & !below) << 1) | (address & below);
+        mask &= mask - 1;
+    }
+
+    address
+}
+
+/// Remove from `address` the bits set in `mask`, shifting the higher bits down.
+fn reduce(mut address: usize, mut mask: usize) -> usize {
+    while mask != 0 {
+        let below = (mask - 1) & !mask;
+        address = (address & below) | ((address >> 1) & !below);
+        mask = (mask & (mask - 1)) >> 1;
+    }
+
+    address
+}
+
+/// Fill in the optional fields of the core's descriptors, following the rules of libretro.h.
+///
+/// Descriptors that break those rules are dropped with a warning instead of being guessed at.
+fn preprocess(descriptors: &[libretro::retro_memory_descriptor]) -> Vec<Descriptor> {
+    let mut top_addr: usize = 1;
+    for descriptor in descriptors {
+        top_addr |= if descriptor.select != 0 {
+            descriptor.select
+        } else {
+            // Cores may place memory at the very top of the address space.
+            descriptor
+                .start
+                .saturating_add(descriptor.len)
+                .saturating_sub(1)
+        };
+    }
+    let top_addr = add_bits_down(top_addr);
+
+    let mut processed = Vec::new();
+
+    for descriptor in descriptors {
+        let mut select = descriptor.select;
+        let mut disconnect = descriptor.disconnect;
+        let mut len = descriptor.len;
+
+        if select == 0 {
+            // Each byte is mapped exactly once, which only works for power of two sizes.
+            if len == 0 || !len.is_power_of_two() {
+                println!(
+                    "ignoring memory descriptor at {:#x}: without select its len must be a power of two",
+                    descriptor.start
+                );
+                continue;
+            }
+
+            select = top_addr & !inflate(add_bits_down(len - 1), disconnect);
+        }
+
+        if len == 0 {
+            len = add_bits_down(reduce(top_addr & !select, disconnect)) + 1;
+        }
+
+        if descriptor.start & !select != 0 {
+            println!(
+                "ignoring memory descriptor at {:#x}: start has bits outside of select",
+                descriptor.start
+            );
+            continue;
+        }
+
+        // Address bits that could never index into the chip are not connected to it either.
+        let reachable = add_bits_down(inflate(len - 1, disconnect));
+        loop {
+            let unused = highest_bit(top_addr & !select & !disconnect);
+            if unused == 0 || unused <= reachable {
+                break;
+            }
+
+            disconnect |= unused;
+        }
+
+        let addrspace = if descriptor.addrspace.is_null() {
+            String::new()
+        } else {
+            unsafe { CStr::from_ptr(descriptor.addrspace) }
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        processed.push(Descriptor {
+            flags: descriptor.flags,
+            ptr: descriptor.ptr as *mut u8,
+            offset: descriptor.offset,
+            start: descriptor.start,
+            select,
+            disconnect,
+            len,
+            addrspace,
+        });
+    }
+
+    processed
+}
+
+/// Bytes read at once at most, a few screens of hex dump.
+pub const MAX_READ: usize = 0x10000;
+
+/// Descriptors of the loaded game, in the order the core gave them: the first one to claim an
+/// address is the one that applies.
+static mut DESCRIPTORS: Cell<Vec<Descriptor>> = Cell::new(Vec::new());
+/// The default address space built from `DESCRIPTORS`, freezes go through it on every frame.
+static mut ADDRESS_SPACE: Cell<Option<Rc<AddressSpace>>> = Cell::new(None);
+
+/// Keep a copy of the core's memory map, the array it points to is only valid during the call.
+pub unsafe fn set_memory_maps(map: *const libretro::retro_memory_map) {
+    let map = &*map;
+    let descriptors = std::slice::from_raw_parts(map.descriptors, map.num_descriptors as usize);
+
+    DESCRIPTORS.set(preprocess(descriptors));
+    ADDRESS_SPACE.set(None);
+}
+
+/// Forget the memory map, it points into the memory of a game that is gone.
+pub fn clear() {
+    unsafe {
+        DESCRIPTORS.set(Vec::new());
+        ADDRESS_SPACE.set(None);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryError {
+    /// No descriptor claims the address, or the one that does has no memory behind it (hardware
+    /// registers, open bus).
+    Unmapped(usize),
+    /// The memory is ROM (`RETRO_MEMDESC_CONST`).
+    ReadOnly(usize),
+    /// The access breaks the `RETRO_MEMDESC_ALIGN_*` or `RETRO_MEMDESC_MINSIZE_*` rules.
+    Misaligned(usize),
+    /// More than `MAX_READ` bytes were asked for.
+    TooLong(usize),
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::Unmapped(address) => write!(f, "{:#x} is not mapped", address),
+            MemoryError::ReadOnly(address) => write!(f, "{:#x} is read-only", address),
+            MemoryError::Misaligned(address) => {
+                write!(f, "{:#x} can't be accessed with that size", address)
+            }
+            MemoryError::TooLong(len) => {
+                write!(f, "{} bytes is too much, {} at most", len, MAX_READ)
+            }
+        }
+    }
+}
+
+/// The emulated address space, as the guest CPU sees it.
+///
+/// Cores that describe their memory with `SET_MEMORY_MAPS` get full address translation, mirrors
+/// included. For the others, `RETRO_MEMORY_SYSTEM_RAM` is mapped once, starting at address zero.
+pub struct AddressSpace {
+    descriptors: Vec<Descriptor>,
+}
+
+/// The default address space, the one with an empty name.
+///
+/// It's kept until the core changes its memory map or the game is unloaded. An empty one isn't,
+/// the game may not be loaded yet.
+pub fn address_space() -> Rc<AddressSpace> {
+    unsafe {
+        if let Some(space) = ADDRESS_SPACE.get_mut() {
+            return space.clone();
+        }
+
+        let space = Rc::new(named_address_space(""));
+        if !space.descriptors.is_empty() {
+            ADDRESS_SPACE.set(Some(space.clone()));
+        }
+
+        space
+    }
+}
+
+pub fn named_address_space(name: &str) -> AddressSpace {
+    let mut descriptors: Vec<Descriptor> = unsafe { DESCRIPTORS.get_mut() }
+        .iter()
+        .filter(|descriptor| descriptor.addrspace == name)
+        .cloned()
+        .collect();
+
+    if descriptors.is_empty() && name.is_empty() {
+        let Ok(api) = super::core::api() else {
+            return AddressSpace { descriptors };
+        };
+
+        unsafe {
+            let ptr = (api.retro_get_memory_data)(libretro::RETRO_MEMORY_SYSTEM_RAM);
+            let len = (api.retro_get_memory_size)(libretro::RETRO_MEMORY_SYSTEM_RAM);
+
+            if !ptr.is_null() && len > 0 {
+                descriptors.push(Descriptor {
+                    flags: libretro::RETRO_MEMDESC_SYSTEM_RAM as u64,
+                    ptr: ptr as *mut u8,
+                    offset: 0,
+                    start: 0,
+                    select: !(len.next_power_of_two() - 1),
+                    disconnect: 0,
+                    len,
+                    addrspace: String::new(),
+                });
+            }
+        }
+    }
+
+    AddressSpace { descriptors }
+}
+
+impl AddressSpace {
+    /// An address space over descriptors the front-end made up, to test what goes through it.
+    #[cfg(test)]
+    pub fn from_descriptors(descriptors: &[libretro::retro_memory_descriptor]) -> AddressSpace {
+        AddressSpace {
+            descriptors: preprocess(descriptors),
+        }
+    }
+
+    fn descriptor(&self, address: usize) -> Result<&Descriptor, MemoryError> {
+        let descriptor = self
+            .descriptors
+            .iter()
+            .find(|descriptor| descriptor.claims(address))
+            .ok_or(MemoryError::Unmapped(address))?;
+
+        if descriptor.ptr.is_null() {
+            return Err(MemoryError::Unmapped(address));
+        }
+
+        Ok(descriptor)
+    }
+
+    fn byte(&self, address: usize) -> Result<*mut u8, MemoryError> {
+        let descriptor = self.descriptor(address)?;
+
+        Ok(unsafe { descriptor.ptr.add(descriptor.translate(address)) })
+    }
+
+    /// The addresses of `len` bytes from `address`, which must not run past the address space.
+    /// The last byte of it is fine.
+    fn range(address: usize, len: usize) -> Result<impl Iterator<Item = usize>, MemoryError> {
+        if len > 0 && address.checked_add(len - 1).is_none() {
+            return Err(MemoryError::Unmapped(usize::MAX));
+        }
+
+        Ok((0..len).map(move |index| address + index))
+    }
+
+    /// Read `len` bytes starting at `address`, following mirrors byte by byte.
+    pub fn read(&self, address: usize, len: usize) -> Result<Vec<u8>, MemoryError> {
+        if len > MAX_READ {
+            return Err(MemoryError::TooLong(len));
+        }
+
+        Self::range(address, len)?
+            .map(|address| self.byte(address).map(|byte| unsafe { *byte }))
+            .collect()
+    }
+
+    /// Write bytes starting at `address`, as one access of their size. Nothing is written if any
+    /// of them lands in ROM or in unmapped memory, or the memory can't take an access that wide.
+    pub fn write(&self, address: usize, bytes: &[u8]) -> Result<(), MemoryError> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        self.check_access(address, bytes.len())?;
+
+        let mut targets = Vec::with_capacity(bytes.len());
+
+        for address in Self::range(address, bytes.len())? {
+            if self
+                .descriptor(address)?
+                .has_flag(libretro::RETRO_MEMDESC_CONST)
+            {
+                return Err(MemoryError::ReadOnly(address));
+            }
+
+            targets.push(self.byte(address)?);
+        }
+
+        for (target, byte) in targets.into_iter().zip(bytes) {
+            unsafe {
+                *target = *byte;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check a `width` bytes wide access against the alignment and size rules of its memory,
+    /// returning whether the memory is big endian.
+    fn check_access(&self, address: usize, width: usize) -> Result<bool, MemoryError> {
+        let descriptor = self.descriptor(address)?;
+
+        if width < descriptor.min_size() || address % width.min(descriptor.align()) != 0 {
+            return Err(MemoryError::Misaligned(address));
+        }
+
+        Ok(descriptor.has_flag(libretro::RETRO_MEMDESC_BIGENDIAN))
+    }
+
+    /// Read a `width` bytes wide value in the byte order of the memory holding it.
+    pub fn read_value(&self, address: usize, width: usize) -> Result<u32, MemoryError> {
+        let big_endian = self.check_access(address, width)?;
+        let bytes = self.read(address, width)?;
+
+        let fold = |value: u32, byte: &u8| value << 8 | *byte as u32;
+        Ok(if big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        })
+    }
+
+    /// Writable RAM of the guest, each block at its first mirror, for tools that scan memory.
+    ///
+    /// Blocks flagged as system RAM are preferred; when the core flags none, every writable block
+    /// is used. Descriptors with address lines disconnected inside the chip don't map linearly
+    /// and are left out.
+    pub fn regions(&self) -> Vec<Region> {
+        let usable = |descriptor: &&Descriptor| {
+            !descriptor.ptr.is_null()
+                && descriptor.disconnect & add_bits_down(descriptor.len - 1) == 0
+                && !descriptor.has_flag(libretro::RETRO_MEMDESC_CONST)
+        };
+
+        let system_ram: Vec<&Descriptor> = self
+            .descriptors
+            .iter()
+            .filter(usable)
+            .filter(|descriptor| descriptor.has_flag(libretro::RETRO_MEMDESC_SYSTEM_RAM))
+            .collect();
+
+        let descriptors = if system_ram.is_empty() {
+            self.descriptors.iter().filter(usable).collect()
+        } else {
+            system_ram
+        };
+
+        descriptors
+            .into_iter()
+            .map(|descriptor| Region {
+                start: descriptor.start,
+                len: descriptor.len,
+                ptr: unsafe { descriptor.ptr.add(descriptor.offset) },
+            })
+            .collect()
+    }
+}
+
+/// A contiguous block of guest memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    /// Guest address of the first byte.
+    pub start: usize,
+    pub len: usize,
+    ptr: *mut u8,
+}
+
+impl Region {
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.start && address - self.start < self.len
+    }
+
+    /// The bytes of the region, as they are right now.
+    ///
+    /// # Safety
+    /// The slice borrows the core's memory: it must not outlive the loaded game, and it changes
+    /// under our feet on every `retro_run`.
+    pub unsafe fn bytes(&self) -> &[u8] {
+        std::slice::from_raw_parts(self.ptr, self.len)
+    }
+}
+
+/// Read guest memory from the default address space.
+pub fn read(address: usize, len: usize) -> Result<Vec<u8>, MemoryError> {
+    address_space().read(address, len)
+}
+
+/// Write guest memory in the default address space.
+pub fn write(address: usize, bytes: &[u8]) -> Result<(), MemoryError> {
+    address_space().write(address, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(
+        flags: u32,
+        memory: &mut [u8],
+        start: usize,
+        select: usize,
+        len: usize,
+    ) -> libretro::retro_memory_descriptor {
+        libretro::retro_memory_descriptor {
+            flags: flags as u64,
+            ptr: memory.as_mut_ptr() as *mut std::os::raw::c_void,
+            offset: 0,
+            start,
+            select,
+            disconnect: 0,
+            len,
+            addrspace: std::ptr::null(),
+        }
+    }
+
+    /// The GBA's work RAMs, mirrored over their 16 MiB windows, VRAM with its 16-bit bus, and a
+    /// small ROM, as mGBA describes them.
+    struct Gba {
+        ewram: Vec<u8>,
+        iwram: Vec<u8>,
+        vram: Vec<u8>,
+        rom: Vec<u8>,
+    }
+
+    impl Gba {
+        fn new() -> Gba {
+            Gba {
+                ewram: vec![0; 0x40000],
+                iwram: vec![0; 0x8000],
+                vram: vec![0; 0x20000],
+                rom: (0..0x400).map(|index| index as u8).collect(),
+            }
+        }
+
+        fn space(&mut self) -> AddressSpace {
+            let system_ram = libretro::RETRO_MEMDESC_SYSTEM_RAM;
+            let vram = libretro::RETRO_MEMDESC_VIDEO_RAM
+                | libretro::RETRO_MEMDESC_ALIGN_2
+                | libretro::RETRO_MEMDESC_MINSIZE_2;
+            let descriptors = [
+                descriptor(
+                    system_ram,
+                    &mut self.ewram,
+                    0x0200_0000,
+                    0xFF00_0000,
+                    0x40000,
+                ),
+                descriptor(
+                    system_ram,
+                    &mut self.iwram,
+                    0x0300_0000,
+                    0xFF00_0000,
+                    0x8000,
+                ),
+                descriptor(vram, &mut self.vram, 0x0600_0000, 0xFF00_0000, 0x18000),
+                descriptor(
+                    libretro::RETRO_MEMDESC_CONST,
+                    &mut self.rom,
+                    0x0800_0000,
+                    0,
+                    0x400,
+                ),
+            ];
+
+            AddressSpace::from_descriptors(&descriptors)
+        }
+    }
+
+    #[test]
+    fn inflate_and_reduce_undo_each_other() {
+        assert_eq!(inflate(0b1011, 0b0100), 0b10011);
+        assert_eq!(reduce(0b10011, 0b0100), 0b1011);
+
+        for mask in [0, 0b1, 0b1010, 0xFC_0000, 0xF0F0] {
+            for address in [0, 1, 0x1234, 0x3FFFF] {
+                assert_eq!(reduce(inflate(address, mask), mask), address);
+            }
+        }
+    }
+
+    #[test]
+    fn translates_mirrors() {
+        let mut gba = Gba::new();
+        let space = gba.space();
+
+        space.write(0x0200_0010, &[0xAB]).unwrap();
+        // EWRAM repeats every 256 KiB of its window, IWRAM every 32 KiB.
+        assert_eq!(space.read(0x0204_0010, 1).unwrap(), [0xAB]);
+        assert_eq!(space.read(0x02FC_0010, 1).unwrap(), [0xAB]);
+
+        space.write(0x03FF_FFFF, &[0xCD]).unwrap();
+        assert_eq!(space.read(0x0300_7FFF, 1).unwrap(), [0xCD]);
+
+        // The 96 KiB of VRAM fill a 128 KiB window, past its end the highest bit of the offset
+        // is cleared.
+        space.write(0x0600_8000, &[1, 2]).unwrap();
+        assert_eq!(space.read(0x0601_8000, 2).unwrap(), [1, 2]);
+
+        assert_eq!(space.read(0x0800_0100, 2).unwrap(), [0x00, 0x01]);
+        assert_eq!(space.read_value(0x0800_0102, 2).unwrap(), 0x0302);
+
+        drop(space);
+        assert_eq!((gba.ewram[0x10], gba.iwram[0x7FFF]), (0xAB, 0xCD));
+        assert_eq!(&gba.vram[0x8000..0x8002], &[1, 2]);
+    }
+
+    #[test]
+    fn refuses_writes_the_memory_does_not_take() {
+        let mut gba = Gba::new();
+        let space = gba.space();
+
+        assert_eq!(
+            space.write(0x0800_0000, &[0xFF]),
+            Err(MemoryError::ReadOnly(0x0800_0000))
+        );
+        assert_eq!(
+            space.write(0x0500_0000, &[0xFF]),
+            Err(MemoryError::Unmapped(0x0500_0000))
+        );
+        // VRAM only takes 16-bit accesses, aligned.
+        assert_eq!(
+            space.write(0x0600_0000, &[0xFF]),
+            Err(MemoryError::Misaligned(0x0600_0000))
+        );
+        assert_eq!(
+            space.write(0x0600_0001, &[0xFF, 0xFF]),
+            Err(MemoryError::Misaligned(0x0600_0001))
+        );
+        space.write(0x0600_0002, &[0xFF, 0xFF]).unwrap();
+
+        // Running off the end of IWRAM's window writes nothing at all.
+        assert_eq!(
+            space.write(0x03FF_FFFF, &[1, 2]),
+            Err(MemoryError::Unmapped(0x0400_0000))
+        );
+        assert_eq!(space.read(0x03FF_FFFF, 1).unwrap(), [0]);
+
+        assert_eq!(
+            space.read(0x0200_0000, MAX_READ + 1),
+            Err(MemoryError::TooLong(MAX_READ + 1))
+        );
+        assert_eq!(
+            space.read(usize::MAX, 2),
+            Err(MemoryError::Unmapped(usize::MAX))
+        );
+    }
+
+    #[test]
+    fn maps_memory_at_the_top_of_the_address_space() {
+        let mut memory = vec![0u8; 0x100];
+        let start = usize::MAX - 0xFF;
+        let space = AddressSpace {
+            descriptors: preprocess(&[descriptor(0, &mut memory, start, 0, 0x100)]),
+        };
+
+        space.write(usize::MAX, &[7]).unwrap();
+        assert_eq!(space.read(usize::MAX, 1).unwrap(), [7]);
+
+        drop(space);
+        assert_eq!(memory[0xFF], 7);
+    }
+
+    #[test]
+    fn lists_system_ram_as_regions() {
+        let mut gba = Gba::new();
+        let regions = gba.space().regions();
+
+        let regions: Vec<(usize, usize)> = regions
+            .iter()
+            .map(|region| (region.start, region.len))
+            .collect();
+        assert_eq!(regions, [(0x0200_0000, 0x40000), (0x0300_0000, 0x8000)]);
+    }
+}
@@ -0,0 +1,33 @@
+/// A notice for the user, sent by the core with `SET_MESSAGE` or `SET_MESSAGE_EXT`, or by the
+/// front-end itself.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub text: String,
+    /// How many frames the message stays on screen.
+    pub frames: u32,
+    /// Messages with a higher priority are shown before the others.
+    pub priority: u32,
+    pub kind: MessageKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// Stacks with the other notifications until it expires.
+    Notification,
+    /// A line of status, replaced by the next one.
+    Status,
+    /// A task going on, with its progress from 0 to 100, or -1 when it can't tell.
+    Progress(i8),
+}
+
+impl Message {
+    /// A notification from the front-end, shown for three seconds.
+    pub fn notification(text: impl Into<String>) -> Message {
+        Message {
+            text: text.into(),
+            frames: 180,
+            priority: 0,
+            kind: MessageKind::Notification,
+        }
+    }
+}
@@ -1,13 +1,112 @@
 use std::cell::Cell;
+use std::ffi::{CStr, CString};
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::ptr;
 
+pub mod cheats;
+pub mod color;
+mod convert;
+pub mod core;
+pub mod cores;
+pub mod hash;
 mod libretro;
+pub mod memory;
+pub mod message;
+pub mod movie;
+pub mod options;
+mod patch;
+pub mod rotation;
+pub mod search;
+pub mod state;
+
+pub use convert::PixelFormat;
 
 static mut BUTTONS_PRESSED: Cell<Option<Vec<i16>>> = Cell::new(None);
 
-static mut VIDEO_FRAME_DATA: Cell<Option<Vec<u32>>> = Cell::new(None);
+/// The last frame the core sent, converted to `PIXEL_FORMAT`. The buffer is reused for every
+/// frame.
+static mut VIDEO_FRAME_DATA: Cell<Vec<u32>> = Cell::new(Vec::new());
+/// Whether the core sent a frame, new or duplicated, during the last `retro_run`.
+static mut VIDEO_FRAME_NEW: Cell<bool> = Cell::new(false);
+static mut VIDEO_FRAME_SIZE: Cell<(usize, usize)> = Cell::new((0, 0));
+/// Frame handed to the core by `GET_CURRENT_SOFTWARE_FRAMEBUFFER`, for it to draw into.
+static mut SOFTWARE_FRAMEBUFFER: Cell<Vec<u16>> = Cell::new(Vec::new());
+/// Whether the last frame the core sent was drawn into `SOFTWARE_FRAMEBUFFER`.
+static mut SOFTWARE_FRAMEBUFFER_USED: Cell<bool> = Cell::new(false);
+/// Whether it was logged which frame the core draws into, once per core.
+static mut SOFTWARE_FRAMEBUFFER_LOGGED: Cell<bool> = Cell::new(false);
+/// Format of the core's frames, agreed with `SET_PIXEL_FORMAT`. Cores start in 0RGB1555.
+static mut CORE_PIXEL_FORMAT: Cell<libretro::retro_pixel_format> =
+    Cell::new(libretro::retro_pixel_format_RETRO_PIXEL_FORMAT_0RGB1555);
+/// A 0RGB1555 frame rewritten as RGB565, the only format the converter reads.
+static mut RGB565_FRAME: Cell<Vec<u8>> = Cell::new(Vec::new());
+/// Converts the frames of the core, with the table of the selected color profile.
+static mut CONVERTER: Cell<Option<convert::Converter>> = Cell::new(None);
+static mut COLOR_PROFILE: Cell<(color::ColorProfile, f32)> =
+    Cell::new((color::ColorProfile::Off, 2.2));
+static mut PIXEL_FORMAT: Cell<PixelFormat> = Cell::new(PixelFormat::Rgba8);
+/// Rotation the core asked for with `SET_ROTATION`.
+static mut ROTATION: Cell<rotation::Rotation> = Cell::new(rotation::Rotation::None);
+/// Rotation forced by the user, over the core's.
+static mut FORCED_ROTATION: Cell<Option<rotation::Rotation>> = Cell::new(None);
+/// Position of the mouse on the core's screen, `None` when it's outside, and whether its button
+/// is held. Cores read it as a pointer or a lightgun.
+static mut POINTER: Cell<(Option<(i16, i16)>, bool)> = Cell::new((None, false));
+/// Options the core declared, with the values selected for them.
+static mut OPTIONS: Cell<Vec<options::CoreOption>> = Cell::new(Vec::new());
+/// Whether an option changed since the core last asked with `GET_VARIABLE_UPDATE`.
+static mut OPTIONS_UPDATED: Cell<bool> = Cell::new(false);
+
+/// Slot `save_state` and `load_state` use, 0 being the `state` file next to the ROM.
+static mut STATE_SLOT: Cell<u32> = Cell::new(0);
+
+/// Messages to show on screen, until the front-end takes them.
+static mut MESSAGES: Cell<Vec<message::Message>> = Cell::new(Vec::new());
+/// Frames run since the content was loaded.
+static mut FRAME_COUNT: Cell<u64> = Cell::new(0);
+/// Frames per second and audio sample rate of the core, asked for once the game is loaded.
+static mut TIMING: Cell<(f64, f64)> = Cell::new((0.0, 0.0));
+/// Interleaved stereo samples of the frame, `None` unless audio is being captured.
 static mut AUDIO_FRAME_DATA: Cell<Option<Vec<i16>>> = Cell::new(None);
+/// How many consumers asked for the audio with `capture_audio`.
+static mut AUDIO_CAPTURES: Cell<u32> = Cell::new(0);
+
+static mut CONTENT: Cell<Option<Content>> = Cell::new(None);
+
+static mut CHEATS: Cell<Vec<cheats::Cheat>> = Cell::new(Vec::new());
+
+static mut FREEZES: Cell<Vec<search::Freeze>> = Cell::new(Vec::new());
+
+static mut MOVIE: Cell<Option<movie::Movie>> = Cell::new(None);
+/// Whether the save RAM is read from and written to `game.srm`.
+static mut SRAM_FILES: Cell<bool> = Cell::new(true);
+/// The save RAM was replaced by the one of a movie, it isn't the player's to write to `game.srm`.
+static mut SRAM_FROM_MOVIE: Cell<bool> = Cell::new(false);
+
+/// `RETRO_SERIALIZATION_QUIRK_*` flags the core set, the limits of its states.
+static mut SERIALIZATION_QUIRKS: Cell<u64> = Cell::new(0);
+/// The auto state is loaded, and written, once the first frame ran, for cores that can't
+/// serialize before.
+static mut AUTO_STATE_LOAD_QUEUED: Cell<bool> = Cell::new(false);
+static mut AUTO_STATE_SAVE_QUEUED: Cell<bool> = Cell::new(false);
+/// Identifies this run of the front-end in states, for cores whose states don't outlive it.
+static mut SESSION_ID: Cell<u64> = Cell::new(0);
+
+/// Frames run ahead of the shown one, and whether on a second instance of the core.
+static mut RUN_AHEAD: Cell<(u32, bool)> = Cell::new((0, false));
+/// Why the core is asked to serialize, told with `GET_SAVESTATE_CONTEXT`.
+static mut SAVESTATE_CONTEXT: Cell<libretro::retro_savestate_context> =
+    Cell::new(libretro::retro_savestate_context_RETRO_SAVESTATE_CONTEXT_NORMAL);
+/// The state run-ahead goes back to, kept between frames to not allocate it every time.
+static mut RUN_AHEAD_STATE: Cell<Vec<u8>> = Cell::new(Vec::new());
+/// Frames run ahead make no sound, and only the last one is drawn.
+static mut RUNNING_AHEAD: Cell<bool> = Cell::new(false);
+static mut VIDEO_SKIPPED: Cell<bool> = Cell::new(false);
+/// The second instance of the core is being called, it mustn't change what the first one set.
+static mut IN_SECONDARY: Cell<bool> = Cell::new(false);
+/// Options changed since the second instance last asked.
+static mut SECONDARY_OPTIONS_UPDATED: Cell<bool> = Cell::new(false);
 
 /*pub const RETRO_DEVICE_ID_JOYPAD_B: i16 = 0;
 pub const RETRO_DEVICE_ID_JOYPAD_Y: i16 = 1;
@@ -41,113 +140,229 @@ pub const DEVICE_ID_JOYPAD_X: i16 = 9;
 //  [00 0      0       0000 0000 0  0  0  0]
 
 unsafe extern "C" fn my_environment(
-    _cmd: std::os::raw::c_uint,
-    _data: *mut std::os::raw::c_void,
+    cmd: std::os::raw::c_uint,
+    data: *mut std::os::raw::c_void,
 ) -> bool {
-    /*match cmd {
+    // The second instance is the same core with the same game, what it declares is already
+    // known and its messages would show twice.
+    if IN_SECONDARY.get() {
+        match cmd {
+            libretro::RETRO_ENVIRONMENT_SET_ROTATION
+            | libretro::RETRO_ENVIRONMENT_SET_VARIABLES
+            | libretro::RETRO_ENVIRONMENT_SET_MESSAGE
+            | libretro::RETRO_ENVIRONMENT_SET_MESSAGE_EXT
+            | libretro::RETRO_ENVIRONMENT_SET_SERIALIZATION_QUIRKS
+            | libretro::RETRO_ENVIRONMENT_SET_MEMORY_MAPS => return true,
+            libretro::RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+                *(data as *mut bool) = SECONDARY_OPTIONS_UPDATED.replace(false);
+                return true;
+            }
+            _ => {}
+        }
+    }
+
+    match cmd {
         libretro::RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
-            /*let pixel_format = *(data as *const u32);
-            let pixel_format_as_enum = pixel_format;
-            match pixel_format_as_enum {
-                libretro::retro_pixel_format_RETRO_PIXEL_FORMAT_0RGB1555 => {
-                    panic!("Core will send us pixel data in the RETRO_PIXEL_FORMAT_0RGB1555 format")
+            // Frames are converted from RGB565, 0RGB1555 is rewritten to it first. XRGB8888 is
+            // refused, cores then fall back to one of the others.
+            let format = *(data as *const libretro::retro_pixel_format);
+            return match format {
+                libretro::retro_pixel_format_RETRO_PIXEL_FORMAT_RGB565
+                | libretro::retro_pixel_format_RETRO_PIXEL_FORMAT_0RGB1555 => {
+                    CORE_PIXEL_FORMAT.set(format);
+                    true
                 }
-                libretro::retro_pixel_format_RETRO_PIXEL_FORMAT_RGB565 => {
-                    panic!("Core will send us pixel data in the RETRO_PIXEL_FORMAT_RGB565 format") <-----
+                _ => false,
+            };
+        }
+        libretro::RETRO_ENVIRONMENT_SET_ROTATION => {
+            return match rotation::Rotation::from_index(*(data as *const u32)) {
+                Some(rotation) => {
+                    ROTATION.set(rotation);
+                    true
                 }
-                libretro::retro_pixel_format_RETRO_PIXEL_FORMAT_XRGB8888 => {
-                    panic!("Core will send us pixel data in the RETRO_PIXEL_FORMAT_XRGB8888 format")
+                None => false,
+            };
+        }
+        libretro::RETRO_ENVIRONMENT_SET_VARIABLES => {
+            OPTIONS.set(options::parse_variables(
+                data as *const libretro::retro_variable,
+            ));
+
+            return true;
+        }
+        libretro::RETRO_ENVIRONMENT_GET_VARIABLE => {
+            let variable = &mut *(data as *mut libretro::retro_variable);
+            let key = CStr::from_ptr(variable.key);
+
+            // The value points into the option, which lives until the core declares new ones.
+            return match OPTIONS
+                .get_mut()
+                .iter()
+                .find(|option| option.key.as_bytes() == key.to_bytes())
+            {
+                Some(option) => {
+                    variable.value = option.value().as_ptr();
+                    true
                 }
-                _ => panic!("Core is trying to use an Unknown Pixel Format"),
-            }*/
+                None => false,
+            };
+        }
+        libretro::RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            let updated = OPTIONS_UPDATED.replace(false);
+            if updated {
+                SECONDARY_OPTIONS_UPDATED.set(true);
+            }
+            *(data as *mut bool) = updated;
 
             return true;
         }
-        _ => println!("libretro_environment_callback Called with command: {}", cmd),
-    }*/
+        libretro::RETRO_ENVIRONMENT_GET_MESSAGE_INTERFACE_VERSION => {
+            // Version 1 is `SET_MESSAGE_EXT`.
+            *(data as *mut u32) = 1;
 
-    return false;
-}
+            return true;
+        }
+        libretro::RETRO_ENVIRONMENT_SET_MESSAGE => {
+            let message = &*(data as *const libretro::retro_message);
 
-/// Convert an RGB565 framebuffer to XRGB8888 format.
-///
-/// The mGBA core outputs pixels in RGB565 format, but pixels requires XRGB8888.
-/// This function converts each pixel accordingly.
-///
-/// ### RETRO_PIXEL_FORMAT_RGB565
-/// - 5 bits for red   (RRRRR)
-/// - 6 bits for green (GGGGGG) -> Humans perceive more shades of green.
-/// - 5 bits for blue  (BBBBB)
-/// - 16 bits total (2 bytes per pixel).
-///
-/// ### RETRO_PIXEL_FORMAT_XRGB8888
-/// - 8-bit padding (unused, set to 0xFF for full opacity).
-/// - 8 bits for red   (RRRRRRRR)
-/// - 8 bits for green (GGGGGGGG)
-/// - 8 bits for blue  (BBBBBBBB)
-/// - 32 bits total (4 bytes per pixel).
-fn convert_pixel_array_from_rgb565_to_xrgb8888(
-    frame: &[u8],
-    width: usize,
-    height: usize,
-    pitch: usize,
-) -> Box<[u32]> {
-    let frame_size = width * height;
-    let mut converted_frame = vec![0u32; frame_size].into_boxed_slice();
-
-    for y in 0..height {
-        let row_offset = y * pitch;
-        let row_bytes = &frame[row_offset..row_offset + (width * 2)];
-
-        for x in 0..width {
-            let pixel_offset = x * 2;
-
-            let byte1 = row_bytes[pixel_offset] as u32;
-            let byte2 = row_bytes[pixel_offset + 1] as u32;
-
-            // One pixel is two bytes or 16 bits. (byte1 and byte2).
-            //                           byte2    byte1
-            // pixel = 00000000 00000000 ^^^^^^^^ ^^^^^^^^
-            let pixel = (byte2 << 8) | byte1;
-
-            // Extract the color components from the 16-bit RGB565 format:
-            //
-            // RGB565 bit layout:
-            //  RRRRR GGGGGG BBBBB
-            //
-            // Extract red (5 bits) -> shift right 11 places, mask to keep 5 bits, then scale to 8 bits.
-            //
-            //  RRRRR GGGGGG BBBBB   (Original)
-            //  00000 000000 RRRRR   (After `>> 11`)
-            //
-            let r = ((pixel >> 11) & 0x1F) << 3;
-
-            // Extract green (6 bits) -> shift right 5 places, mask to keep 6 bits, then scale to 8 bits.
-            //
-            // RRRRR GGGGGG BBBBB    (Original)
-            // 00000 000000 GGGGGG   (After `>> 5`)
-            //
-            let g = ((pixel >> 5) & 0x3F) << 2;
-            // Extract blue (5 bits) -> mask to keep 5 bits, then scale to 8 bits.
-            let b = (pixel & 0x1F) << 3;
-
-            // Combine into XRGB8888 format (0xFF for full opacity).
-            /*
-                X  = 0xFF  (11111111 00000000 00000000 00000000)
-                B  = 0x80  (00000000 10000000 00000000 00000000)
-                G  = 0xC0  (00000000 00000000 11000000 00000000)
-                R  = 0x40  (00000000 00000000 00000000 01000000)
-                -----------------------------------------------
-                Result =   11111111 10000000 11000000 01000000
-            */
-            let xrgb8888_pixel = (0xFF << 24) | (b << 16) | (g << 8) | r;
-
-            // Store the converted pixel in the output frame.
-            converted_frame[y * width + x] = xrgb8888_pixel;
-        }
-    }
-
-    converted_frame
+            MESSAGES.get_mut().push(message::Message {
+                text: CStr::from_ptr(message.msg).to_string_lossy().into_owned(),
+                frames: message.frames,
+                priority: 0,
+                kind: message::MessageKind::Notification,
+            });
+
+            return true;
+        }
+        libretro::RETRO_ENVIRONMENT_SET_MESSAGE_EXT => {
+            let message = &*(data as *const libretro::retro_message_ext);
+            let text = CStr::from_ptr(message.msg).to_string_lossy().into_owned();
+
+            if message.target != libretro::retro_message_target_RETRO_MESSAGE_TARGET_OSD {
+                println!("{}", text);
+            }
+            if message.target == libretro::retro_message_target_RETRO_MESSAGE_TARGET_LOG {
+                return true;
+            }
+
+            // The duration is in milliseconds, the OSD counts frames. The core can't be asked
+            // for its timing from inside its own call, and while it loads the game it doesn't
+            // know it yet, 60 frames per second is close enough then.
+            let (fps, _) = TIMING.get();
+            let fps = if fps > 0.0 { fps } else { 60.0 };
+            let frames = (message.duration as f64 * fps / 1000.0).round() as u32;
+
+            MESSAGES.get_mut().push(message::Message {
+                text,
+                frames,
+                priority: message.priority,
+                kind: match message.type_ {
+                    libretro::retro_message_type_RETRO_MESSAGE_TYPE_STATUS => {
+                        message::MessageKind::Status
+                    }
+                    libretro::retro_message_type_RETRO_MESSAGE_TYPE_PROGRESS => {
+                        message::MessageKind::Progress(message.progress)
+                    }
+                    _ => message::MessageKind::Notification,
+                },
+            });
+
+            return true;
+        }
+        libretro::RETRO_ENVIRONMENT_GET_CAN_DUPE => {
+            // Duplicated frames come as a null `data` in `my_video_refresh`, which shows the last
+            // frame again.
+            *(data as *mut bool) = true;
+
+            return true;
+        }
+        libretro::RETRO_ENVIRONMENT_GET_CURRENT_SOFTWARE_FRAMEBUFFER => {
+            // The core asks for a frame of its size, in the format it agreed on, which it may
+            // draw into instead of its own memory. Both formats are 16 bits per pixel.
+            let framebuffer = &mut *(data as *mut libretro::retro_framebuffer);
+            let width = framebuffer.width as usize;
+            let len = width * framebuffer.height as usize;
+
+            let access = framebuffer.access_flags;
+            let known = libretro::RETRO_MEMORY_ACCESS_READ | libretro::RETRO_MEMORY_ACCESS_WRITE;
+            if access & !known != 0 {
+                return false;
+            }
+
+            // Reading needs the previous frame to be in the buffer, which is only the case when
+            // the core drew it there at the same size.
+            let buffer = SOFTWARE_FRAMEBUFFER.get_mut();
+            if access & libretro::RETRO_MEMORY_ACCESS_READ != 0
+                && (!SOFTWARE_FRAMEBUFFER_USED.get() || buffer.len() != len)
+            {
+                return false;
+            }
+            buffer.resize(len, 0);
+
+            framebuffer.data = buffer.as_mut_ptr() as *mut std::os::raw::c_void;
+            framebuffer.pitch = width * 2;
+            framebuffer.format = CORE_PIXEL_FORMAT.get();
+            framebuffer.memory_flags = libretro::RETRO_MEMORY_TYPE_CACHED;
+
+            return true;
+        }
+        libretro::RETRO_ENVIRONMENT_SET_SERIALIZATION_QUIRKS => {
+            // Flags the front-end doesn't know about are cleared, telling the core so.
+            let quirks = &mut *(data as *mut u64);
+            *quirks &= (libretro::RETRO_SERIALIZATION_QUIRK_PLATFORM_DEPENDENT << 1) as u64 - 1;
+            // The size is asked for on every save and states of any size are handed back, the
+            // front-end copes with it changing.
+            if *quirks & libretro::RETRO_SERIALIZATION_QUIRK_CORE_VARIABLE_SIZE as u64 != 0 {
+                *quirks |= libretro::RETRO_SERIALIZATION_QUIRK_FRONT_VARIABLE_SIZE as u64;
+            }
+            SERIALIZATION_QUIRKS.set(*quirks);
+
+            for (quirk, limitation) in [
+                (
+                    libretro::RETRO_SERIALIZATION_QUIRK_INCOMPLETE,
+                    "the core's states may not restore everything",
+                ),
+                (
+                    libretro::RETRO_SERIALIZATION_QUIRK_MUST_INITIALIZE,
+                    "the core can only save states once the game runs",
+                ),
+                (
+                    libretro::RETRO_SERIALIZATION_QUIRK_SINGLE_SESSION,
+                    "the core's states only load until the front-end is closed",
+                ),
+                (
+                    libretro::RETRO_SERIALIZATION_QUIRK_ENDIAN_DEPENDENT,
+                    "the core's states only load on machines of the same byte order",
+                ),
+                (
+                    libretro::RETRO_SERIALIZATION_QUIRK_PLATFORM_DEPENDENT,
+                    "the core's states only load on the same platform",
+                ),
+            ] {
+                if *quirks & quirk as u64 != 0 {
+                    Emualtor {}.notify(limitation);
+                }
+            }
+
+            return true;
+        }
+        libretro::RETRO_ENVIRONMENT_GET_SAVESTATE_CONTEXT => {
+            if !data.is_null() {
+                *(data as *mut libretro::retro_savestate_context) = SAVESTATE_CONTEXT.get();
+            }
+
+            return true;
+        }
+        libretro::RETRO_ENVIRONMENT_SET_MEMORY_MAPS => {
+            memory::set_memory_maps(data as *const libretro::retro_memory_map);
+
+            return true;
+        }
+        _ => {}
+    }
+
+    return false;
 }
 
 unsafe extern "C" fn my_video_refresh(
@@ -156,32 +371,67 @@ unsafe extern "C" fn my_video_refresh(
     height: std::os::raw::c_uint,
     pitch: usize,
 ) {
+    if VIDEO_SKIPPED.get() {
+        return;
+    }
+
+    // A null frame duplicates the previous one, which is still in `VIDEO_FRAME_DATA`.
     if data == ptr::null() {
-        println!("frame_buffer_data was null");
+        VIDEO_FRAME_NEW.set(!VIDEO_FRAME_DATA.get_mut().is_empty());
         return;
     }
 
-    // let length_of_frame_buffer = width * height;
-    let length_of_frame_buffer = ((pitch as u32) * height) * 2 as u32;
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    // The core either drew into the frame we gave it or sent one of its own, the conversion is
+    // the same but the frame can only be read back by the core when it's ours.
+    let shared = data == SOFTWARE_FRAMEBUFFER.get_mut().as_ptr() as *const std::os::raw::c_void;
+    SOFTWARE_FRAMEBUFFER_USED.set(shared);
+    if !SOFTWARE_FRAMEBUFFER_LOGGED.replace(true) {
+        bevy::log::debug!(
+            "core draws into {} framebuffer",
+            if shared { "the front-end's" } else { "its own" }
+        );
+    }
+
+    let length_of_frame_buffer = pitch * (height - 1) + width * 2;
 
-    let buffer_slice =
-        std::slice::from_raw_parts(data as *const u8, length_of_frame_buffer as usize);
+    let mut buffer_slice = std::slice::from_raw_parts(data as *const u8, length_of_frame_buffer);
+    let mut pitch = pitch;
+    if CORE_PIXEL_FORMAT.get() == libretro::retro_pixel_format_RETRO_PIXEL_FORMAT_0RGB1555 {
+        let frame = RGB565_FRAME.get_mut();
+        convert::rgb565_from_0rgb1555(buffer_slice, width, height, pitch, frame);
+        (buffer_slice, pitch) = (frame.as_slice(), width * 2);
+    }
 
-    let result = convert_pixel_array_from_rgb565_to_xrgb8888(
+    let converter = CONVERTER
+        .get_mut()
+        .get_or_insert_with(|| convert::Converter::new(PIXEL_FORMAT.get(), None));
+    converter.convert(
         buffer_slice,
-        width as usize,
-        height as usize,
+        width,
+        height,
         pitch,
+        VIDEO_FRAME_DATA.get_mut(),
     );
 
-    let buffer_vec: Vec<u32> = Vec::from(result);
-
-    //CURRENT_EMULATOR_STATE.frame_buffer = Some(buffer_vec);
-    VIDEO_FRAME_DATA.set(Some(buffer_vec));
+    VIDEO_FRAME_NEW.set(true);
+    VIDEO_FRAME_SIZE.set((width, height));
 }
 
-unsafe extern "C" fn my_audio_sample_batch(_data: *const i16, _frames: usize) -> usize {
-    return 0;
+unsafe extern "C" fn my_audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    if RUNNING_AHEAD.get() {
+        return frames;
+    }
+
+    if let Some(samples) = AUDIO_FRAME_DATA.get_mut() {
+        samples.extend_from_slice(std::slice::from_raw_parts(data, frames * 2));
+    }
+
+    return frames;
 }
 
 unsafe extern "C" fn my_input_poll() {
@@ -189,108 +439,1437 @@ unsafe extern "C" fn my_input_poll() {
 }
 
 unsafe extern "C" fn my_input_state(
-    _port: std::os::raw::c_uint,
-    _device: std::os::raw::c_uint,
-    _index: std::os::raw::c_uint,
+    port: std::os::raw::c_uint,
+    device: std::os::raw::c_uint,
+    index: std::os::raw::c_uint,
     id: std::os::raw::c_uint,
 ) -> i16 {
-    let is_pressed = match BUTTONS_PRESSED.get_mut() {
-        Some(buttons_pressed) => buttons_pressed[id as usize],
-        None => 0,
+    if let Some(movie) = MOVIE.get_mut() {
+        if let Some(value) = movie.input(port, device, index, id) {
+            return value;
+        }
+    }
+
+    let is_pressed = match (device, BUTTONS_PRESSED.get_mut()) {
+        (libretro::RETRO_DEVICE_POINTER | libretro::RETRO_DEVICE_LIGHTGUN, _) => {
+            pointer_state(device, id)
+        }
+        (_, Some(buttons_pressed)) => buttons_pressed[id as usize],
+        (_, None) => 0,
     };
 
+    if let Some(movie) = MOVIE.get_mut() {
+        movie.record(port, device, index, id, is_pressed);
+    }
+
     return is_pressed;
 }
 
-unsafe extern "C" fn my_audio_sample(_left: i16, _right: i16) {}
+/// The mouse, read by the core as a pointer or as a lightgun.
+unsafe fn pointer_state(device: std::os::raw::c_uint, id: std::os::raw::c_uint) -> i16 {
+    let (position, pressed) = POINTER.get();
+    let (x, y) = position.unwrap_or((0, 0));
+
+    match (device, id) {
+        (libretro::RETRO_DEVICE_POINTER, libretro::RETRO_DEVICE_ID_POINTER_X)
+        | (libretro::RETRO_DEVICE_LIGHTGUN, libretro::RETRO_DEVICE_ID_LIGHTGUN_SCREEN_X) => x,
+        (libretro::RETRO_DEVICE_POINTER, libretro::RETRO_DEVICE_ID_POINTER_Y)
+        | (libretro::RETRO_DEVICE_LIGHTGUN, libretro::RETRO_DEVICE_ID_LIGHTGUN_SCREEN_Y) => y,
+        (libretro::RETRO_DEVICE_POINTER, libretro::RETRO_DEVICE_ID_POINTER_PRESSED) => {
+            (pressed && position.is_some()) as i16
+        }
+        (libretro::RETRO_DEVICE_POINTER, libretro::RETRO_DEVICE_ID_POINTER_COUNT) => {
+            position.is_some() as i16
+        }
+        (libretro::RETRO_DEVICE_LIGHTGUN, libretro::RETRO_DEVICE_ID_LIGHTGUN_TRIGGER) => {
+            pressed as i16
+        }
+        (libretro::RETRO_DEVICE_LIGHTGUN, libretro::RETRO_DEVICE_ID_LIGHTGUN_IS_OFFSCREEN) => {
+            position.is_none() as i16
+        }
+        _ => 0,
+    }
+}
+
+unsafe extern "C" fn my_audio_sample(left: i16, right: i16) {
+    if RUNNING_AHEAD.get() {
+        return;
+    }
+
+    if let Some(samples) = AUDIO_FRAME_DATA.get_mut() {
+        samples.extend_from_slice(&[left, right]);
+    }
+}
+
+/// The movie saved along with a save state, `game.state.movie` for `game.state`.
+fn state_movie_path(state: &Path) -> PathBuf {
+    let mut path = state.as_os_str().to_owned();
+    path.push(".movie");
+
+    PathBuf::from(path)
+}
+
+/// The game loaded into the core.
+struct Content {
+    path: PathBuf,
+    /// CRC-32 of the data handed to the core, after patches were applied.
+    crc32: u32,
+    patched: bool,
+    /// The data handed to the core, for the second instance of run-ahead.
+    data: Vec<u8>,
+}
+
+impl Content {
+    /// Path of a save file for this content, next to the ROM.
+    ///
+    /// Patched content carries its CRC-32 in the name, so saves of a romhack or translation
+    /// never clash with the ones of the original game.
+    fn save_path(&self, extension: &str) -> PathBuf {
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("content"));
+
+        let name = if self.patched {
+            format!("{}.{:08x}.{}", stem, self.crc32, extension)
+        } else {
+            format!("{}.{}", stem, extension)
+        };
+
+        self.path.with_file_name(name)
+    }
+}
 
 pub struct Emualtor {}
 
 impl Emualtor {
+    /// Start the core linked into the binary.
     pub fn init(&self) {
+        self.load_core(None)
+            .expect("could not initialize the built-in core");
+    }
+
+    /// Start the core in the shared library at `path`, or the linked one with `None`, in place of
+    /// the running one, which is unloaded along with its game first.
+    pub fn load_core(&self, path: Option<&Path>) -> Result<(), core::CoreError> {
+        self.unload_core();
+        core::load(path)?;
+
+        unsafe {
+            let api = core::api()?;
+
+            (api.retro_set_environment)(Some(my_environment));
+
+            (api.retro_set_video_refresh)(Some(my_video_refresh));
+
+            (api.retro_set_audio_sample)(Some(my_audio_sample));
+
+            (api.retro_set_audio_sample_batch)(Some(my_audio_sample_batch));
+
+            (api.retro_set_input_state)(Some(my_input_state));
+
+            (api.retro_set_input_poll)(Some(my_input_poll));
+
+            (api.retro_init)();
+        }
+
+        Ok(())
+    }
+
+    pub fn is_core_loaded(&self) -> bool {
+        core::api().is_ok()
+    }
+
+    /// Shared library of the running core, `None` for the linked one.
+    pub fn core_path(&self) -> Option<PathBuf> {
+        core::path()
+    }
+
+    /// Deinitialize the core and close its library, unloading its game first.
+    ///
+    /// Until another core is loaded, calls into the core fail instead of reaching it.
+    pub fn unload_core(&self) {
+        let Ok(api) = core::api() else {
+            return;
+        };
+
+        self.unload_game();
+
+        unsafe {
+            (api.retro_deinit)();
+        }
+        core::unload();
+
+        // Options and the shared framebuffer belong to the core.
+        unsafe {
+            OPTIONS.set(Vec::new());
+            OPTIONS_UPDATED.set(false);
+            SERIALIZATION_QUIRKS.set(0);
+            SOFTWARE_FRAMEBUFFER.set(Vec::new());
+            SOFTWARE_FRAMEBUFFER_USED.set(false);
+            SOFTWARE_FRAMEBUFFER_LOGGED.set(false);
+            CORE_PIXEL_FORMAT.set(libretro::retro_pixel_format_RETRO_PIXEL_FORMAT_0RGB1555);
+        }
+    }
+
+    /// Unload the game, writing its save RAM next to it, and forget everything that belonged to
+    /// it: cheats, freezes, the movie, the memory map and the last frame.
+    pub fn unload_game(&self) {
+        if self.content().is_none() {
+            return;
+        }
+
+        self.save_sram();
+        self.stop_movie();
+        self.unload_secondary();
+
+        if let Ok(api) = core::api() {
+            unsafe { (api.retro_unload_game)() };
+        }
+
+        memory::clear();
+
         unsafe {
-            libretro::retro_set_environment(Some(my_environment));
+            CONTENT.set(None);
+            CHEATS.set(Vec::new());
+            FREEZES.set(Vec::new());
+            BUTTONS_PRESSED.set(None);
+            VIDEO_FRAME_DATA.set(Vec::new());
+            VIDEO_FRAME_NEW.set(false);
+            VIDEO_FRAME_SIZE.set((0, 0));
+            ROTATION.set(rotation::Rotation::None);
+            FRAME_COUNT.set(0);
+            TIMING.set((0.0, 0.0));
+            AUTO_STATE_LOAD_QUEUED.set(false);
+            AUTO_STATE_SAVE_QUEUED.set(false);
+            RUN_AHEAD_STATE.set(Vec::new());
+            SRAM_FROM_MOVIE.set(false);
+        }
+    }
+
+    /// The battery-backed memory of the game, `RETRO_MEMORY_SAVE_RAM`, empty for games without.
+    fn sram(&self) -> Option<&'static mut [u8]> {
+        let api = core::api().ok()?;
+
+        unsafe {
+            let ptr = (api.retro_get_memory_data)(libretro::RETRO_MEMORY_SAVE_RAM);
+            let len = (api.retro_get_memory_size)(libretro::RETRO_MEMORY_SAVE_RAM);
 
-            libretro::retro_set_video_refresh(Some(my_video_refresh));
+            (!ptr.is_null() && len > 0).then(|| std::slice::from_raw_parts_mut(ptr as *mut u8, len))
+        }
+    }
 
-            libretro::retro_set_audio_sample(Some(my_audio_sample));
+    /// Read and write `game.srm`, or leave the save RAM of the game as the core powers it on.
+    pub fn set_sram_files(&self, enabled: bool) {
+        unsafe { SRAM_FILES.set(enabled) }
+    }
 
-            libretro::retro_set_audio_sample_batch(Some(my_audio_sample_batch));
+    /// Write the save RAM of the game to `game.srm`.
+    pub fn save_sram(&self) {
+        let (Some(sram), Some(path)) = (self.sram(), self.save_path("srm")) else {
+            return;
+        };
+        if unsafe { !SRAM_FILES.get() } {
+            return;
+        }
+        if unsafe { SRAM_FROM_MOVIE.get() } {
+            println!(
+                "the save RAM comes from a movie, {} is left as is",
+                path.display()
+            );
+            return;
+        }
 
-            libretro::retro_set_input_state(Some(my_input_state));
+        if let Err(err) = std::fs::write(&path, sram) {
+            self.notify(format!("could not save {}: {}", path.display(), err));
+        }
+    }
 
-            libretro::retro_set_input_poll(Some(my_input_poll));
+    /// Bring the save RAM of the game back from `game.srm`.
+    fn load_sram(&self) {
+        let (Some(sram), Some(path)) = (self.sram(), self.save_path("srm")) else {
+            return;
+        };
+        if unsafe { !SRAM_FILES.get() } {
+            return;
+        }
+        let Ok(data) = std::fs::read(&path) else {
+            return;
+        };
 
-            libretro::retro_init();
+        if data.len() != sram.len() {
+            println!(
+                "{} holds {} bytes but the game has {}, loading what fits",
+                path.display(),
+                data.len(),
+                sram.len()
+            );
         }
+
+        let len = data.len().min(sram.len());
+        sram[..len].copy_from_slice(&data[..len]);
     }
 
-    pub fn load_rom(&self, rom: &str) {
-        let mut file = std::fs::File::open(rom).expect("could not open the rom's file");
-        let size = file.metadata().unwrap().len() as usize;
+    /// Load a ROM into the core, applying soft-patches to it first.
+    ///
+    /// When `patches` is empty, IPS/UPS/BPS files with the same stem as the ROM are used instead.
+    /// Patches are applied in order, each one on top of the result of the previous. Nothing is
+    /// loaded when the ROM can't be read or a patch doesn't apply.
+    pub fn load_rom(&self, rom: &str, patches: &[String]) -> Result<(), String> {
+        let mut buffer = Vec::new();
+        std::fs::File::open(rom)
+            .and_then(|mut file| file.read_to_end(&mut buffer))
+            .map_err(|err| format!("could not read {}: {}", rom, err))?;
+
+        let patches: Vec<PathBuf> = if patches.is_empty() {
+            patch::find_patches(Path::new(rom))
+        } else {
+            patches.iter().map(PathBuf::from).collect()
+        };
+
+        for path in &patches {
+            buffer = patch::apply_file(path, &buffer)
+                .map_err(|err| format!("could not apply the patch {}: {}", path.display(), err))?;
+
+            println!("applied patch {}", path.display());
+        }
+
+        let path =
+            CString::new(rom).map_err(|_| String::from("the rom's path contains a nul byte"))?;
+
+        // The core keeps a single game, the one before goes with its saves.
+        self.unload_game();
 
-        let mut buffer = Vec::with_capacity(size);
-        file.read_to_end(&mut buffer)
-            .expect("could not read the rom's file");
+        let api = core::api().map_err(|err| err.to_string())?;
 
         let info = libretro::retro_game_info {
-            path: rom.as_ptr() as *const libc::c_char,
+            path: path.as_ptr(),
             data: buffer.as_ptr() as *const libc::c_void,
-            size,
+            size: buffer.len(),
             meta: std::ptr::null(),
         };
 
         unsafe {
-            if !libretro::retro_load_game(&info) {
-                return;
+            if !(api.retro_load_game)(&info) {
+                return Err(String::from("the core could not load the game"));
             }
+
+            CONTENT.set(Some(Content {
+                path: PathBuf::from(rom),
+                crc32: hash::crc32(&buffer),
+                patched: !patches.is_empty(),
+                data: buffer,
+            }));
+
+            let mut info: libretro::retro_system_av_info = std::mem::zeroed();
+            (api.retro_get_system_av_info)(&mut info);
+            TIMING.set((info.timing.fps, info.timing.sample_rate));
         }
-    }
 
-    pub fn save_state(&self) {
+        // Bring back the cheats saved for this game, along with whether they were enabled.
+        let cheats = match self.save_path("cht").filter(|path| path.is_file()) {
+            Some(path) => cheats::load_cht(&path).unwrap_or_else(|err| {
+                self.notify(format!("could not read {}: {}", path.display(), err));
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+
         unsafe {
-            let size = libretro::retro_serialize_size();
-            let mut buffer: Vec<u8> = vec![0; size];
+            CHEATS.set(cheats);
+        }
 
-            if !libretro::retro_serialize(buffer.as_mut_ptr() as *mut std::ffi::c_void, size) {
-                return;
+        self.apply_cheats();
+        self.load_sram();
+
+        // And the options selected for it, which the core reads on its next frame.
+        if let Some(path) = self.save_path("opt") {
+            unsafe {
+                if options::load(OPTIONS.get_mut(), &path) {
+                    OPTIONS_UPDATED.set(true);
+                }
             }
+        }
+
+        Ok(())
+    }
 
-            let mut file =
-                std::fs::File::create("save_state").expect("could not create the save state");
-            file.write_all(&buffer).unwrap();
+    /// Reset the game, like pressing the console's reset button.
+    pub fn reset(&self) {
+        let Ok(api) = core::api() else {
+            return;
+        };
+
+        unsafe {
+            (api.retro_reset)();
         }
+
+        self.apply_cheats();
     }
 
-    pub fn load_state(&self) {
-        let mut file =
-            std::fs::File::open("save_state").expect("could not open the save state file");
-        let size = file.metadata().unwrap().len() as usize;
+    pub fn cheats(&self) -> Vec<cheats::Cheat> {
+        unsafe { CHEATS.get_mut().clone() }
+    }
+
+    pub fn cheat(&self, index: usize) -> Option<cheats::Cheat> {
+        unsafe { CHEATS.get_mut().get(index).cloned() }
+    }
 
-        let mut buffer = Vec::with_capacity(size);
-        file.read_to_end(&mut buffer).unwrap();
+    pub fn cheat_count(&self) -> usize {
+        unsafe { CHEATS.get_mut().len() }
+    }
+
+    /// Add the cheats of a RetroArch `.cht` file to the game's cheats.
+    ///
+    /// Codes the game already has are skipped, so loading the same file twice is harmless.
+    pub fn load_cheats(&self, path: &str) -> Result<(), String> {
+        let loaded = cheats::load_cht(Path::new(path)).map_err(|err| err.to_string())?;
 
         unsafe {
-            if !libretro::retro_unserialize(buffer.as_mut_ptr() as *mut std::ffi::c_void, size) {
-                return;
+            let cheats = CHEATS.get_mut();
+            for cheat in loaded {
+                if !cheats.iter().any(|known| known.code == cheat.code) {
+                    cheats.push(cheat);
+                }
             }
         }
+
+        self.cheats_changed();
+
+        Ok(())
     }
 
-    pub fn push_buttons(&self, buttons: Vec<i16>) {
+    /// Add a single GameShark, Action Replay or CodeBreaker code, enabled.
+    pub fn add_cheat(&self, description: &str, code: &str) {
+        let cheat = cheats::Cheat::new(description, code, true);
+
         unsafe {
-            BUTTONS_PRESSED.set(Some(buttons));
+            let cheats = CHEATS.get_mut();
+            match cheats.iter_mut().find(|known| known.code == cheat.code) {
+                Some(known) => known.enabled = true,
+                None => cheats.push(cheat),
+            }
         }
+
+        self.cheats_changed();
     }
 
-    pub fn run(&self) -> (Option<Vec<u32>>, Option<(i16, i16)>) {
-        let video;
+    /// Enable or disable a cheat, returning whether it is now enabled.
+    pub fn toggle_cheat(&self, index: usize) -> Option<bool> {
+        let enabled = unsafe {
+            let cheat = CHEATS.get_mut().get_mut(index)?;
+            cheat.enabled = !cheat.enabled;
+            cheat.enabled
+        };
+
+        self.cheats_changed();
+
+        Some(enabled)
+    }
 
+    /// Apply the cheats and save them next to the game.
+    fn cheats_changed(&self) {
+        self.apply_cheats();
+
+        if let Some(path) = self.save_path("cht") {
+            let cheats = unsafe { CHEATS.get_mut() };
+            if let Err(err) = cheats::save_cht(&path, cheats) {
+                self.notify(format!("could not save {}: {}", path.display(), err));
+            }
+        }
+    }
+
+    fn apply_cheats(&self) {
         unsafe {
-            libretro::retro_run();
-            video = VIDEO_FRAME_DATA.replace(None);
+            cheats::apply(CHEATS.get_mut());
         }
+    }
 
-        return (video, None);
+    /// Path and CRC-32 of the loaded content.
+    pub fn content(&self) -> Option<(PathBuf, u32)> {
+        unsafe {
+            CONTENT
+                .get_mut()
+                .as_ref()
+                .map(|content| (content.path.clone(), content.crc32))
+        }
+    }
+
+    /// Path of a save file for the loaded content, or `None` when nothing is loaded.
+    pub fn save_path(&self, extension: &str) -> Option<PathBuf> {
+        unsafe {
+            CONTENT
+                .get_mut()
+                .as_ref()
+                .map(|content| content.save_path(extension))
+        }
+    }
+
+    /// Snapshot the state of the core with `retro_serialize`.
+    ///
+    /// The size is asked for every time, some cores change it as the game runs.
+    fn serialize(&self) -> Result<Vec<u8>, &'static str> {
+        if self.content().is_none() {
+            return Err("no game is loaded");
+        }
+        let api = core::api().map_err(|_| "no core is loaded")?;
+
+        if self.before_first_frame() {
+            return Err("the core can only save its state once the game has run");
+        }
+
+        unsafe {
+            let size = (api.retro_serialize_size)();
+            let mut buffer: Vec<u8> = vec![0; size];
+
+            if !(api.retro_serialize)(buffer.as_mut_ptr() as *mut std::ffi::c_void, size) {
+                return Err("the core could not serialize its state");
+            }
+
+            Ok(buffer)
+        }
+    }
+
+    fn has_quirk(&self, quirk: u32) -> bool {
+        unsafe { SERIALIZATION_QUIRKS.get() & quirk as u64 != 0 }
+    }
+
+    /// Whether the core can't serialize yet, being one that needs a frame to run first.
+    fn before_first_frame(&self) -> bool {
+        self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_MUST_INITIALIZE)
+            && self.frame_count() == 0
+    }
+
+    /// Whether the core's states only load where they were saved.
+    fn origin_bound(&self) -> bool {
+        self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_SINGLE_SESSION)
+            || self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_ENDIAN_DEPENDENT)
+            || self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_PLATFORM_DEPENDENT)
+    }
+
+    /// Where states saved now come from.
+    fn state_origin(&self) -> state::Origin {
+        let session = unsafe {
+            if SESSION_ID.get() == 0 {
+                let nanos = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|duration| duration.as_nanos() as u64)
+                    .unwrap_or(0);
+                SESSION_ID.set((nanos ^ (std::process::id() as u64) << 32) | 1);
+            }
+
+            SESSION_ID.get()
+        };
+
+        state::Origin::current(session)
+    }
+
+    /// Restore a snapshot made by `serialize`, and the cheats the core forgets when doing so.
+    fn unserialize(&self, buffer: &[u8]) -> bool {
+        if self.content().is_none() {
+            return false;
+        }
+        let Ok(api) = core::api() else {
+            return false;
+        };
+
+        unsafe {
+            if !(api.retro_unserialize)(buffer.as_ptr() as *const std::ffi::c_void, buffer.len()) {
+                return false;
+            }
+        }
+
+        self.apply_cheats();
+
+        true
+    }
+
+    /// Write the state of the core to `path`.
+    pub fn write_state(&self, path: &Path) -> std::io::Result<()> {
+        let error = |message: String| std::io::Error::new(std::io::ErrorKind::Other, message);
+
+        let Some(header) = self.state_header() else {
+            return Err(error(String::from("no game is loaded")));
+        };
+        let data = self
+            .serialize()
+            .map_err(|message| error(message.to_string()))?;
+
+        let state = state::State {
+            header,
+            data,
+            origin: Some(self.state_origin()),
+        };
+        let bytes = state::encode(&state, state::Compression::Deflate)
+            .map_err(|err| error(err.to_string()))?;
+
+        std::fs::File::create(path)?.write_all(&bytes)
+    }
+
+    /// The data to hand to `retro_unserialize` from a state file, after checking it belongs to
+    /// the core and game running.
+    ///
+    /// Files without a header are raw `retro_serialize` blobs, as written before states had
+    /// one, and are taken as they are.
+    fn read_state(&self, path: &Path, bytes: Vec<u8>) -> Result<Vec<u8>, state::StateError> {
+        if !state::is_state(&bytes) {
+            println!(
+                "{} has no header, loading it as a raw state",
+                path.display()
+            );
+            // Without a header nothing tells where the state comes from.
+            if self.origin_bound() {
+                return Err(state::StateError::UnknownOrigin);
+            }
+
+            return Ok(bytes);
+        }
+
+        let state = state::decode(&bytes)?;
+        self.check_state(&state.header, &state.data, state.origin.as_ref())?;
+
+        Ok(state.data)
+    }
+
+    /// Select the slot of `save_state` and `load_state`.
+    pub fn set_state_slot(&self, slot: u32) {
+        unsafe { STATE_SLOT.set(slot) }
+    }
+
+    pub fn state_slot(&self) -> u32 {
+        unsafe { STATE_SLOT.get() }
+    }
+
+    /// The state file of the selected slot, `game.state` for slot 0 and `game.stateN` after.
+    fn state_path(&self) -> Option<PathBuf> {
+        match self.state_slot() {
+            0 => self.save_path("state"),
+            slot => self.save_path(&format!("state{}", slot)),
+        }
+    }
+
+    pub fn save_state(&self) {
+        let Some(path) = self.state_path() else {
+            return;
+        };
+
+        // Keep the movie as it is at this state, so loading the state can seek or branch in it.
+        // A state without its movie would load as if no movie was running, the movie is written
+        // first and the state skipped when it can't be.
+        let movie_path = state_movie_path(&path);
+        if let Some(movie) = unsafe { MOVIE.get_mut() } {
+            if let Err(err) = movie.save(&movie_path) {
+                self.notify(format!(
+                    "could not save the state: could not write {}: {}",
+                    movie_path.display(),
+                    err
+                ));
+                return;
+            }
+        }
+
+        if let Err(err) = self.write_state(&path) {
+            if unsafe { MOVIE.get_mut().is_some() } {
+                let _ = std::fs::remove_file(&movie_path);
+            }
+            self.notify(format!("could not save the state: {}", err));
+            return;
+        }
+
+        if let Some(movie) = unsafe { MOVIE.get_mut() } {
+            self.save_movie(movie);
+        }
+
+        self.notify("state saved");
+    }
+
+    pub fn load_state(&self) {
+        let Some(path) = self.state_path() else {
+            return;
+        };
+
+        // An empty slot isn't an error, the menu can go through all of them.
+        let Ok(buffer) = std::fs::read(&path) else {
+            self.notify(format!("no state in slot {}", self.state_slot()));
+            return;
+        };
+
+        let data = match self.read_state(&path, buffer) {
+            Ok(data) => data,
+            Err(err) => {
+                self.notify(format!("could not load the state: {}", err));
+                return;
+            }
+        };
+
+        if !self.unserialize(&data) {
+            self.notify("could not load the state: the core refused it");
+            return;
+        }
+        self.notify("state loaded");
+
+        let Some(movie) = (unsafe { MOVIE.get_mut() }) else {
+            return;
+        };
+
+        match movie::Movie::load(&state_movie_path(&path), true) {
+            Ok(snapshot) => movie.restore(snapshot),
+            Err(err) => {
+                println!(
+                    "the state doesn't belong to the movie ({}), stopping it",
+                    err
+                );
+                self.stop_movie();
+            }
+        }
+    }
+
+    /// The state written automatically, `game.state.auto`.
+    fn auto_state_path(&self) -> Option<PathBuf> {
+        self.save_path("state.auto")
+    }
+
+    /// What states saved now are checked against, `None` without a game.
+    fn state_header(&self) -> Option<state::Header> {
+        let (_, rom_crc32) = self.content()?;
+        let (core_name, core_version) = self.core_info();
+
+        let size = core::api()
+            .map(|api| unsafe { (api.retro_serialize_size)() })
+            .unwrap_or(0);
+
+        Some(state::Header {
+            core_name,
+            core_version,
+            rom_crc32,
+            size,
+        })
+    }
+
+    /// Refuse states of other cores and ROMs, and ones the core can't take whole.
+    fn check_state(
+        &self,
+        header: &state::Header,
+        data: &[u8],
+        origin: Option<&state::Origin>,
+    ) -> Result<(), state::StateError> {
+        let Some(current) = self.state_header() else {
+            return Ok(());
+        };
+
+        if header.core_name != current.core_name {
+            return Err(state::StateError::OtherCore(header.core_name.clone()));
+        }
+        if header.rom_crc32 != current.rom_crc32 {
+            return Err(state::StateError::OtherRom(header.rom_crc32));
+        }
+
+        if self.origin_bound() {
+            let Some(origin) = origin else {
+                return Err(state::StateError::UnknownOrigin);
+            };
+            let here = self.state_origin();
+
+            if self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_SINGLE_SESSION)
+                && origin.session != here.session
+            {
+                return Err(state::StateError::OtherSession);
+            }
+            if self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_ENDIAN_DEPENDENT)
+                && origin.big_endian != here.big_endian
+            {
+                return Err(state::StateError::OtherPlatform(origin.platform.clone()));
+            }
+            if self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_PLATFORM_DEPENDENT)
+                && origin.platform != here.platform
+            {
+                return Err(state::StateError::OtherPlatform(origin.platform.clone()));
+            }
+        }
+
+        // Cores whose states change size as the game runs can restore smaller or larger ones.
+        if data.len() != current.size
+            && !self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_CORE_VARIABLE_SIZE)
+        {
+            return Err(state::StateError::WrongSize {
+                expected: current.size,
+                found: data.len(),
+            });
+        }
+
+        // Cores usually keep their states across versions, it's only worth a word.
+        if header.core_version != current.core_version {
+            println!(
+                "the state was saved by {} {}, loading it into {}",
+                header.core_name, header.core_version, current.core_version
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Write the auto state, which is restored when the game is launched again.
+    ///
+    /// Cores whose states don't outlive the front-end have none, and for cores that can only
+    /// save once the game runs it's written after the first frame.
+    pub fn save_auto_state(&self) {
+        if self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_SINGLE_SESSION) {
+            return;
+        }
+        if self.before_first_frame() {
+            unsafe { AUTO_STATE_SAVE_QUEUED.set(true) };
+            return;
+        }
+
+        let Some(path) = self.auto_state_path() else {
+            return;
+        };
+
+        if let Err(err) = self.write_state(&path) {
+            println!("could not write the auto state: {}", err);
+        }
+    }
+
+    /// Resume the game from its auto state, returning whether it had one that loaded.
+    ///
+    /// States that are damaged or belong to another core or ROM are skipped with a message. For
+    /// cores that can only load once the game runs, it's loaded after the first frame and this
+    /// tells whether there is one.
+    pub fn load_auto_state(&self) -> bool {
+        // A game played for the first time has none, that's not worth a message.
+        let Some(path) = self.auto_state_path() else {
+            return false;
+        };
+        if self.before_first_frame() {
+            unsafe { AUTO_STATE_LOAD_QUEUED.set(path.is_file()) };
+            return path.is_file();
+        }
+        let Ok(bytes) = std::fs::read(&path) else {
+            return false;
+        };
+
+        let data = match self.read_state(&path, bytes) {
+            Ok(data) => data,
+            Err(err) => {
+                self.notify(format!("skipping the auto state: {}", err));
+                return false;
+            }
+        };
+
+        if !self.unserialize(&data) {
+            self.notify("skipping the auto state: the core refused it");
+            return false;
+        }
+
+        self.notify("resumed from the auto state");
+        true
+    }
+
+    /// The options of the core, with their selected values.
+    pub fn core_options(&self) -> Vec<options::CoreOption> {
+        unsafe { OPTIONS.get_mut().clone() }
+    }
+
+    /// Select the value at `value` of the option at `index`, saving the options of the game.
+    pub fn set_core_option(&self, index: usize, value: usize) {
+        let options = unsafe { OPTIONS.get_mut() };
+        let Some(option) = options.get_mut(index) else {
+            return;
+        };
+        if value >= option.values.len() {
+            return;
+        }
+
+        option.selected = value;
+        unsafe { OPTIONS_UPDATED.set(true) };
+
+        if let Some(path) = self.save_path("opt") {
+            if let Err(err) = options::save(options, &path) {
+                self.notify(format!("could not write {}: {}", path.display(), err));
+            }
+        }
+    }
+
+    /// Show `message` on screen.
+    pub fn show_message(&self, message: message::Message) {
+        unsafe { MESSAGES.get_mut().push(message) }
+    }
+
+    /// Tell the user about `text`, in the terminal and on screen.
+    pub fn notify(&self, text: impl Into<String>) {
+        let text = text.into();
+
+        println!("{}", text);
+        self.show_message(message::Message::notification(text));
+    }
+
+    /// The messages sent since the last call, oldest first.
+    pub fn take_messages(&self) -> Vec<message::Message> {
+        unsafe { std::mem::take(MESSAGES.get_mut()) }
+    }
+
+    /// Frames per second and audio sample rate of the core, from `retro_get_system_av_info` when
+    /// the game was loaded.
+    ///
+    /// Both are zero when no game is loaded.
+    pub fn timing(&self) -> (f64, f64) {
+        unsafe { TIMING.get() }
+    }
+
+    /// Correct the colors of the frames with `profile`, for a display of the given gamma.
+    pub fn set_color_profile(&self, profile: color::ColorProfile, gamma: f32) {
+        unsafe {
+            COLOR_PROFILE.set((profile, gamma));
+        }
+        self.update_converter();
+    }
+
+    /// The selected color profile and display gamma.
+    pub fn color_profile(&self) -> (color::ColorProfile, f32) {
+        unsafe { COLOR_PROFILE.get() }
+    }
+
+    /// Convert the frames to `format` from the next one on, RGBA8 unless changed.
+    pub fn set_pixel_format(&self, format: PixelFormat) {
+        unsafe {
+            PIXEL_FORMAT.set(format);
+        }
+        self.update_converter();
+    }
+
+    /// The layout of the pixels of `frame` and `last_frame`.
+    pub fn pixel_format(&self) -> PixelFormat {
+        unsafe { PIXEL_FORMAT.get() }
+    }
+
+    fn update_converter(&self) {
+        let (profile, gamma) = self.color_profile();
+        let table = profile.table(gamma);
+
+        unsafe {
+            CONVERTER.set(Some(convert::Converter::new(
+                PIXEL_FORMAT.get(),
+                table.as_deref(),
+            )));
+        }
+    }
+
+    /// Rotate the screen by `rotation` whatever the core asks for, or follow the core again with
+    /// `None`.
+    pub fn set_rotation(&self, rotation: Option<rotation::Rotation>) {
+        unsafe { FORCED_ROTATION.set(rotation) }
+    }
+
+    /// How the frames should be rotated when shown.
+    pub fn rotation(&self) -> rotation::Rotation {
+        unsafe { FORCED_ROTATION.get().unwrap_or(ROTATION.get()) }
+    }
+
+    /// Move the pointer to `position` on the shown screen, in libretro's pointer coordinates,
+    /// or take it off the screen with `None`.
+    pub fn set_pointer(&self, position: Option<(i16, i16)>, pressed: bool) {
+        let rotation = self.rotation();
+        let position = position.map(|(x, y)| rotation.to_core(x, y));
+
+        unsafe { POINTER.set((position, pressed)) }
+    }
+
+    /// Width and height of the last frame the core sent.
+    pub fn frame_size(&self) -> (usize, usize) {
+        unsafe { VIDEO_FRAME_SIZE.get() }
+    }
+
+    /// The last frame the core sent, whether it was during the last `run` or before.
+    pub fn frame(&self) -> Option<&'static [u32]> {
+        unsafe {
+            let frame = VIDEO_FRAME_DATA.get_mut();
+
+            (!frame.is_empty()).then_some(frame.as_slice())
+        }
+    }
+
+    /// A copy of the last frame the core sent, with its width and height.
+    pub fn last_frame(&self) -> Option<(Vec<u32>, usize, usize)> {
+        let (width, height) = self.frame_size();
+
+        self.frame().map(|frame| (frame.to_vec(), width, height))
+    }
+
+    /// How many frames ran since the content was loaded.
+    pub fn frame_count(&self) -> u64 {
+        unsafe { FRAME_COUNT.get() }
+    }
+
+    /// Start or stop keeping the audio the core outputs, to be taken with `take_audio`.
+    ///
+    /// Every `true` is matched by a `false` of the same consumer, a video recording and a WAV
+    /// file can take the audio at once and the capture goes on until both stopped.
+    pub fn capture_audio(&self, enabled: bool) {
+        unsafe {
+            let captures = if enabled {
+                AUDIO_CAPTURES.get() + 1
+            } else {
+                AUDIO_CAPTURES.get().saturating_sub(1)
+            };
+            AUDIO_CAPTURES.set(captures);
+
+            if captures == 0 {
+                AUDIO_FRAME_DATA.set(None);
+            } else if AUDIO_FRAME_DATA.get_mut().is_none() {
+                AUDIO_FRAME_DATA.set(Some(Vec::new()));
+            }
+        }
+    }
+
+    /// Interleaved stereo samples output since the last call, when audio is captured.
+    pub fn take_audio(&self) -> Vec<i16> {
+        unsafe {
+            match AUDIO_FRAME_DATA.get_mut() {
+                Some(samples) => std::mem::take(samples),
+                None => Vec::new(),
+            }
+        }
+    }
+
+    /// Name and version of the core, from `retro_get_system_info`.
+    pub fn core_info(&self) -> (String, String) {
+        unsafe {
+            let mut info: libretro::retro_system_info = std::mem::zeroed();
+            if let Ok(api) = core::api() {
+                (api.retro_get_system_info)(&mut info);
+            }
+
+            let text = |ptr: *const libc::c_char| {
+                if ptr.is_null() {
+                    String::new()
+                } else {
+                    std::ffi::CStr::from_ptr(ptr).to_string_lossy().into_owned()
+                }
+            };
+
+            (text(info.library_name), text(info.library_version))
+        }
+    }
+
+    /// Start recording an input movie.
+    ///
+    /// The movie starts from power-on, which resets the game, or from the current state when
+    /// `from_state` is set, in which case the state is embedded in the movie. Games read their
+    /// save RAM when they boot, so movies from power-on keep it too.
+    pub fn record_movie(&self, path: &str, from_state: bool) -> Result<(), String> {
+        let Some(rom_crc32) = (unsafe { CONTENT.get_mut() }).as_ref().map(|c| c.crc32) else {
+            return Err(String::from("no game is loaded"));
+        };
+
+        let (start_state, start_sram) = if from_state {
+            (Some(self.serialize()?), None)
+        } else {
+            (None, self.sram().map(|sram| sram.to_vec()))
+        };
+
+        self.stop_movie();
+        if !from_state {
+            self.reset();
+        }
+
+        let (core_name, core_version) = self.core_info();
+        let movie = movie::Movie::new(
+            Path::new(path),
+            rom_crc32,
+            &core_name,
+            &core_version,
+            start_state,
+            start_sram,
+        );
+
+        unsafe {
+            MOVIE.set(Some(movie));
+        }
+
+        Ok(())
+    }
+
+    /// Play an input movie back from its start.
+    pub fn play_movie(&self, path: &str, read_only: bool) -> Result<(), String> {
+        let mut movie =
+            movie::Movie::load(Path::new(path), read_only).map_err(|err| err.to_string())?;
+
+        if let Some(content) = unsafe { CONTENT.get_mut() } {
+            if content.crc32 != movie.rom_crc32 {
+                return Err(format!(
+                    "the movie was recorded with the rom {:08x}, but {:08x} is loaded",
+                    movie.rom_crc32, content.crc32
+                ));
+            }
+        }
+
+        let (core_name, core_version) = self.core_info();
+        if core_name != movie.core_name || core_version != movie.core_version {
+            println!(
+                "the movie was recorded with {} {}, it may desync on {} {}",
+                movie.core_name, movie.core_version, core_name, core_version
+            );
+        }
+
+        self.stop_movie();
+
+        match &movie.start_state {
+            Some(state) => {
+                if !self.unserialize(state) {
+                    return Err(String::from("the core refused the movie's starting state"));
+                }
+            }
+            None => {
+                // The game boots with the save RAM it had when the movie was recorded.
+                if let (Some(sram), Some(recorded)) = (self.sram(), &movie.start_sram) {
+                    if sram.len() != recorded.len() {
+                        return Err(String::from("the movie's save RAM doesn't fit the game's"));
+                    }
+
+                    sram.copy_from_slice(recorded);
+                    unsafe { SRAM_FROM_MOVIE.set(true) };
+                    self.notify("the movie's save RAM won't be saved over the game's");
+                }
+
+                self.reset();
+            }
+        }
+
+        movie.rewind();
+
+        unsafe {
+            MOVIE.set(Some(movie));
+        }
+
+        Ok(())
+    }
+
+    /// Switch the movie being played between read-only and read-write.
+    pub fn set_movie_read_only(&self, read_only: bool) {
+        if let Some(movie) = unsafe { MOVIE.get_mut() } {
+            if let movie::MovieMode::Playing { .. } = movie.mode {
+                movie.mode = movie::MovieMode::Playing { read_only };
+            }
+        }
+    }
+
+    /// Mode, current frame, length and rerecord count of the active movie.
+    pub fn movie_status(&self) -> Option<(movie::MovieMode, usize, usize, u32)> {
+        unsafe { MOVIE.get_mut() }
+            .as_ref()
+            .map(|movie| (movie.mode, movie.frame(), movie.len(), movie.rerecords))
+    }
+
+    /// Stop the movie, writing it to disk unless it was only played back.
+    pub fn stop_movie(&self) {
+        if let Some(movie) = unsafe { MOVIE.take() } {
+            self.save_movie(&movie);
+        }
+    }
+
+    fn save_movie(&self, movie: &movie::Movie) {
+        if movie.mode == (movie::MovieMode::Playing { read_only: true }) {
+            return;
+        }
+
+        if let Err(err) = movie.save(&movie.path) {
+            self.notify(format!("could not save {}: {}", movie.path.display(), err));
+        }
+    }
+
+    /// Keep writing a value to guest memory on every frame, replacing any freeze at that address.
+    pub fn freeze(&self, freeze: search::Freeze) {
+        unsafe {
+            let freezes = FREEZES.get_mut();
+            freezes.retain(|known| known.address != freeze.address);
+            freezes.push(freeze);
+        }
+    }
+
+    pub fn unfreeze(&self, address: usize) {
+        unsafe {
+            FREEZES.get_mut().retain(|known| known.address != address);
+        }
+    }
+
+    pub fn freezes(&self) -> Vec<search::Freeze> {
+        unsafe { FREEZES.get_mut().clone() }
+    }
+
+    /// Write a value to guest memory once.
+    pub fn poke(
+        &self,
+        address: usize,
+        value: u32,
+        width: search::Width,
+        endian: search::Endian,
+    ) -> Result<(), memory::MemoryError> {
+        self.write_memory(address, &search::encode(value, width, endian))
+    }
+
+    /// Read guest memory at an address of the emulated address space.
+    pub fn read_memory(&self, address: usize, len: usize) -> Result<Vec<u8>, memory::MemoryError> {
+        memory::read(address, len)
+    }
+
+    /// Write guest memory at an address of the emulated address space.
+    pub fn write_memory(&self, address: usize, bytes: &[u8]) -> Result<(), memory::MemoryError> {
+        memory::write(address, bytes)
+    }
+
+    /// Read a value in the byte order of the memory holding it, the way the guest CPU would.
+    pub fn read_value(
+        &self,
+        address: usize,
+        width: search::Width,
+    ) -> Result<u32, memory::MemoryError> {
+        memory::address_space().read_value(address, width.bytes())
+    }
+
+    pub fn push_buttons(&self, buttons: Vec<i16>) {
+        unsafe {
+            BUTTONS_PRESSED.set(Some(buttons));
+        }
+    }
+
+    /// Run a frame of the core, returning the frame it sent, if it sent one. A duplicated frame
+    /// is returned as the last one again.
+    ///
+    /// The frame lives in a buffer the next call writes over. Nothing runs without a game.
+    pub fn run(&self) -> (Option<&'static [u32]>, Option<(i16, i16)>) {
+        let video;
+
+        let (Some(_), Ok(api)) = (self.content(), core::api()) else {
+            return (None, None);
+        };
+
+        unsafe {
+            let mut shown = self.run_frame(api, true);
+
+            FRAME_COUNT.set(FRAME_COUNT.get() + 1);
+
+            if let Some(movie) = MOVIE.get_mut() {
+                if movie.end_frame() {
+                    println!("movie finished after {} frames", movie.len());
+                }
+            }
+
+            // The core can serialize now, for the ones that needed a frame first.
+            if AUTO_STATE_LOAD_QUEUED.replace(false) {
+                self.load_auto_state();
+            }
+            if AUTO_STATE_SAVE_QUEUED.replace(false) {
+                self.save_auto_state();
+            }
+
+            if let Some((frames, second_instance)) = self.frames_ahead() {
+                match self.run_ahead(api, frames, second_instance) {
+                    Ok(new) => shown |= new,
+                    Err(message) => {
+                        self.notify(format!("run-ahead is off: {}", message));
+                        self.set_run_ahead(0, false);
+                    }
+                }
+            }
+
+            video = shown.then(|| VIDEO_FRAME_DATA.get_mut().as_slice());
+        }
+
+        return (video, None);
+    }
+
+    /// Run a frame of `api`, returning whether it drew a new one.
+    unsafe fn run_frame(&self, api: core::Api, freezes: bool) -> bool {
+        if freezes {
+            for freeze in FREEZES.get_mut().iter() {
+                let _ = freeze.apply();
+            }
+        }
+
+        VIDEO_FRAME_NEW.set(false);
+        (api.retro_run)();
+
+        VIDEO_FRAME_NEW.get()
+    }
+
+    /// Run `frames` frames ahead of `run` with the same input, to show the last one and cut the
+    /// lag of games that react a few frames after the input. Hidden frames make no sound.
+    ///
+    /// On the same instance the state is saved after the real frame and loaded back once ahead.
+    /// A second instance gets the state of the first one and runs ahead on its own, which costs
+    /// no load and suits cores that lose something when loading a state, like audio.
+    pub fn set_run_ahead(&self, frames: u32, second_instance: bool) {
+        unsafe { RUN_AHEAD.set((frames, second_instance)) };
+
+        if frames == 0 || !second_instance {
+            self.unload_secondary();
+        }
+    }
+
+    pub fn run_ahead_frames(&self) -> (u32, bool) {
+        unsafe { RUN_AHEAD.get() }
+    }
+
+    /// The frames to run ahead now, if any.
+    ///
+    /// Movies are left alone, their input must be read and written once per frame.
+    fn frames_ahead(&self) -> Option<(u32, bool)> {
+        let (frames, second_instance) = self.run_ahead_frames();
+        if frames == 0 || unsafe { MOVIE.get_mut().is_some() } {
+            return None;
+        }
+
+        if !self.states_complete() {
+            self.notify("run-ahead is off: the core's states are incomplete");
+            self.set_run_ahead(0, false);
+            return None;
+        }
+
+        Some((frames, second_instance))
+    }
+
+    /// Whether the core's states restore everything, which running ahead relies on.
+    pub fn states_complete(&self) -> bool {
+        !self.has_quirk(libretro::RETRO_SERIALIZATION_QUIRK_INCOMPLETE)
+    }
+
+    unsafe fn run_ahead(
+        &self,
+        api: core::Api,
+        frames: u32,
+        second_instance: bool,
+    ) -> Result<bool, String> {
+        let (ahead, context) = if second_instance {
+            // Without a second instance, the first one runs ahead itself.
+            let secondary = match self.secondary() {
+                Ok(secondary) => secondary,
+                Err(message) => {
+                    self.notify(format!("running ahead on the same instance: {}", message));
+                    self.set_run_ahead(frames, false);
+                    return self.run_ahead(api, frames, false);
+                }
+            };
+
+            (
+                secondary,
+                libretro::retro_savestate_context_RETRO_SAVESTATE_CONTEXT_RUNAHEAD_SAME_BINARY,
+            )
+        } else {
+            (
+                api,
+                libretro::retro_savestate_context_RETRO_SAVESTATE_CONTEXT_RUNAHEAD_SAME_INSTANCE,
+            )
+        };
+
+        SAVESTATE_CONTEXT.set(context);
+
+        let result = (|| {
+            let state = RUN_AHEAD_STATE.get_mut();
+            state.resize((api.retro_serialize_size)(), 0);
+
+            if !(api.retro_serialize)(state.as_mut_ptr() as *mut libc::c_void, state.len()) {
+                return Err(String::from("the core could not save its state"));
+            }
+
+            IN_SECONDARY.set(second_instance);
+            if second_instance
+                && !(ahead.retro_unserialize)(state.as_ptr() as *const libc::c_void, state.len())
+            {
+                return Err(String::from("the second instance could not load the state"));
+            }
+
+            RUNNING_AHEAD.set(true);
+
+            let mut shown = false;
+            for frame in 1..=frames {
+                VIDEO_SKIPPED.set(frame < frames);
+                // The second instance's memory isn't the one freezes write to.
+                shown |= self.run_frame(ahead, !second_instance);
+            }
+
+            RUNNING_AHEAD.set(false);
+            VIDEO_SKIPPED.set(false);
+            IN_SECONDARY.set(false);
+
+            if !second_instance
+                && !(api.retro_unserialize)(state.as_ptr() as *const libc::c_void, state.len())
+            {
+                return Err(String::from("the core could not load its state back"));
+            }
+
+            Ok(shown)
+        })();
+
+        RUNNING_AHEAD.set(false);
+        VIDEO_SKIPPED.set(false);
+        IN_SECONDARY.set(false);
+        SAVESTATE_CONTEXT.set(libretro::retro_savestate_context_RETRO_SAVESTATE_CONTEXT_NORMAL);
+
+        result
+    }
+
+    /// The second instance of the core, loaded with the game the first time it's needed.
+    unsafe fn secondary(&self) -> Result<core::Api, String> {
+        if let Ok(api) = core::secondary_api() {
+            return Ok(api);
+        }
+
+        let Some(content) = CONTENT.get_mut().as_ref() else {
+            return Err(String::from("no game is loaded"));
+        };
+
+        core::load_secondary().map_err(|err| err.to_string())?;
+        let api = core::secondary_api().map_err(|err| err.to_string())?;
+
+        (api.retro_set_environment)(Some(my_environment));
+        (api.retro_set_video_refresh)(Some(my_video_refresh));
+        (api.retro_set_audio_sample)(Some(my_audio_sample));
+        (api.retro_set_audio_sample_batch)(Some(my_audio_sample_batch));
+        (api.retro_set_input_state)(Some(my_input_state));
+        (api.retro_set_input_poll)(Some(my_input_poll));
+
+        let path = CString::new(content.path.as_os_str().to_string_lossy().as_bytes())
+            .map_err(|_| String::from("the rom's path contains a nul byte"))?;
+        let info = libretro::retro_game_info {
+            path: path.as_ptr(),
+            data: content.data.as_ptr() as *const libc::c_void,
+            size: content.data.len(),
+            meta: std::ptr::null(),
+        };
+
+        IN_SECONDARY.set(true);
+        (api.retro_init)();
+        let loaded = (api.retro_load_game)(&info);
+        IN_SECONDARY.set(false);
+
+        if !loaded {
+            IN_SECONDARY.set(true);
+            (api.retro_deinit)();
+            IN_SECONDARY.set(false);
+            core::unload_secondary();
+
+            return Err(String::from("it could not load the game"));
+        }
+
+        println!("running ahead on a second instance of the core");
+        Ok(api)
+    }
+
+    /// Unload the game of the second instance of the core, and the instance.
+    fn unload_secondary(&self) {
+        let Ok(api) = core::secondary_api() else {
+            return;
+        };
+
+        unsafe {
+            IN_SECONDARY.set(true);
+            (api.retro_unload_game)();
+            (api.retro_deinit)();
+            IN_SECONDARY.set(false);
+            SECONDARY_OPTIONS_UPDATED.set(false);
+        }
+
+        core::unload_secondary();
     }
 }
@@ -0,0 +1,481 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"ADVMOVIE";
+/// Version 2 added the save RAM of movies starting from power-on.
+const VERSION: u32 = 2;
+
+/// One answer of `my_input_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Input {
+    port: u8,
+    device: u8,
+    index: u8,
+    id: u16,
+    value: i16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieMode {
+    /// Inputs of every frame are appended to the movie.
+    Recording,
+    /// Inputs come from the movie instead of the keyboard.
+    ///
+    /// In read-only mode loading a state only seeks in the movie. In read-write mode it branches:
+    /// the movie is cut at the state and recording goes on from there.
+    Playing { read_only: bool },
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "movie version {} is not supported", version)
+            }
+            MovieError::Truncated => write!(f, "movie file is truncated"),
+            MovieError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for MovieError {
+    fn from(err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            MovieError::Truncated
+        } else {
+            MovieError::Io(err)
+        }
+    }
+}
+
+/// An input movie: every input the core read, frame by frame, from power-on or from a state.
+///
+/// Replaying the inputs from the same starting point reproduces the run exactly, which is what
+/// makes movies useful to share runs and to reproduce bugs.
+pub struct Movie {
+    pub path: PathBuf,
+    pub mode: MovieMode,
+    /// CRC-32 of the content the movie was recorded with.
+    pub rom_crc32: u32,
+    pub core_name: String,
+    pub core_version: String,
+    /// How many times the movie was branched from a state while recording.
+    pub rerecords: u32,
+    /// State the movie starts from, or `None` to start from power-on.
+    pub start_state: Option<Vec<u8>>,
+    /// Save RAM the game powers on with, for movies that start from power-on.
+    pub start_sram: Option<Vec<u8>>,
+    frames: Vec<Vec<Input>>,
+    /// Index of the frame being emulated.
+    frame: usize,
+    /// Inputs of the frame being recorded.
+    pending: Vec<Input>,
+}
+
+impl Movie {
+    pub fn new(
+        path: &Path,
+        rom_crc32: u32,
+        core_name: &str,
+        core_version: &str,
+        start_state: Option<Vec<u8>>,
+        start_sram: Option<Vec<u8>>,
+    ) -> Movie {
+        Movie {
+            path: path.to_path_buf(),
+            mode: MovieMode::Recording,
+            rom_crc32,
+            core_name: core_name.to_string(),
+            core_version: core_version.to_string(),
+            rerecords: 0,
+            start_state,
+            start_sram,
+            frames: Vec::new(),
+            frame: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Go back to the first frame, to play the movie from its start.
+    pub fn rewind(&mut self) {
+        self.frame = 0;
+        self.pending.clear();
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Answer an input query from the movie, or `None` when it has to come from the user.
+    pub fn input(&self, port: u32, device: u32, index: u32, id: u32) -> Option<i16> {
+        let MovieMode::Playing { .. } = self.mode else {
+            return None;
+        };
+
+        let inputs = self.frames.get(self.frame)?;
+        let value = inputs
+            .iter()
+            .find(|input| input.matches(port, device, index, id))
+            .map(|input| input.value)
+            .unwrap_or(0);
+
+        Some(value)
+    }
+
+    /// Remember what the user pressed, when recording.
+    pub fn record(&mut self, port: u32, device: u32, index: u32, id: u32, value: i16) {
+        if self.mode != MovieMode::Recording {
+            return;
+        }
+
+        if self
+            .pending
+            .iter()
+            .any(|input| input.matches(port, device, index, id))
+        {
+            return;
+        }
+
+        self.pending.push(Input {
+            port: port as u8,
+            device: device as u8,
+            index: index as u8,
+            id: id as u16,
+            value,
+        });
+    }
+
+    /// Move on to the next frame, after `retro_run` returned.
+    ///
+    /// Returns true when playback just reached the end of a read-only movie.
+    pub fn end_frame(&mut self) -> bool {
+        match self.mode {
+            MovieMode::Recording => {
+                self.frames.truncate(self.frame);
+                self.frames.push(std::mem::take(&mut self.pending));
+                self.frame += 1;
+
+                false
+            }
+            MovieMode::Playing { read_only } => {
+                self.frame += 1;
+
+                if self.frame != self.frames.len() {
+                    return false;
+                }
+
+                // At the end, a read-write movie keeps going by recording.
+                if !read_only {
+                    self.mode = MovieMode::Recording;
+                }
+
+                read_only
+            }
+        }
+    }
+
+    /// Bring the movie to where a state made while it was active is.
+    ///
+    /// `snapshot` is the movie as it was saved alongside the state. In read-only playback only
+    /// the position changes. Otherwise the movie takes the snapshot's inputs, recording resumes
+    /// from the state and it counts as a rerecord.
+    pub fn restore(&mut self, snapshot: Movie) {
+        self.frame = snapshot.frame;
+        self.pending.clear();
+
+        match self.mode {
+            MovieMode::Playing { read_only: true } => {}
+            _ => {
+                self.frames = snapshot.frames;
+                self.frames.truncate(self.frame);
+                self.rerecords = self.rerecords.max(snapshot.rerecords) + 1;
+                self.mode = MovieMode::Recording;
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MovieError> {
+        std::fs::File::create(path)?.write_all(&self.to_bytes())?;
+
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_crc32.to_le_bytes());
+        write_string(&mut out, &self.core_name);
+        write_string(&mut out, &self.core_version);
+        out.extend_from_slice(&self.rerecords.to_le_bytes());
+        out.extend_from_slice(&(self.frame as u32).to_le_bytes());
+
+        for block in [&self.start_state, &self.start_sram] {
+            match block {
+                Some(block) => {
+                    out.push(1);
+                    out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+                    out.extend_from_slice(block);
+                }
+                None => out.push(0),
+            }
+        }
+
+        out.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for inputs in &self.frames {
+            out.extend_from_slice(&(inputs.len() as u16).to_le_bytes());
+
+            for input in inputs {
+                out.extend_from_slice(&[input.port, input.device, input.index]);
+                out.extend_from_slice(&input.id.to_le_bytes());
+                out.extend_from_slice(&input.value.to_le_bytes());
+            }
+        }
+
+        out
+    }
+
+    /// Load a movie, positioned at the frame it was saved at.
+    pub fn load(path: &Path, read_only: bool) -> Result<Movie, MovieError> {
+        Movie::from_bytes(&std::fs::read(path)?, path, read_only)
+    }
+
+    /// Lengths are checked against what's left of the file, a damaged one can't make it
+    /// allocate more than its own size.
+    fn from_bytes(bytes: &[u8], path: &Path, read_only: bool) -> Result<Movie, MovieError> {
+        let mut file = bytes;
+
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+
+        let version = read_u32(&mut file)?;
+        if version != 1 && version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let rom_crc32 = read_u32(&mut file)?;
+        let core_name = read_string(&mut file)?;
+        let core_version = read_string(&mut file)?;
+        let rerecords = read_u32(&mut file)?;
+        let frame = read_u32(&mut file)? as usize;
+
+        let start_state = read_block(&mut file)?;
+        let start_sram = if version >= 2 {
+            read_block(&mut file)?
+        } else {
+            None
+        };
+
+        // Every frame takes at least the two bytes of its input count.
+        let frame_count = read_u32(&mut file)? as usize;
+        let mut frames = Vec::with_capacity(frame_count.min(file.len() / 2));
+
+        for _ in 0..frame_count {
+            let count = u16::from_le_bytes(read_bytes(&mut file, 2)?.try_into().unwrap());
+            let mut inputs = Vec::with_capacity((count as usize).min(file.len() / 7));
+
+            for _ in 0..count {
+                let bytes = read_bytes(&mut file, 7)?;
+                inputs.push(Input {
+                    port: bytes[0],
+                    device: bytes[1],
+                    index: bytes[2],
+                    id: u16::from_le_bytes([bytes[3], bytes[4]]),
+                    value: i16::from_le_bytes([bytes[5], bytes[6]]),
+                });
+            }
+
+            frames.push(inputs);
+        }
+
+        Ok(Movie {
+            path: path.to_path_buf(),
+            mode: MovieMode::Playing { read_only },
+            rom_crc32,
+            core_name,
+            core_version,
+            rerecords,
+            start_state,
+            start_sram,
+            frames,
+            frame: frame.min(frame_count),
+            pending: Vec::new(),
+        })
+    }
+}
+
+impl Input {
+    fn matches(&self, port: u32, device: u32, index: u32, id: u32) -> bool {
+        self.port as u32 == port
+            && self.device as u32 == device
+            && self.index as u32 == index
+            && self.id as u32 == id
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, text: &str) {
+    out.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn read_bytes(file: &mut &[u8], len: usize) -> Result<Vec<u8>, MovieError> {
+    if len > file.len() {
+        return Err(MovieError::Truncated);
+    }
+
+    let (bytes, rest) = file.split_at(len);
+    *file = rest;
+
+    Ok(bytes.to_vec())
+}
+
+fn read_u32(file: &mut &[u8]) -> Result<u32, MovieError> {
+    Ok(u32::from_le_bytes(read_bytes(file, 4)?.try_into().unwrap()))
+}
+
+/// A block that may be missing: a flag byte, then its length and bytes.
+fn read_block(file: &mut &[u8]) -> Result<Option<Vec<u8>>, MovieError> {
+    match read_bytes(file, 1)?[0] {
+        0 => Ok(None),
+        _ => {
+            let len = read_u32(file)? as usize;
+            Ok(Some(read_bytes(file, len)?))
+        }
+    }
+}
+
+fn read_string(file: &mut &[u8]) -> Result<String, MovieError> {
+    let len = u16::from_le_bytes(read_bytes(file, 2)?.try_into().unwrap());
+
+    Ok(String::from_utf8_lossy(&read_bytes(file, len as usize)?).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded(start_state: Option<Vec<u8>>, start_sram: Option<Vec<u8>>) -> Movie {
+        let mut movie = Movie::new(
+            Path::new("test.mov"),
+            0x1234_5678,
+            "mGBA",
+            "0.10",
+            start_state,
+            start_sram,
+        );
+
+        for frame in 0..5u32 {
+            movie.record(0, 1, 0, frame, frame as i16);
+            movie.record(0, 1, 0, 8, -1);
+            // Only the first answer of a frame is kept.
+            movie.record(0, 1, 0, 8, 7);
+            movie.end_frame();
+        }
+
+        movie
+    }
+
+    #[test]
+    fn round_trips() {
+        let movie = recorded(None, Some(vec![0xAA; 32]));
+        let loaded = Movie::from_bytes(&movie.to_bytes(), &movie.path, true).unwrap();
+
+        assert_eq!(loaded.rom_crc32, 0x1234_5678);
+        assert_eq!(
+            (loaded.core_name.as_str(), loaded.core_version.as_str()),
+            ("mGBA", "0.10")
+        );
+        assert_eq!(loaded.start_state, None);
+        assert_eq!(loaded.start_sram, Some(vec![0xAA; 32]));
+        assert_eq!(loaded.frames, movie.frames);
+        assert_eq!(loaded.frame(), 5);
+
+        let mut loaded = loaded;
+        loaded.rewind();
+        assert_eq!(loaded.input(0, 1, 0, 3), Some(0));
+        assert_eq!(loaded.input(0, 1, 0, 8), Some(-1));
+        loaded.end_frame();
+        assert_eq!(loaded.input(0, 1, 0, 1), Some(1));
+
+        let movie = recorded(Some(vec![1, 2, 3]), None);
+        let loaded = Movie::from_bytes(&movie.to_bytes(), &movie.path, false).unwrap();
+        assert_eq!(loaded.start_state, Some(vec![1, 2, 3]));
+        assert_eq!(loaded.start_sram, None);
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let movie = recorded(Some(vec![9; 4]), None);
+        let mut bytes = movie.to_bytes();
+
+        // Version 1 has no save RAM block, which is the flag byte right after the state.
+        bytes[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let sram_flag = 8 + 4 + 4 + (2 + 4) + (2 + 4) + 4 + 4 + (1 + 4 + 4);
+        assert_eq!(bytes.remove(sram_flag), 0);
+
+        let loaded = Movie::from_bytes(&bytes, &movie.path, true).unwrap();
+        assert_eq!(loaded.start_state, Some(vec![9; 4]));
+        assert_eq!(loaded.frames, movie.frames);
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let bytes = recorded(Some(vec![1, 2, 3]), None).to_bytes();
+        let path = Path::new("test.mov");
+
+        assert!(matches!(
+            Movie::from_bytes(b"NOTAMOVIE", path, true),
+            Err(MovieError::BadMagic)
+        ));
+
+        let mut version = bytes.clone();
+        version[8] = 99;
+        assert!(matches!(
+            Movie::from_bytes(&version, path, true),
+            Err(MovieError::UnsupportedVersion(99))
+        ));
+
+        for len in 0..bytes.len() {
+            assert!(
+                Movie::from_bytes(&bytes[..len], path, true).is_err(),
+                "cut at {}",
+                len
+            );
+        }
+
+        // Lengths far past the end of the file fail instead of being allocated.
+        let state_len = 8 + 4 + 4 + (2 + 4) + (2 + 4) + 4 + 4 + 1;
+        let mut huge = bytes.clone();
+        huge[state_len..state_len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Movie::from_bytes(&huge, path, true),
+            Err(MovieError::Truncated)
+        ));
+
+        let frame_count = bytes.len() - 5 * (2 + 2 * 7) - 4;
+        let mut huge = bytes;
+        huge[frame_count..frame_count + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Movie::from_bytes(&huge, path, true),
+            Err(MovieError::Truncated)
+        ));
+    }
+}
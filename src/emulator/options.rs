@@ -0,0 +1,85 @@
+use std::ffi::{CStr, CString};
+use std::path::Path;
+
+use super::libretro;
+
+/// A setting of the core, declared with `SET_VARIABLES` and read back with `GET_VARIABLE`.
+#[derive(Debug, Clone)]
+pub struct CoreOption {
+    pub key: String,
+    pub description: String,
+    pub values: Vec<CString>,
+    /// Index of the selected value, the first one until changed.
+    pub selected: usize,
+}
+
+impl CoreOption {
+    pub fn value(&self) -> &CStr {
+        &self.values[self.selected]
+    }
+}
+
+/// Read the options of a null terminated array of `retro_variable`.
+///
+/// The value of each variable is its description and its values: `Description; a|b|c`.
+pub unsafe fn parse_variables(mut variable: *const libretro::retro_variable) -> Vec<CoreOption> {
+    let mut options = Vec::new();
+
+    while !(*variable).key.is_null() {
+        let key = CStr::from_ptr((*variable).key)
+            .to_string_lossy()
+            .into_owned();
+        let value = CStr::from_ptr((*variable).value).to_string_lossy();
+
+        if let Some((description, values)) = value.split_once("; ") {
+            options.push(CoreOption {
+                key,
+                description: description.to_string(),
+                values: values
+                    .split('|')
+                    .filter_map(|value| CString::new(value).ok())
+                    .collect(),
+                selected: 0,
+            });
+        }
+
+        variable = variable.add(1);
+    }
+
+    options.retain(|option| !option.values.is_empty());
+    options
+}
+
+/// Select the values saved in `path`, one `key=value` per line. Returns whether any changed.
+pub fn load(options: &mut [CoreOption], path: &Path) -> bool {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return false;
+    };
+
+    let mut changed = false;
+    for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+        let Some(option) = options.iter_mut().find(|option| option.key == key) else {
+            continue;
+        };
+
+        if let Some(index) = option
+            .values
+            .iter()
+            .position(|candidate| candidate.to_bytes() == value.as_bytes())
+        {
+            changed |= index != option.selected;
+            option.selected = index;
+        }
+    }
+
+    changed
+}
+
+pub fn save(options: &[CoreOption], path: &Path) -> std::io::Result<()> {
+    let text: String = options
+        .iter()
+        .map(|option| format!("{}={}\n", option.key, option.value().to_string_lossy()))
+        .collect();
+
+    std::fs::write(path, text)
+}
@@ -0,0 +1,702 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::hash::crc32;
+
+/// Largest ROM a UPS or BPS patch may produce, above any cartridge or disc image, so that a
+/// damaged size is refused rather than allocated.
+const MAX_TARGET: usize = 1 << 30;
+
+/// Soft-patch formats understood by the front-end.
+///
+/// Patches are applied to the ROM buffer in memory before it reaches `retro_load_game`, so the
+/// file on disk is never modified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchFormat {
+    /// Formats in the order they are looked up next to a ROM.
+    const ALL: [PatchFormat; 3] = [PatchFormat::Bps, PatchFormat::Ups, PatchFormat::Ips];
+
+    pub fn from_path(path: &Path) -> Option<PatchFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        // Numbered patches (`game.ips1`, `game.ips2`, ...) share the format of their base extension.
+        let extension = extension.trim_end_matches(|c: char| c.is_ascii_digit());
+
+        match extension {
+            "ips" => Some(PatchFormat::Ips),
+            "ups" => Some(PatchFormat::Ups),
+            "bps" => Some(PatchFormat::Bps),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            PatchFormat::Ips => "ips",
+            PatchFormat::Ups => "ups",
+            PatchFormat::Bps => "bps",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    UnknownFormat,
+    BadMagic,
+    Truncated,
+    OutOfBounds,
+    /// The patch makes a ROM of this size, larger than `MAX_TARGET`.
+    TooLarge(usize),
+    SourceChecksum {
+        expected: u32,
+        actual: u32,
+    },
+    TargetChecksum {
+        expected: u32,
+        actual: u32,
+    },
+    PatchChecksum {
+        expected: u32,
+        actual: u32,
+    },
+    Io(std::io::Error),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "unknown patch format"),
+            PatchError::BadMagic => write!(f, "patch header does not match its format"),
+            PatchError::Truncated => write!(f, "patch is truncated"),
+            PatchError::OutOfBounds => write!(f, "patch reads outside of the rom or its own data"),
+            PatchError::TooLarge(size) => {
+                write!(f, "patch makes a rom of {} bytes, which is too large", size)
+            }
+            PatchError::SourceChecksum { expected, actual } => write!(
+                f,
+                "patch expects a rom with crc32 {:08x}, but got {:08x}",
+                expected, actual
+            ),
+            PatchError::TargetChecksum { expected, actual } => write!(
+                f,
+                "patched rom has crc32 {:08x}, but {:08x} was expected",
+                actual, expected
+            ),
+            PatchError::PatchChecksum { expected, actual } => write!(
+                f,
+                "patch file is corrupted (crc32 {:08x}, expected {:08x})",
+                actual, expected
+            ),
+            PatchError::Io(err) => write!(f, "could not read the patch: {}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for PatchError {
+    fn from(err: std::io::Error) -> Self {
+        PatchError::Io(err)
+    }
+}
+
+/// Find the patches sitting next to a ROM with the same stem.
+///
+/// Like RetroArch, `game.bps`, `game.ups` and `game.ips` are picked up, followed by their numbered
+/// siblings (`game.ips1`, `game.ips2`, ...) so several patches can be stacked in order.
+pub fn find_patches(rom: &Path) -> Vec<PathBuf> {
+    let mut patches = Vec::new();
+
+    for format in PatchFormat::ALL {
+        let first = rom.with_extension(format.extension());
+        if !first.is_file() {
+            continue;
+        }
+
+        patches.push(first);
+
+        for index in 1.. {
+            let next = rom.with_extension(format!("{}{}", format.extension(), index));
+            if !next.is_file() {
+                break;
+            }
+
+            patches.push(next);
+        }
+    }
+
+    patches
+}
+
+/// Read a patch from disk and apply it to `rom`, returning the patched buffer.
+pub fn apply_file(path: &Path, rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let format = PatchFormat::from_path(path).ok_or(PatchError::UnknownFormat)?;
+    let patch = std::fs::read(path)?;
+
+    apply(format, &patch, rom)
+}
+
+pub fn apply(format: PatchFormat, patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match format {
+        PatchFormat::Ips => apply_ips(patch, rom),
+        PatchFormat::Ups => apply_ups(patch, rom),
+        PatchFormat::Bps => apply_bps(patch, rom),
+    }
+}
+
+/// Sequential reader over the bytes of a patch.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, offset: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.offset.checked_add(len).ok_or(PatchError::Truncated)?;
+        let bytes = self
+            .data
+            .get(self.offset..end)
+            .ok_or(PatchError::Truncated)?;
+        self.offset = end;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<usize, PatchError> {
+        let bytes = self.bytes(2)?;
+
+        Ok((bytes[0] as usize) << 8 | bytes[1] as usize)
+    }
+
+    fn u24_be(&mut self) -> Result<usize, PatchError> {
+        let bytes = self.bytes(3)?;
+
+        Ok((bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize)
+    }
+
+    fn u32_le(&mut self) -> Result<u32, PatchError> {
+        let bytes = self.bytes(4)?;
+
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Decode the variable-length integers used by UPS and BPS.
+    ///
+    /// Each byte carries 7 bits, the high bit marks the last byte, and every continuation adds
+    /// one so that there is exactly one encoding per value.
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+
+        loop {
+            let byte = self.byte()?;
+            value = ((byte & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|part| value.checked_add(part))
+                .ok_or(PatchError::OutOfBounds)?;
+
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+
+            shift = shift.checked_shl(7).ok_or(PatchError::OutOfBounds)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+}
+
+/// UPS and BPS end with the source, target and patch CRC-32s, 12 bytes in total.
+struct Footer {
+    source: u32,
+    target: u32,
+}
+
+fn read_footer(patch: &[u8]) -> Result<Footer, PatchError> {
+    if patch.len() < 12 {
+        return Err(PatchError::Truncated);
+    }
+
+    let mut footer = Reader::new(&patch[patch.len() - 12..]);
+    let source = footer.u32_le()?;
+    let target = footer.u32_le()?;
+    let expected = footer.u32_le()?;
+
+    let actual = crc32(&patch[..patch.len() - 4]);
+    if actual != expected {
+        return Err(PatchError::PatchChecksum { expected, actual });
+    }
+
+    Ok(Footer { source, target })
+}
+
+fn check_source(footer: &Footer, rom: &[u8]) -> Result<(), PatchError> {
+    let actual = crc32(rom);
+    if actual != footer.source {
+        return Err(PatchError::SourceChecksum {
+            expected: footer.source,
+            actual,
+        });
+    }
+
+    Ok(())
+}
+
+fn check_target(footer: &Footer, output: &[u8]) -> Result<(), PatchError> {
+    let actual = crc32(output);
+    if actual != footer.target {
+        return Err(PatchError::TargetChecksum {
+            expected: footer.target,
+            actual,
+        });
+    }
+
+    Ok(())
+}
+
+/// IPS: a list of `offset (24 bits) + size (16 bits) + data` records terminated by `EOF`.
+///
+/// A zero size marks an RLE record (`count (16 bits) + value`), and an optional 24 bits after
+/// `EOF` truncate the output. IPS carries no checksums.
+fn apply_ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut reader = Reader::new(patch);
+    if reader.bytes(5)? != b"PATCH" {
+        return Err(PatchError::BadMagic);
+    }
+
+    let mut output = rom.to_vec();
+
+    loop {
+        let offset_bytes = reader.bytes(3)?;
+        if offset_bytes == b"EOF" {
+            break;
+        }
+
+        let offset = (offset_bytes[0] as usize) << 16
+            | (offset_bytes[1] as usize) << 8
+            | offset_bytes[2] as usize;
+        let size = reader.u16_be()?;
+
+        let (len, run) = if size == 0 {
+            let len = reader.u16_be()?;
+            (len, Some(reader.byte()?))
+        } else {
+            (size, None)
+        };
+
+        if output.len() < offset + len {
+            output.resize(offset + len, 0);
+        }
+
+        match run {
+            Some(value) => output[offset..offset + len].fill(value),
+            None => output[offset..offset + len].copy_from_slice(reader.bytes(len)?),
+        }
+    }
+
+    if let Ok(truncate) = reader.u24_be() {
+        output.truncate(truncate);
+    }
+
+    Ok(output)
+}
+
+/// UPS: XOR hunks at relative offsets, each terminated by a zero byte.
+fn apply_ups(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = read_footer(patch)?;
+    check_source(&footer, rom)?;
+
+    let mut reader = Reader::new(&patch[..patch.len() - 12]);
+    if reader.bytes(4)? != b"UPS1" {
+        return Err(PatchError::BadMagic);
+    }
+
+    let _source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if target_size > MAX_TARGET {
+        return Err(PatchError::TooLarge(target_size));
+    }
+
+    let mut output = vec![0u8; target_size];
+    let shared = rom.len().min(target_size);
+    output[..shared].copy_from_slice(&rom[..shared]);
+
+    let mut offset = 0usize;
+    while reader.offset < reader.data.len() {
+        offset = offset
+            .checked_add(reader.varint()?)
+            .ok_or(PatchError::OutOfBounds)?;
+
+        loop {
+            let xor = reader.byte()?;
+            if xor == 0 {
+                offset = offset.checked_add(1).ok_or(PatchError::OutOfBounds)?;
+                break;
+            }
+
+            *output.get_mut(offset).ok_or(PatchError::OutOfBounds)? ^= xor;
+            offset = offset.checked_add(1).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+
+    check_target(&footer, &output)?;
+
+    Ok(output)
+}
+
+/// BPS: a stream of source-read, target-read, source-copy and target-copy actions.
+fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = read_footer(patch)?;
+    check_source(&footer, rom)?;
+
+    let mut reader = Reader::new(&patch[..patch.len() - 12]);
+    if reader.bytes(4)? != b"BPS1" {
+        return Err(PatchError::BadMagic);
+    }
+
+    let _source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    if target_size > MAX_TARGET {
+        return Err(PatchError::TooLarge(target_size));
+    }
+
+    let mut output = vec![0u8; target_size];
+    let mut output_offset = 0usize;
+    let mut source_relative = 0usize;
+    let mut target_relative = 0usize;
+
+    // Apply a signed relative offset, encoded as `magnitude << 1 | sign`.
+    fn relative(base: usize, encoded: usize) -> Result<usize, PatchError> {
+        let magnitude = encoded >> 1;
+        let moved = if encoded & 1 != 0 {
+            base.checked_sub(magnitude)
+        } else {
+            base.checked_add(magnitude)
+        };
+
+        moved.ok_or(PatchError::OutOfBounds)
+    }
+
+    while reader.offset < reader.data.len() {
+        let action = reader.varint()?;
+        let len = (action >> 2) + 1;
+
+        let end = output_offset
+            .checked_add(len)
+            .filter(|end| *end <= target_size)
+            .ok_or(PatchError::OutOfBounds)?;
+
+        match action & 3 {
+            // SourceRead: copy from the same offset of the source.
+            0 => {
+                let source = rom.get(output_offset..end).ok_or(PatchError::OutOfBounds)?;
+                output[output_offset..end].copy_from_slice(source);
+            }
+            // TargetRead: copy literal bytes from the patch.
+            1 => {
+                output[output_offset..end].copy_from_slice(reader.bytes(len)?);
+            }
+            // SourceCopy: copy from anywhere in the source.
+            2 => {
+                source_relative = relative(source_relative, reader.varint()?)?;
+                let source = source_relative
+                    .checked_add(len)
+                    .and_then(|source_end| rom.get(source_relative..source_end))
+                    .ok_or(PatchError::OutOfBounds)?;
+                output[output_offset..end].copy_from_slice(source);
+                source_relative += len;
+            }
+            // TargetCopy: copy from what was already written, byte by byte since the ranges
+            // may overlap to express runs.
+            _ => {
+                target_relative = relative(target_relative, reader.varint()?)?;
+                for offset in output_offset..end {
+                    if target_relative >= offset {
+                        return Err(PatchError::OutOfBounds);
+                    }
+
+                    output[offset] = output[target_relative];
+                    target_relative += 1;
+                }
+            }
+        }
+
+        output_offset = end;
+    }
+
+    check_target(&footer, &output)?;
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: &[u8] = b"the quick brown fox jumps over the lazy dog";
+
+    /// Encode `value` as the variable-length integers of UPS and BPS.
+    fn varint(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    /// Append the UPS/BPS footer: the source, target and patch CRC-32s.
+    fn footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+
+        patch
+    }
+
+    /// A UPS patch turning `source` into `target`, as one XOR hunk per differing run.
+    fn ups(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        varint(source.len(), &mut patch);
+        varint(target.len(), &mut patch);
+
+        let byte = |data: &[u8], offset: usize| data.get(offset).copied().unwrap_or(0);
+        let (mut offset, mut last) = (0, 0);
+        while offset < target.len() {
+            if byte(source, offset) == target[offset] {
+                offset += 1;
+                continue;
+            }
+
+            varint(offset - last, &mut patch);
+            while offset < target.len() && byte(source, offset) != target[offset] {
+                patch.push(byte(source, offset) ^ target[offset]);
+                offset += 1;
+            }
+            patch.push(0);
+            offset += 1;
+            last = offset;
+        }
+
+        footer(patch, source, target)
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 0x4080, 0xFFFF_FFFF] {
+            let mut bytes = Vec::new();
+            varint(value, &mut bytes);
+
+            assert_eq!(Reader::new(&bytes).varint().unwrap(), value, "{:#x}", value);
+        }
+    }
+
+    #[test]
+    fn numbered_patches_keep_their_format() {
+        assert_eq!(
+            PatchFormat::from_path(Path::new("game.IPS2")),
+            Some(PatchFormat::Ips)
+        );
+        assert_eq!(
+            PatchFormat::from_path(Path::new("game.bps")),
+            Some(PatchFormat::Bps)
+        );
+        assert_eq!(PatchFormat::from_path(Path::new("game.gba")), None);
+    }
+
+    #[test]
+    fn applies_ips_records_runs_and_truncation() {
+        let mut patch = b"PATCH".to_vec();
+        // 3 bytes at 4.
+        patch.extend_from_slice(&[0, 0, 4, 0, 3]);
+        patch.extend_from_slice(b"red");
+        // A run of 4 `!` past the end of the ROM.
+        patch.extend_from_slice(&[0, 0, 50, 0, 0, 0, 4, b'!']);
+        patch.extend_from_slice(b"EOF");
+
+        let output = apply(PatchFormat::Ips, &patch, ROM).unwrap();
+        assert_eq!(&output[..10], b"the redck ");
+        assert_eq!(output.len(), 54);
+        assert_eq!(&output[ROM.len()..50], &[0; 7]);
+        assert_eq!(&output[50..], b"!!!!");
+
+        patch.extend_from_slice(&[0, 0, 9]);
+        assert_eq!(apply(PatchFormat::Ips, &patch, ROM).unwrap(), b"the redck");
+    }
+
+    #[test]
+    fn rejects_broken_ips() {
+        assert!(matches!(
+            apply(PatchFormat::Ips, b"PITCHEOF", ROM),
+            Err(PatchError::BadMagic)
+        ));
+        // A record announcing more data than the patch holds, and no `EOF`.
+        assert!(matches!(
+            apply(PatchFormat::Ips, b"PATCH\0\0\x04\0\x10red", ROM),
+            Err(PatchError::Truncated)
+        ));
+        assert!(matches!(
+            apply(PatchFormat::Ips, b"PATCH", ROM),
+            Err(PatchError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn ups_round_trips() {
+        let target = b"the quick red fox jumps over the lazy dogs!";
+        let output = apply(PatchFormat::Ups, &ups(ROM, target), ROM).unwrap();
+
+        assert_eq!(output, target);
+
+        // Shrinking works as well.
+        let output = apply(PatchFormat::Ups, &ups(ROM, b"the slow"), ROM).unwrap();
+        assert_eq!(output, b"the slow");
+    }
+
+    #[test]
+    fn rejects_broken_ups() {
+        let patch = ups(ROM, b"the quick red fox");
+
+        assert!(matches!(
+            apply(PatchFormat::Ups, &patch, b"another rom"),
+            Err(PatchError::SourceChecksum { .. })
+        ));
+
+        let mut corrupted = patch.clone();
+        corrupted[8] ^= 0xFF;
+        assert!(matches!(
+            apply(PatchFormat::Ups, &corrupted, ROM),
+            Err(PatchError::PatchChecksum { .. })
+        ));
+
+        assert!(matches!(
+            apply(PatchFormat::Ups, &patch[..8], ROM),
+            Err(PatchError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn refuses_huge_targets() {
+        for format in [PatchFormat::Ups, PatchFormat::Bps] {
+            let mut patch = match format {
+                PatchFormat::Ups => b"UPS1".to_vec(),
+                _ => b"BPS1".to_vec(),
+            };
+            varint(ROM.len(), &mut patch);
+            varint(usize::MAX, &mut patch);
+            varint(0, &mut patch);
+
+            let patch = footer(patch, ROM, b"");
+            assert!(
+                matches!(
+                    apply(format, &patch, ROM),
+                    Err(PatchError::TooLarge(usize::MAX))
+                ),
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_ups_offsets_past_the_end() {
+        let mut patch = b"UPS1".to_vec();
+        varint(ROM.len(), &mut patch);
+        varint(ROM.len(), &mut patch);
+        // A hunk as far as offsets go, ending right away, then another one.
+        varint(usize::MAX, &mut patch);
+        patch.push(0);
+        varint(0, &mut patch);
+        patch.extend_from_slice(&[1, 0]);
+
+        let patch = footer(patch, ROM, ROM);
+        assert!(matches!(
+            apply(PatchFormat::Ups, &patch, ROM),
+            Err(PatchError::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn applies_every_bps_action() {
+        let target = b"the lazy dog, the lazy doooog";
+
+        let mut patch = b"BPS1".to_vec();
+        varint(ROM.len(), &mut patch);
+        varint(target.len(), &mut patch);
+        varint(0, &mut patch);
+
+        let action =
+            |kind: usize, len: usize, patch: &mut Vec<u8>| varint((len - 1) << 2 | kind, patch);
+        // SourceRead "the ".
+        action(0, 4, &mut patch);
+        // SourceCopy "lazy dog" from 35.
+        action(2, 8, &mut patch);
+        varint(35 << 1, &mut patch);
+        // TargetRead ", ".
+        action(1, 2, &mut patch);
+        patch.extend_from_slice(b", ");
+        // TargetCopy "the lazy do" from the start.
+        action(3, 11, &mut patch);
+        varint(0, &mut patch);
+        // TargetCopy "ooo" from the last byte, as a run, 13 bytes after where the copy ended.
+        action(3, 3, &mut patch);
+        varint(13 << 1, &mut patch);
+        // TargetRead "g".
+        action(1, 1, &mut patch);
+        patch.push(b'g');
+
+        let patch = footer(patch, ROM, target);
+        assert_eq!(apply(PatchFormat::Bps, &patch, ROM).unwrap(), target);
+
+        assert!(matches!(
+            apply(PatchFormat::Bps, &patch, b"another rom"),
+            Err(PatchError::SourceChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_broken_bps() {
+        let mut patch = b"BPS1".to_vec();
+        varint(ROM.len(), &mut patch);
+        varint(4, &mut patch);
+        varint(0, &mut patch);
+        // A SourceCopy from past the end of the source.
+        varint(3 << 2 | 2, &mut patch);
+        varint(100 << 1, &mut patch);
+
+        let patch = footer(patch, ROM, b"abcd");
+        assert!(matches!(
+            apply(PatchFormat::Bps, &patch, ROM),
+            Err(PatchError::OutOfBounds)
+        ));
+
+        let mut magic = footer(b"BPZ1\x80\x80\x80".to_vec(), ROM, b"");
+        assert!(matches!(
+            apply(PatchFormat::Bps, &magic, ROM),
+            Err(PatchError::BadMagic)
+        ));
+
+        magic.truncate(11);
+        assert!(matches!(
+            apply(PatchFormat::Bps, &magic, ROM),
+            Err(PatchError::Truncated)
+        ));
+    }
+}
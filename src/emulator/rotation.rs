@@ -0,0 +1,164 @@
+/// Rotation of the screen, as the core asks with `SET_ROTATION` or the user forces it.
+///
+/// libretro counts rotations counter-clockwise, in steps of 90 degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Ccw90,
+    Ccw180,
+    Ccw270,
+}
+
+impl Rotation {
+    /// The rotation of `SET_ROTATION`, from 0 to 3.
+    pub fn from_index(index: u32) -> Option<Rotation> {
+        match index {
+            0 => Some(Rotation::None),
+            1 => Some(Rotation::Ccw90),
+            2 => Some(Rotation::Ccw180),
+            3 => Some(Rotation::Ccw270),
+            _ => None,
+        }
+    }
+
+    /// Parse counter-clockwise degrees, 0, 90, 180 or 270.
+    pub fn parse(text: &str) -> Option<Rotation> {
+        match text {
+            "0" => Some(Rotation::None),
+            "90" => Some(Rotation::Ccw90),
+            "180" => Some(Rotation::Ccw180),
+            "270" => Some(Rotation::Ccw270),
+            _ => None,
+        }
+    }
+
+    pub fn degrees(&self) -> u32 {
+        match self {
+            Rotation::None => 0,
+            Rotation::Ccw90 => 90,
+            Rotation::Ccw180 => 180,
+            Rotation::Ccw270 => 270,
+        }
+    }
+
+    /// Whether width and height trade places, for a quarter turn.
+    pub fn swaps_axes(&self) -> bool {
+        matches!(self, Rotation::Ccw90 | Rotation::Ccw270)
+    }
+
+    /// Rotate a frame of `width` by `height` pixels, returning it with its new width and height.
+    pub fn rotate(&self, pixels: &[u32], width: usize, height: usize) -> (Vec<u32>, usize, usize) {
+        let (out_width, out_height) = if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let mut out = Vec::with_capacity(out_width * out_height);
+
+        for y in 0..out_height {
+            for x in 0..out_width {
+                // The pixel of the frame that lands at `x`, `y` once rotated.
+                let (source_x, source_y) = match self {
+                    Rotation::None => (x, y),
+                    Rotation::Ccw90 => (width - 1 - y, x),
+                    Rotation::Ccw180 => (width - 1 - x, height - 1 - y),
+                    Rotation::Ccw270 => (y, height - 1 - x),
+                };
+
+                out.push(pixels[source_y * width + source_x]);
+            }
+        }
+
+        (out, out_width, out_height)
+    }
+
+    /// Bring a position on the rotated screen back to the core's screen. Both are in libretro's
+    /// pointer coordinates, from -0x7FFF at the top left to 0x7FFF at the bottom right.
+    pub fn to_core(&self, x: i16, y: i16) -> (i16, i16) {
+        match self {
+            Rotation::None => (x, y),
+            Rotation::Ccw90 => (-y, x),
+            Rotation::Ccw180 => (-x, -y),
+            Rotation::Ccw270 => (y, -x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Rotation; 4] = [
+        Rotation::None,
+        Rotation::Ccw90,
+        Rotation::Ccw180,
+        Rotation::Ccw270,
+    ];
+
+    /// 2 pixels wide, 3 high:
+    ///
+    /// ```text
+    /// 1 2
+    /// 3 4
+    /// 5 6
+    /// ```
+    const FRAME: [u32; 6] = [1, 2, 3, 4, 5, 6];
+
+    #[test]
+    fn rotates_frames_counter_clockwise() {
+        assert_eq!(Rotation::None.rotate(&FRAME, 2, 3), (FRAME.to_vec(), 2, 3));
+        assert_eq!(
+            Rotation::Ccw90.rotate(&FRAME, 2, 3),
+            (vec![2, 4, 6, 1, 3, 5], 3, 2)
+        );
+        assert_eq!(
+            Rotation::Ccw180.rotate(&FRAME, 2, 3),
+            (vec![6, 5, 4, 3, 2, 1], 2, 3)
+        );
+        assert_eq!(
+            Rotation::Ccw270.rotate(&FRAME, 2, 3),
+            (vec![5, 3, 1, 6, 4, 2], 3, 2)
+        );
+    }
+
+    #[test]
+    fn points_follow_the_frame() {
+        let corners = |width: usize, height: usize| {
+            [
+                (-0x7FFF, -0x7FFF, 0, 0),
+                (0x7FFF, -0x7FFF, width - 1, 0),
+                (-0x7FFF, 0x7FFF, 0, height - 1),
+                (0x7FFF, 0x7FFF, width - 1, height - 1),
+            ]
+        };
+        let pixel = |x: i16, y: i16| {
+            let column = if x < 0 { 0 } else { 1 };
+            let row = if y < 0 { 0 } else { 2 };
+            FRAME[row * 2 + column]
+        };
+
+        for rotation in ALL {
+            let (rotated, width, height) = rotation.rotate(&FRAME, 2, 3);
+
+            // Each corner of the rotated screen is the core's corner its pixel came from.
+            for (x, y, column, row) in corners(width, height) {
+                let (core_x, core_y) = rotation.to_core(x, y);
+                assert_eq!(pixel(core_x, core_y), rotated[row * width + column]);
+            }
+        }
+    }
+
+    #[test]
+    fn to_core_round_trips() {
+        let inverse =
+            |rotation: Rotation| Rotation::from_index((4 - rotation.degrees() / 90) % 4).unwrap();
+
+        for rotation in ALL {
+            for (x, y) in [(0, 0), (100, -2000), (-0x7FFF, 0x7FFF), (0x1234, 0x7FFF)] {
+                let (core_x, core_y) = rotation.to_core(x, y);
+                assert_eq!(inverse(rotation).to_core(core_x, core_y), (x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use super::memory;
+
+/// Size of the values being searched for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    U8,
+    U16,
+    U32,
+}
+
+impl Width {
+    pub fn bytes(&self) -> usize {
+        match self {
+            Width::U8 => 1,
+            Width::U16 => 2,
+            Width::U32 => 4,
+        }
+    }
+
+    /// Parse a width given in bits, `8`, `16` or `32`.
+    pub fn from_bits(bits: &str) -> Option<Width> {
+        match bits {
+            "8" => Some(Width::U8),
+            "16" => Some(Width::U16),
+            "32" => Some(Width::U32),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// Decode a value of `bytes.len()` bytes.
+pub fn decode(bytes: &[u8], endian: Endian) -> u32 {
+    let fold = |value: u32, byte: &u8| value << 8 | *byte as u32;
+
+    match endian {
+        Endian::Little => bytes.iter().rev().fold(0, fold),
+        Endian::Big => bytes.iter().fold(0, fold),
+    }
+}
+
+/// Encode the low `width` bytes of a value.
+pub fn encode(value: u32, width: Width, endian: Endian) -> Vec<u8> {
+    let bytes = match endian {
+        Endian::Little => value.to_le_bytes(),
+        Endian::Big => value.to_be_bytes(),
+    };
+
+    match endian {
+        Endian::Little => bytes[..width.bytes()].to_vec(),
+        Endian::Big => bytes[4 - width.bytes()..].to_vec(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+}
+
+impl Compare {
+    fn matches(&self, current: u32, operand: u32) -> bool {
+        match self {
+            Compare::Equal => current == operand,
+            Compare::NotEqual => current != operand,
+            Compare::Greater => current > operand,
+            Compare::Less => current < operand,
+        }
+    }
+}
+
+/// What candidates are compared against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// The value the candidate had at the previous search step, e.g. `NotEqual` + `Previous`
+    /// keeps the addresses that changed.
+    Previous,
+    Value(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate {
+    pub address: usize,
+    /// Value at the last search step.
+    pub value: u32,
+}
+
+/// A RAM search, narrowing down the addresses that hold a game variable.
+///
+/// It starts from a snapshot of every aligned value in guest RAM, and each call to `filter` keeps
+/// the candidates matching a comparison against a known value or against the previous snapshot.
+pub struct RamSearch {
+    pub width: Width,
+    pub endian: Endian,
+    candidates: Vec<Candidate>,
+}
+
+impl RamSearch {
+    pub fn new(width: Width, endian: Endian) -> RamSearch {
+        RamSearch::in_regions(&memory::address_space().regions(), width, endian)
+    }
+
+    /// A search over the given regions only.
+    pub fn in_regions(regions: &[memory::Region], width: Width, endian: Endian) -> RamSearch {
+        let mut candidates = Vec::new();
+
+        for region in regions {
+            let bytes = unsafe { region.bytes() };
+
+            for (index, chunk) in bytes.chunks_exact(width.bytes()).enumerate() {
+                candidates.push(Candidate {
+                    address: region.start + index * width.bytes(),
+                    value: decode(chunk, endian),
+                });
+            }
+        }
+
+        RamSearch {
+            width,
+            endian,
+            candidates,
+        }
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    /// Current value at a candidate's address.
+    pub fn current(&self, address: usize) -> Option<u32> {
+        memory::read(address, self.width.bytes())
+            .ok()
+            .map(|bytes| decode(&bytes, self.endian))
+    }
+
+    /// Keep the candidates whose current value matches, and remember that value for the next step.
+    pub fn filter(&mut self, compare: Compare, operand: Operand) {
+        self.filter_regions(&memory::address_space().regions(), compare, operand);
+    }
+
+    /// `filter`, reading the values from the given regions.
+    pub fn filter_regions(
+        &mut self,
+        regions: &[memory::Region],
+        compare: Compare,
+        operand: Operand,
+    ) {
+        let width = self.width.bytes();
+        let endian = self.endian;
+
+        self.candidates.retain_mut(|candidate| {
+            let Some(region) = regions.iter().find(|region| {
+                region.contains(candidate.address) && region.contains(candidate.address + width - 1)
+            }) else {
+                return false;
+            };
+
+            let offset = candidate.address - region.start;
+            let current = decode(unsafe { &region.bytes()[offset..offset + width] }, endian);
+
+            let operand = match operand {
+                Operand::Previous => candidate.value,
+                Operand::Value(value) => value,
+            };
+
+            candidate.value = current;
+            compare.matches(current, operand)
+        });
+    }
+}
+
+/// A value written into guest memory on every frame, keeping a game variable locked.
+#[derive(Debug, Clone, Copy)]
+pub struct Freeze {
+    pub address: usize,
+    pub value: u32,
+    pub width: Width,
+    pub endian: Endian,
+}
+
+impl Freeze {
+    pub fn apply(&self) -> Result<(), memory::MemoryError> {
+        self.apply_to(&memory::address_space())
+    }
+
+    pub fn apply_to(&self, space: &memory::AddressSpace) -> Result<(), memory::MemoryError> {
+        space.write(self.address, &encode(self.value, self.width, self.endian))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::libretro;
+    use super::*;
+
+    /// 64 bytes of system RAM at `0x0300_0000`.
+    fn space(ram: &mut [u8; 64]) -> memory::AddressSpace {
+        memory::AddressSpace::from_descriptors(&[libretro::retro_memory_descriptor {
+            flags: libretro::RETRO_MEMDESC_SYSTEM_RAM as u64,
+            ptr: ram.as_mut_ptr() as *mut std::os::raw::c_void,
+            offset: 0,
+            start: 0x0300_0000,
+            select: 0,
+            disconnect: 0,
+            len: ram.len(),
+            addrspace: std::ptr::null(),
+        }])
+    }
+
+    fn addresses(search: &RamSearch) -> Vec<usize> {
+        search
+            .candidates()
+            .iter()
+            .map(|candidate| candidate.address)
+            .collect()
+    }
+
+    #[test]
+    fn decodes_and_encodes_little_and_big_endian() {
+        assert_eq!(decode(&[0x34, 0x12], Endian::Little), 0x1234);
+        assert_eq!(
+            decode(&[0x78, 0x56, 0x34, 0x12], Endian::Little),
+            0x1234_5678
+        );
+        assert_eq!(decode(&[0x12, 0x34], Endian::Big), 0x1234);
+        assert_eq!(decode(&[0xFF], Endian::Little), 0xFF);
+
+        assert_eq!(
+            encode(0x1234_5678, Width::U16, Endian::Little),
+            [0x78, 0x56]
+        );
+        assert_eq!(encode(0x1234_5678, Width::U16, Endian::Big), [0x56, 0x78]);
+        assert_eq!(
+            encode(0x1234_5678, Width::U32, Endian::Little),
+            [0x78, 0x56, 0x34, 0x12]
+        );
+    }
+
+    #[test]
+    fn snapshots_aligned_values() {
+        let mut ram = [0u8; 64];
+        ram[4..8].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        let regions = space(&mut ram).regions();
+
+        let search = RamSearch::in_regions(&regions, Width::U16, Endian::Little);
+        assert_eq!(search.candidates().len(), 32);
+        assert_eq!(search.candidates()[2].address, 0x0300_0004);
+        assert_eq!(search.candidates()[2].value, 0x5678);
+        assert_eq!(search.candidates()[3].value, 0x1234);
+
+        let search = RamSearch::in_regions(&regions, Width::U32, Endian::Little);
+        assert_eq!(search.candidates().len(), 16);
+        assert_eq!(search.candidates()[1].address, 0x0300_0004);
+        assert_eq!(search.candidates()[1].value, 0x1234_5678);
+    }
+
+    #[test]
+    fn filters_against_values() {
+        let mut ram = [0u8; 64];
+        ram[0] = 10;
+        ram[1] = 20;
+        ram[2] = 30;
+        let regions = space(&mut ram).regions();
+
+        let matching = |compare: Compare, value: u32| {
+            let mut search = RamSearch::in_regions(&regions, Width::U8, Endian::Little);
+            search.filter_regions(&regions, compare, Operand::Value(value));
+            addresses(&search)
+        };
+
+        assert_eq!(matching(Compare::Equal, 20), [0x0300_0001]);
+        assert_eq!(matching(Compare::Greater, 15), [0x0300_0001, 0x0300_0002]);
+        assert!(matching(Compare::Less, 0).is_empty());
+        assert_eq!(
+            matching(Compare::NotEqual, 0),
+            [0x0300_0000, 0x0300_0001, 0x0300_0002]
+        );
+    }
+
+    #[test]
+    fn narrows_down_across_snapshots() {
+        let mut ram = [0u8; 64];
+        ram[8] = 3;
+        ram[12] = 3;
+        let regions = space(&mut ram).regions();
+
+        let mut search = RamSearch::in_regions(&regions, Width::U32, Endian::Little);
+        search.filter_regions(&regions, Compare::Equal, Operand::Value(3));
+        assert_eq!(addresses(&search), [0x0300_0008, 0x0300_000C]);
+
+        // The game takes a hit: only one of them goes down.
+        ram[8] = 2;
+        let regions = space(&mut ram).regions();
+        search.filter_regions(&regions, Compare::Less, Operand::Previous);
+        assert_eq!(addresses(&search), [0x0300_0008]);
+        assert_eq!(search.candidates()[0].value, 2);
+
+        // Nothing changed since, comparing with the previous step keeps it.
+        search.filter_regions(&regions, Compare::Equal, Operand::Previous);
+        assert_eq!(addresses(&search), [0x0300_0008]);
+    }
+
+    #[test]
+    fn freezes_write_their_value() {
+        let mut ram = [0u8; 64];
+        let space = space(&mut ram);
+
+        let freeze = Freeze {
+            address: 0x0300_0010,
+            value: 0x1234_5678,
+            width: Width::U16,
+            endian: Endian::Little,
+        };
+        freeze.apply_to(&space).unwrap();
+        assert_eq!(space.read(0x0300_0010, 4).unwrap(), [0x78, 0x56, 0, 0]);
+
+        let outside = Freeze {
+            address: 0x0300_0040,
+            ..freeze
+        };
+        assert!(outside.apply_to(&space).is_err());
+    }
+}
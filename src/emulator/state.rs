@@ -0,0 +1,416 @@
+use std::fmt;
+use std::io::Read;
+
+use super::hash;
+
+const MAGIC: &[u8; 8] = b"ADVSTATE";
+const VERSION: u32 = 2;
+/// Largest block inflated, far above any core's state, so a damaged file can't exhaust memory.
+const MAX_BLOCK: usize = 256 * 1024 * 1024;
+
+/// Tags of the blocks following the header.
+const DATA: &[u8; 4] = b"DATA";
+const ORIGIN: &[u8; 4] = b"ORIG";
+
+/// Where a state comes from, checked before the core is handed its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub core_name: String,
+    pub core_version: String,
+    /// CRC-32 of the content the state was saved from.
+    pub rom_crc32: u32,
+    /// What `retro_serialize_size` answered when the state was saved.
+    pub size: usize,
+}
+
+/// Where a state was saved, for cores whose states only load in the same session or on the
+/// same kind of machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// Identifies the run of the front-end that saved the state.
+    pub session: u64,
+    pub big_endian: bool,
+    /// Operating system and CPU architecture, `linux-x86_64`.
+    pub platform: String,
+}
+
+impl Origin {
+    /// This machine, in the run identified by `session`.
+    pub fn current(session: u64) -> Origin {
+        Origin {
+            session,
+            big_endian: cfg!(target_endian = "big"),
+            platform: format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH),
+        }
+    }
+}
+
+/// How the blocks of a state are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Deflate,
+    /// Reserved for states written by other tools, zstd isn't built in.
+    Zstd,
+}
+
+impl Compression {
+    fn from_byte(byte: u8) -> Result<Compression, StateError> {
+        match byte {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            2 => Ok(Compression::Zstd),
+            _ => Err(StateError::UnsupportedCompression(byte)),
+        }
+    }
+
+    fn byte(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>, StateError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Deflate => Ok(miniz_oxide::deflate::compress_to_vec(data, 6)),
+            Compression::Zstd => Err(StateError::UnsupportedCompression(self.byte())),
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> Result<Vec<u8>, StateError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Deflate => {
+                miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_BLOCK)
+                    .map_err(|_| StateError::Corrupted)
+            }
+            Compression::Zstd => Err(StateError::UnsupportedCompression(self.byte())),
+        }
+    }
+}
+
+/// A save state: what `retro_serialize` wrote, and where.
+#[derive(Debug, Clone)]
+pub struct State {
+    pub header: Header,
+    pub data: Vec<u8>,
+    pub origin: Option<Origin>,
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    UnsupportedCompression(u8),
+    Truncated,
+    /// A checksum doesn't match or a block doesn't inflate, the file was damaged.
+    Corrupted,
+    /// The state has no data block.
+    MissingData,
+    /// The state was saved by another core, named here.
+    OtherCore(String),
+    /// The state was saved from another ROM, with this CRC-32.
+    OtherRom(u32),
+    /// The core's states only load in the run of the front-end that saved them.
+    OtherSession,
+    /// The core's states only load on the kind of machine that saved them, this one.
+    OtherPlatform(String),
+    /// The core's states don't travel and the state doesn't say where it was saved.
+    UnknownOrigin,
+    /// The core expects states of another size.
+    WrongSize {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a state file"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "state version {} is not supported", version)
+            }
+            StateError::UnsupportedCompression(2) => {
+                write!(
+                    f,
+                    "the state is compressed with zstd, which isn't supported"
+                )
+            }
+            StateError::UnsupportedCompression(byte) => {
+                write!(f, "state compression {} is not supported", byte)
+            }
+            StateError::Truncated => write!(f, "state file is truncated"),
+            StateError::Corrupted => write!(f, "state file is corrupted"),
+            StateError::MissingData => write!(f, "the state file holds no state"),
+            StateError::OtherCore(name) => write!(f, "the state was saved by {}", name),
+            StateError::OtherRom(crc32) => {
+                write!(f, "the state was saved from the rom {:08x}", crc32)
+            }
+            StateError::OtherSession => write!(
+                f,
+                "the core's states only load in the session that saved them"
+            ),
+            StateError::OtherPlatform(platform) => write!(
+                f,
+                "the state was saved on {}, the core's states only load on the same platform",
+                platform
+            ),
+            StateError::UnknownOrigin => write!(
+                f,
+                "the core's states don't travel and the state doesn't say where it was saved"
+            ),
+            StateError::WrongSize { expected, found } => write!(
+                f,
+                "the state holds {} bytes but the core expects {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for StateError {
+    fn from(_: std::io::Error) -> Self {
+        // States are decoded from memory, running out of bytes is the only way reading fails.
+        StateError::Truncated
+    }
+}
+
+/// Whether `bytes` start like a state file, anything else being a raw `retro_serialize` blob.
+pub fn is_state(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Write a state file: the header, the blocks compressed with `compression`, and a checksum of
+/// everything before it.
+pub fn encode(state: &State, compression: Compression) -> Result<Vec<u8>, StateError> {
+    let mut out = Vec::with_capacity(state.data.len() / 2 + 64);
+
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    write_string(&mut out, &state.header.core_name);
+    write_string(&mut out, &state.header.core_version);
+    out.extend_from_slice(&state.header.rom_crc32.to_le_bytes());
+    out.extend_from_slice(&(state.header.size as u32).to_le_bytes());
+    out.push(compression.byte());
+
+    let mut blocks = vec![(DATA, state.data.clone())];
+    if let Some(origin) = &state.origin {
+        let mut block = Vec::with_capacity(origin.platform.len() + 11);
+        block.extend_from_slice(&origin.session.to_le_bytes());
+        block.push(origin.big_endian as u8);
+        write_string(&mut block, &origin.platform);
+
+        blocks.push((ORIGIN, block));
+    }
+
+    out.push(blocks.len() as u8);
+    for (tag, block) in blocks {
+        let stored = compression.compress(&block)?;
+
+        out.extend_from_slice(tag);
+        out.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        out.extend_from_slice(&stored);
+    }
+
+    out.extend_from_slice(&hash::crc32(&out).to_le_bytes());
+
+    Ok(out)
+}
+
+/// Read a state file, checking it wasn't damaged.
+pub fn decode(bytes: &[u8]) -> Result<State, StateError> {
+    let mut file = bytes;
+
+    let mut magic = [0u8; 8];
+    file.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+
+    let version = read_u32(&mut file)?;
+    if version != VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+
+    // The checksum covers the whole file, check it before trusting any length in it.
+    if bytes.len() < 16 {
+        return Err(StateError::Truncated);
+    }
+    let body = bytes.len() - 4;
+    let checksum = u32::from_le_bytes(bytes[body..].try_into().unwrap());
+    if hash::crc32(&bytes[..body]) != checksum {
+        return Err(StateError::Corrupted);
+    }
+    let mut file = &bytes[12..body];
+
+    let core_name = read_string(&mut file)?;
+    let core_version = read_string(&mut file)?;
+    let rom_crc32 = read_u32(&mut file)?;
+    let size = read_u32(&mut file)? as usize;
+    let compression = Compression::from_byte(read_bytes(&mut file, 1)?[0])?;
+    let count = read_bytes(&mut file, 1)?[0];
+
+    let mut state = State {
+        header: Header {
+            core_name,
+            core_version,
+            rom_crc32,
+            size,
+        },
+        data: Vec::new(),
+        origin: None,
+    };
+    let mut has_data = false;
+
+    for _ in 0..count {
+        let tag = read_bytes(&mut file, 4)?;
+        let len = read_u32(&mut file)? as usize;
+        if len > file.len() {
+            return Err(StateError::Truncated);
+        }
+        let block = compression.decompress(&read_bytes(&mut file, len)?)?;
+
+        // Blocks this version doesn't know are skipped, newer files may add some.
+        match tag.as_slice() {
+            tag if tag == DATA => {
+                state.data = block;
+                has_data = true;
+            }
+            tag if tag == ORIGIN => {
+                let mut block = block.as_slice();
+                let session = u64::from_le_bytes(read_bytes(&mut block, 8)?.try_into().unwrap());
+                let big_endian = read_bytes(&mut block, 1)?[0] != 0;
+
+                state.origin = Some(Origin {
+                    session,
+                    big_endian,
+                    platform: read_string(&mut block)?,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    if !has_data {
+        return Err(StateError::MissingData);
+    }
+
+    Ok(state)
+}
+
+fn write_string(out: &mut Vec<u8>, text: &str) {
+    out.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn read_bytes(file: &mut impl Read, len: usize) -> Result<Vec<u8>, StateError> {
+    let mut bytes = vec![0u8; len];
+    file.read_exact(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+fn read_u32(file: &mut impl Read) -> Result<u32, StateError> {
+    Ok(u32::from_le_bytes(read_bytes(file, 4)?.try_into().unwrap()))
+}
+
+fn read_string(file: &mut impl Read) -> Result<String, StateError> {
+    let len = u16::from_le_bytes(read_bytes(file, 2)?.try_into().unwrap());
+
+    Ok(String::from_utf8_lossy(&read_bytes(file, len as usize)?).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        State {
+            header: Header {
+                core_name: String::from("mGBA"),
+                core_version: String::from("0.10"),
+                rom_crc32: 0x1234_5678,
+                size: 64,
+            },
+            data: (0..64).collect(),
+            origin: Some(Origin::current(42)),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for compression in [Compression::None, Compression::Deflate] {
+            let state = state();
+            let decoded = decode(&encode(&state, compression).unwrap()).unwrap();
+
+            assert_eq!(decoded.header, state.header);
+            assert_eq!(decoded.data, state.data);
+            assert_eq!(decoded.origin, state.origin);
+        }
+
+        let state = State {
+            origin: None,
+            ..state()
+        };
+        let decoded = decode(&encode(&state, Compression::Deflate).unwrap()).unwrap();
+        assert_eq!(decoded.data, state.data);
+        assert_eq!(decoded.origin, None);
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let bytes = encode(&state(), Compression::Deflate).unwrap();
+
+        assert!(!is_state(b"raw retro_serialize data"));
+        assert!(matches!(
+            decode(b"NOTASTATEFILE"),
+            Err(StateError::BadMagic)
+        ));
+
+        let mut version = bytes.clone();
+        version[8] = 99;
+        assert!(matches!(
+            decode(&version),
+            Err(StateError::UnsupportedVersion(99))
+        ));
+
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "cut at {}", len);
+        }
+
+        for index in 12..bytes.len() {
+            let mut flipped = bytes.clone();
+            flipped[index] ^= 0x01;
+            assert!(
+                matches!(decode(&flipped), Err(StateError::Corrupted)),
+                "flipped {}",
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_zstd() {
+        assert!(matches!(
+            encode(&state(), Compression::Zstd),
+            Err(StateError::UnsupportedCompression(2))
+        ));
+
+        // A file written by another tool, with a valid checksum.
+        let mut bytes = encode(&state(), Compression::None).unwrap();
+        let compression = 8 + 4 + (2 + 4) + (2 + 4) + 4 + 4;
+        bytes[compression] = Compression::Zstd.byte();
+        let body = bytes.len() - 4;
+        let checksum = hash::crc32(&bytes[..body]);
+        bytes[body..].copy_from_slice(&checksum.to_le_bytes());
+
+        assert!(matches!(
+            decode(&bytes),
+            Err(StateError::UnsupportedCompression(2))
+        ));
+    }
+}
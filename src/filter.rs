@@ -0,0 +1,345 @@
+use bevy::prelude::*;
+
+use crate::emulator::PixelFormat;
+
+/// A frame as `draw` hands it to pixels, one `u32` per pixel holding RGBA bytes.
+pub struct Image {
+    pub pixels: Vec<u32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// One step of the post-processing chain.
+pub enum Filter {
+    /// Nearest neighbour scaling by a whole factor, `2x` to `6x`.
+    Scale(usize),
+    /// Nearest neighbour scaling to the largest whole factor below the given one, then bilinear
+    /// to it, so pixels stay sharp at fractional scales: `sharp-bilinear:4.5`.
+    SharpBilinear(f32),
+    /// The AdvMAME2x/EPX scaler, `scale2x`.
+    Scale2x,
+    /// The AdvMAME3x scaler, `scale3x`.
+    Scale3x,
+    /// Darken every other row, best after a scaler: `scanlines`.
+    Scanlines,
+    /// Scale by a whole factor and darken the border of each pixel, like the grid of an LCD:
+    /// `lcd` or `lcd:4`.
+    LcdGrid(usize),
+    /// Blend each frame with the previous ones, like the slow response of the GBA's LCD:
+    /// `ghosting`.
+    Ghosting(Option<Vec<u32>>),
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Option<Filter> {
+        let (name, argument) = match text.split_once(':') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (text, None),
+        };
+
+        match (name, argument) {
+            ("2x" | "3x" | "4x" | "5x" | "6x", None) => {
+                Some(Filter::Scale(name[..1].parse().ok()?))
+            }
+            ("sharp-bilinear", Some(scale)) => {
+                let scale: f32 = scale.parse().ok()?;
+                (1.0..=8.0)
+                    .contains(&scale)
+                    .then_some(Filter::SharpBilinear(scale))
+            }
+            ("scale2x", None) => Some(Filter::Scale2x),
+            ("scale3x", None) => Some(Filter::Scale3x),
+            ("scanlines", None) => Some(Filter::Scanlines),
+            ("lcd", None) => Some(Filter::LcdGrid(3)),
+            ("lcd", Some(scale)) => {
+                let scale: usize = scale.parse().ok()?;
+                (2..=8).contains(&scale).then_some(Filter::LcdGrid(scale))
+            }
+            ("ghosting", None) => Some(Filter::Ghosting(None)),
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, image: Image) -> Image {
+        match self {
+            Filter::Scale(scale) => nearest(&image, *scale),
+            Filter::SharpBilinear(scale) => sharp_bilinear(&image, *scale),
+            Filter::Scale2x => scale2x(&image),
+            Filter::Scale3x => scale3x(&image),
+            Filter::Scanlines => scanlines(image),
+            Filter::LcdGrid(scale) => lcd_grid(&image, *scale),
+            Filter::Ghosting(previous) => ghosting(image, previous),
+        }
+    }
+}
+
+/// The filters applied to every frame, in order, set with `--filter` or the `filter` command.
+#[derive(Resource, Default)]
+pub struct Filters {
+    chain: Vec<Filter>,
+}
+
+impl Filters {
+    /// Parse a comma separated chain of filters, such as `scale2x,scanlines`.
+    pub fn parse(text: &str) -> Result<Filters, String> {
+        let chain = text
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && *name != "none")
+            .map(|name| Filter::parse(name).ok_or(format!("unknown filter {}", name)))
+            .collect::<Result<Vec<Filter>, String>>()?;
+
+        Ok(Filters { chain })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+    }
+
+    pub fn apply(&mut self, mut image: Image) -> Image {
+        for filter in self.chain.iter_mut() {
+            image = filter.apply(image);
+        }
+
+        image
+    }
+}
+
+/// Filters run on the frames shown in the window, which are RGBA8.
+fn channels(pixel: u32) -> [u32; 3] {
+    PixelFormat::Rgba8.rgb(pixel).map(u32::from)
+}
+
+fn pack(channels: [u32; 3]) -> u32 {
+    PixelFormat::Rgba8.pack(channels.map(|channel| channel.min(0xFF) as u8))
+}
+
+/// Multiply every channel of a pixel by `factor / 256`.
+fn darken(pixel: u32, factor: u32) -> u32 {
+    pack(channels(pixel).map(|channel| channel * factor / 256))
+}
+
+fn nearest(image: &Image, scale: usize) -> Image {
+    let width = image.width * scale;
+    let mut pixels = Vec::with_capacity(width * image.height * scale);
+
+    for row in image.pixels.chunks_exact(image.width) {
+        let start = pixels.len();
+
+        for pixel in row {
+            pixels.extend(std::iter::repeat(*pixel).take(scale));
+        }
+        for _ in 1..scale {
+            pixels.extend_from_within(start..start + width);
+        }
+    }
+
+    Image {
+        pixels,
+        width,
+        height: image.height * scale,
+    }
+}
+
+fn sharp_bilinear(image: &Image, scale: f32) -> Image {
+    let prescaled = nearest(image, scale.floor() as usize);
+
+    let width = (image.width as f32 * scale).round() as usize;
+    let height = (image.height as f32 * scale).round() as usize;
+    let step_x = prescaled.width as f32 / width as f32;
+    let step_y = prescaled.height as f32 / height as f32;
+
+    let mut pixels = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        let source_y = ((y as f32 + 0.5) * step_y - 0.5).max(0.0);
+        let y0 = (source_y as usize).min(prescaled.height - 1);
+        let y1 = (y0 + 1).min(prescaled.height - 1);
+        let weight_y = source_y - y0 as f32;
+
+        for x in 0..width {
+            let source_x = ((x as f32 + 0.5) * step_x - 0.5).max(0.0);
+            let x0 = (source_x as usize).min(prescaled.width - 1);
+            let x1 = (x0 + 1).min(prescaled.width - 1);
+            let weight_x = source_x - x0 as f32;
+
+            let at = |x: usize, y: usize| channels(prescaled.pixels[y * prescaled.width + x]);
+            let (top_left, top_right) = (at(x0, y0), at(x1, y0));
+            let (bottom_left, bottom_right) = (at(x0, y1), at(x1, y1));
+
+            let mut color = [0u32; 3];
+            for channel in 0..3 {
+                let top = top_left[channel] as f32 * (1.0 - weight_x)
+                    + top_right[channel] as f32 * weight_x;
+                let bottom = bottom_left[channel] as f32 * (1.0 - weight_x)
+                    + bottom_right[channel] as f32 * weight_x;
+
+                color[channel] = (top * (1.0 - weight_y) + bottom * weight_y).round() as u32;
+            }
+
+            pixels.push(pack(color));
+        }
+    }
+
+    Image {
+        pixels,
+        width,
+        height,
+    }
+}
+
+/// The neighbours of a pixel, repeating the pixels of the border.
+///
+/// ```text
+/// A B C
+/// D E F
+/// G H I
+/// ```
+fn neighbours(image: &Image, x: usize, y: usize) -> [u32; 9] {
+    let left = x.saturating_sub(1);
+    let right = (x + 1).min(image.width - 1);
+    let up = y.saturating_sub(1);
+    let down = (y + 1).min(image.height - 1);
+
+    let at = |x: usize, y: usize| image.pixels[y * image.width + x];
+
+    [
+        at(left, up),
+        at(x, up),
+        at(right, up),
+        at(left, y),
+        at(x, y),
+        at(right, y),
+        at(left, down),
+        at(x, down),
+        at(right, down),
+    ]
+}
+
+fn scale2x(image: &Image) -> Image {
+    let width = image.width * 2;
+    let mut pixels = vec![0u32; width * image.height * 2];
+
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let [_, b, _, d, e, f, _, h, _] = neighbours(image, x, y);
+
+            let out = [
+                if d == b && b != f && d != h { d } else { e },
+                if b == f && b != d && f != h { f } else { e },
+                if d == h && d != b && h != f { d } else { e },
+                if h == f && d != h && b != f { f } else { e },
+            ];
+
+            let top = (y * 2) * width + x * 2;
+            pixels[top..top + 2].copy_from_slice(&out[..2]);
+            pixels[top + width..top + width + 2].copy_from_slice(&out[2..]);
+        }
+    }
+
+    Image {
+        pixels,
+        width,
+        height: image.height * 2,
+    }
+}
+
+fn scale3x(image: &Image) -> Image {
+    let width = image.width * 3;
+    let mut pixels = vec![0u32; width * image.height * 3];
+
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let [a, b, c, d, e, f, g, h, i] = neighbours(image, x, y);
+
+            let out = [
+                if d == b && b != f && d != h { d } else { e },
+                if (d == b && b != f && d != h && e != c) || (b == f && b != d && f != h && e != a)
+                {
+                    b
+                } else {
+                    e
+                },
+                if b == f && b != d && f != h { f } else { e },
+                if (d == b && b != f && d != h && e != g) || (d == h && d != b && h != f && e != a)
+                {
+                    d
+                } else {
+                    e
+                },
+                e,
+                if (b == f && b != d && f != h && e != i) || (h == f && d != h && b != f && e != c)
+                {
+                    f
+                } else {
+                    e
+                },
+                if d == h && d != b && h != f { d } else { e },
+                if (d == h && d != b && h != f && e != i) || (h == f && d != h && b != f && e != g)
+                {
+                    h
+                } else {
+                    e
+                },
+                if h == f && d != h && b != f { f } else { e },
+            ];
+
+            for row in 0..3 {
+                let start = (y * 3 + row) * width + x * 3;
+                pixels[start..start + 3].copy_from_slice(&out[row * 3..row * 3 + 3]);
+            }
+        }
+    }
+
+    Image {
+        pixels,
+        width,
+        height: image.height * 3,
+    }
+}
+
+fn scanlines(mut image: Image) -> Image {
+    for row in image
+        .pixels
+        .chunks_exact_mut(image.width)
+        .skip(1)
+        .step_by(2)
+    {
+        for pixel in row {
+            *pixel = darken(*pixel, 160);
+        }
+    }
+
+    image
+}
+
+fn lcd_grid(image: &Image, scale: usize) -> Image {
+    let mut scaled = nearest(image, scale);
+
+    for (y, row) in scaled
+        .pixels
+        .chunks_exact_mut(image.width * scale)
+        .enumerate()
+    {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            if x % scale == scale - 1 || y % scale == scale - 1 {
+                *pixel = darken(*pixel, 192);
+            }
+        }
+    }
+
+    scaled
+}
+
+fn ghosting(mut image: Image, previous: &mut Option<Vec<u32>>) -> Image {
+    if let Some(previous) = previous.as_ref().filter(|p| p.len() == image.pixels.len()) {
+        for (pixel, old) in image.pixels.iter_mut().zip(previous) {
+            let (new, old) = (channels(*pixel), channels(*old));
+            *pixel = pack([0, 1, 2].map(|channel| (new[channel] + old[channel]) / 2));
+        }
+    }
+
+    *previous = Some(image.pixels.clone());
+
+    image
+}
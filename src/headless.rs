@@ -0,0 +1,166 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::cli::Args;
+use crate::emulator::movie::MovieMode;
+use crate::emulator::PixelFormat;
+use crate::recorder::Recording;
+use crate::screenshot::Screenshots;
+use crate::wav::WavWriter;
+use crate::EMU;
+
+/// Drive the core in a plain loop, without a window nor a GPU.
+///
+/// The run stops after `--frames`, once the `--until` condition holds, or at the end of the
+/// movie being played, whichever comes first. Returns the exit code of the process.
+pub fn run(args: &Args) -> i32 {
+    if args.frames.is_none() && args.until.is_none() && args.movie.is_none() {
+        eprintln!("--headless needs --frames, --until or --movie to know when to stop");
+        return 1;
+    }
+
+    // Without a game every frame would run nothing, and the run would look like a success.
+    if EMU.content().is_none() {
+        eprintln!("no game is loaded, nothing to run");
+        return 1;
+    }
+
+    if let Some(dir) = &args.dump_frames {
+        if let Err(err) = std::fs::create_dir_all(dir) {
+            eprintln!("could not create {}: {}", dir, err);
+            return 1;
+        }
+    }
+
+    // Nothing is shown, frames are only captured, so keep them in libretro's own layout.
+    EMU.set_pixel_format(PixelFormat::Xrgb8888);
+    if args.wav.is_some() {
+        EMU.capture_audio(true);
+    }
+
+    let mut wav = match &args.wav {
+        Some(path) => {
+            let (_, sample_rate) = EMU.timing();
+
+            match WavWriter::create(Path::new(path), sample_rate.round() as u32) {
+                Ok(wav) => Some(wav),
+                Err(err) => {
+                    eprintln!("could not create {}: {}", path, err);
+                    return 1;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let mut recording = match &args.record_video {
+        Some(path) => Recording::start(Path::new(path)),
+        None => Recording::default(),
+    };
+
+    let mut frame: u64 = 0;
+
+    loop {
+        if args.frames.is_some_and(|frames| frame >= frames) || movie_finished(args) {
+            break;
+        }
+
+        let (video, _) = EMU.run();
+        let audio = EMU.take_audio();
+
+        // There is no screen to show the messages of the core on.
+        for message in EMU.take_messages() {
+            println!("{}", message.text);
+        }
+
+        recording.push(video, &audio);
+
+        if let Some(wav) = &mut wav {
+            if let Err(err) = wav.write(&audio) {
+                eprintln!("could not write the audio: {}", err);
+                return 1;
+            }
+        }
+
+        // Cores may skip sending a frame when it didn't change, the last one is dumped again then.
+        if let (Some(dir), Some(pixels)) = (&args.dump_frames, EMU.frame()) {
+            if frame % args.dump_every == 0 {
+                let path = Path::new(dir).join(format!("{:06}.ppm", frame));
+                let (width, height) = EMU.frame_size();
+
+                if let Err(err) = write_ppm(&path, pixels, width, height) {
+                    eprintln!("could not write {}: {}", path.display(), err);
+                    return 1;
+                }
+            }
+        }
+
+        frame += 1;
+
+        if let Some((address, width, value)) = args.until {
+            if matches!(EMU.read_value(address, width), Ok(current) if current == value) {
+                println!("condition met after {} frames", frame);
+                break;
+            }
+        }
+    }
+
+    println!("ran {} frames", frame);
+
+    recording.stop();
+
+    if let Some(wav) = wav {
+        EMU.capture_audio(false);
+
+        if let Err(err) = wav.finish() {
+            eprintln!("could not write the audio: {}", err);
+            return 1;
+        }
+    }
+
+    if let Some(path) = &args.save_state {
+        if let Err(err) = EMU.write_state(Path::new(path)) {
+            eprintln!("could not write {}: {}", path, err);
+            return 1;
+        }
+    }
+
+    if args.screenshot {
+        match Screenshots::new(&args.screenshots).take(args.screenshot_scale) {
+            Ok(path) => println!("screenshot saved to {}", path.display()),
+            Err(message) => {
+                eprintln!("{}", message);
+                return 1;
+            }
+        }
+    }
+
+    EMU.stop_movie();
+
+    0
+}
+
+/// Whether the movie given with `--movie` was played to its end.
+fn movie_finished(args: &Args) -> bool {
+    if args.movie.is_none() {
+        return false;
+    }
+
+    match EMU.movie_status() {
+        Some((MovieMode::Playing { .. }, frame, len, _)) => frame >= len,
+        _ => true,
+    }
+}
+
+/// Write a frame as a binary PPM image.
+fn write_ppm(path: &Path, pixels: &[u32], width: usize, height: usize) -> std::io::Result<()> {
+    let mut out = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    out.reserve(width * height * 3);
+
+    let format = EMU.pixel_format();
+    for pixel in pixels.iter().take(width * height) {
+        out.extend_from_slice(&format.rgb(*pixel));
+    }
+
+    std::fs::File::create(path)?.write_all(&out)
+}
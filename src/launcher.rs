@@ -0,0 +1,675 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_pixels::prelude::*;
+
+use crate::bindings::gamepad_just_pressed;
+use crate::cli::Args;
+use crate::emulator::cores::{self, CoreInfo};
+use crate::emulator::hash;
+use crate::font;
+use crate::library::{self, Dat, Game};
+use crate::overlay::{self, Canvas};
+use crate::playlist::{self, Entry, Playlist};
+use crate::recorder::Recording;
+use crate::screenshot;
+use crate::EMU;
+
+/// Size of the launcher's screen.
+const WIDTH: usize = 320;
+const HEIGHT: usize = 240;
+/// Width of the list of games, the details of the selected one are on its right.
+const LIST_WIDTH: usize = 184;
+/// Largest box art shown.
+const THUMBNAIL_WIDTH: usize = 128;
+const THUMBNAIL_HEIGHT: usize = 112;
+
+/// The library, shown instead of a game when directories are given with `--library` or
+/// playlists with `--playlist` and no ROM, and when the game is closed from the pause menu.
+///
+/// Games are found by the extensions the installed cores load, and named after the DAT file
+/// given with `--dat` when they are in it. The recently played games and the playlists are
+/// lists of their own, Tab goes through them.
+#[derive(Resource, Default)]
+pub struct Launcher {
+    pub active: bool,
+    lists: Vec<List>,
+    /// Index of the list shown.
+    list: usize,
+    cores: Vec<CoreInfo>,
+    thumbnails: PathBuf,
+    cursor: usize,
+    /// Whether Return resumes from the auto state, R doing the opposite.
+    resume: bool,
+    /// Box art of the selected game, decoded once it is selected, as RGBA with its size.
+    thumbnail: Option<(PathBuf, Option<(Vec<u8>, usize, usize)>)>,
+}
+
+struct List {
+    name: String,
+    source: Source,
+    games: Vec<Game>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Source {
+    Library,
+    History,
+    /// A playlist given with `--playlist`, which games can be added to.
+    Playlist(PathBuf),
+}
+
+impl Launcher {
+    pub fn new(args: &Args) -> Launcher {
+        let mut cores = cores::installed(Path::new(&args.cores));
+        let mut lists = Vec::new();
+
+        if !args.libraries.is_empty() {
+            let mut extensions: Vec<String> = cores
+                .iter()
+                .flat_map(|core| core.extensions.iter().cloned())
+                .collect();
+            extensions.sort();
+            extensions.dedup();
+
+            let dat = args
+                .dat
+                .as_ref()
+                .and_then(|path| match std::fs::read_to_string(path) {
+                    Ok(text) => Some(Dat::parse(&text)),
+                    Err(err) => {
+                        println!("could not read {}: {}", path, err);
+                        None
+                    }
+                });
+
+            let games = library::scan(&args.libraries, &extensions, dat.as_ref());
+            println!(
+                "{} games in the library, for {} cores",
+                games.len(),
+                cores.len()
+            );
+
+            lists.push(List {
+                name: String::from("Library"),
+                source: Source::Library,
+                games,
+            });
+        }
+
+        lists.push(List {
+            name: String::from("Recently played"),
+            source: Source::History,
+            games: Vec::new(),
+        });
+
+        for path in &args.playlists {
+            let path = PathBuf::from(path);
+
+            lists.push(List {
+                name: path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                source: Source::Playlist(path),
+                games: Vec::new(),
+            });
+        }
+
+        for list in &mut lists {
+            list.reload();
+        }
+
+        // Cores the playlists name that aren't in the cores directory.
+        for path in lists
+            .iter()
+            .flat_map(|list| list.games.iter())
+            .filter_map(|game| game.core.clone())
+        {
+            if cores.iter().any(|core| core.path.as_ref() == Some(&path)) {
+                continue;
+            }
+
+            match cores::probe(&path) {
+                Ok(core) => cores.push(core),
+                Err(message) => println!("skipping the core {}: {}", path.display(), message),
+            }
+        }
+
+        Launcher {
+            active: args.launcher,
+            lists,
+            list: 0,
+            cores,
+            thumbnails: PathBuf::from(&args.thumbnails),
+            cursor: 0,
+            resume: args.auto_state,
+            thumbnail: None,
+        }
+    }
+
+    /// The games of the list shown.
+    fn games(&self) -> &[Game] {
+        self.lists
+            .get(self.list)
+            .map(|list| list.games.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn selected(&self) -> Option<&Game> {
+        self.games().get(self.cursor)
+    }
+
+    /// The core the game names, or the first that loads its extension.
+    fn core(&self, game: &Game) -> Option<&CoreInfo> {
+        match &game.core {
+            Some(path) => self
+                .cores
+                .iter()
+                .find(|core| core.path.as_ref() == Some(path)),
+            None => self.cores.iter().find(|core| core.supports(&game.path)),
+        }
+    }
+
+    /// Show the next list, or the previous one with `step` -1.
+    fn switch_list(&mut self, step: isize) {
+        let count = self.lists.len() as isize;
+        if count == 0 {
+            return;
+        }
+
+        self.list = (self.list as isize + step).rem_euclid(count) as usize;
+        self.cursor = 0;
+    }
+
+    /// Load the selected game, resuming from its auto state when `resume` is set. Returns the
+    /// title of the game when it is running.
+    fn launch(&mut self, resume: bool) -> Option<String> {
+        let game = self.selected()?;
+
+        let Some(core) = self.core(game) else {
+            EMU.notify(format!("no core loads {}", game.path.display()));
+            return None;
+        };
+
+        // The running core is swapped in place when the game needs another one.
+        if !EMU.is_core_loaded() || EMU.core_path() != core.path {
+            if let Err(err) = EMU.load_core(core.path.as_deref()) {
+                EMU.notify(format!("could not start {}: {}", core.name, err));
+                return None;
+            }
+        }
+
+        if let Err(err) = EMU.load_rom(&game.path.to_string_lossy(), &[]) {
+            EMU.notify(format!("could not load {}: {}", game.title, err));
+            return None;
+        }
+
+        EMU.notify(format!("playing {}", game.title));
+        if resume {
+            EMU.load_auto_state();
+        }
+
+        let title = game.title.clone();
+        self.active = false;
+
+        Some(title)
+    }
+
+    /// Add the selected game to the first playlist given with `--playlist`.
+    fn add_to_playlist(&mut self) {
+        let Some(game) = self.selected() else {
+            return;
+        };
+        let Some(index) = self
+            .lists
+            .iter()
+            .position(|list| matches!(list.source, Source::Playlist(_)))
+        else {
+            EMU.notify("no playlist to add to, give one with --playlist");
+            return;
+        };
+        let Source::Playlist(path) = self.lists[index].source.clone() else {
+            return;
+        };
+
+        let crc32 = match game.crc32 {
+            Some(crc32) => crc32,
+            None => match std::fs::read(&game.path) {
+                Ok(data) => hash::crc32(&data),
+                Err(err) => {
+                    EMU.notify(format!("could not read {}: {}", game.path.display(), err));
+                    return;
+                }
+            },
+        };
+        let entry = Entry {
+            path: game.path.clone(),
+            core_path: game.core.clone(),
+            label: game.title.clone(),
+            crc32,
+            playtime: 0,
+            last_played: None,
+        };
+        let title = game.title.clone();
+        let name = self.lists[index].name.clone();
+
+        let result = Playlist::load(&path).and_then(|mut playlist| {
+            if !playlist.add(entry) {
+                return Ok(false);
+            }
+
+            playlist.save(&path).map(|()| true)
+        });
+        match result {
+            Ok(true) => {
+                self.lists[index].reload();
+                EMU.notify(format!("added {} to {}", title, name));
+            }
+            Ok(false) => EMU.notify(format!("{} is already in {}", title, name)),
+            Err(err) => EMU.notify(format!("could not update {}: {}", path.display(), err)),
+        }
+    }
+
+    /// Unload the game and go back to the library, returning false when there is no library to
+    /// go back to.
+    pub fn close_game(&mut self, session: &mut Session, recording: &mut Recording) -> bool {
+        if self.lists.is_empty() {
+            return false;
+        }
+
+        recording.stop();
+        session.end();
+        EMU.unload_game();
+
+        // The game just played goes to the top of the history, with its playtime.
+        for list in &mut self.lists {
+            list.reload();
+        }
+        self.active = true;
+
+        true
+    }
+
+    /// The box art of the selected game, decoded the first time it's asked for.
+    fn thumbnail(&mut self) -> Option<&(Vec<u8>, usize, usize)> {
+        let path = self.selected()?.path.clone();
+
+        if self.thumbnail.as_ref().map(|(selected, _)| selected) != Some(&path) {
+            let image = self
+                .selected()
+                .and_then(|game| library::thumbnail_path(&self.thumbnails, game))
+                .and_then(|path| decode_png(&path));
+
+            self.thumbnail = Some((path, image));
+        }
+
+        self.thumbnail.as_ref()?.1.as_ref()
+    }
+}
+
+impl List {
+    /// Read the playlist again, the library is only scanned once.
+    fn reload(&mut self) {
+        let path = match &self.source {
+            Source::Library => return,
+            Source::History => PathBuf::from(playlist::HISTORY_FILE),
+            Source::Playlist(path) => path.clone(),
+        };
+
+        let playlist = match Playlist::load(&path) {
+            Ok(playlist) => playlist,
+            Err(err) => {
+                println!("could not read {}: {}", path.display(), err);
+                Playlist::default()
+            }
+        };
+
+        // The history keeps every game played for its playtime, but only lists the last ones.
+        let len = match self.source {
+            Source::History => playlist::HISTORY_LENGTH,
+            _ => usize::MAX,
+        };
+
+        let stats = playlist::stats();
+        self.games = playlist
+            .items
+            .into_iter()
+            .take(len)
+            .map(|entry| Game {
+                title: entry.label,
+                known: false,
+                last_played: stats.get(&entry.path).map(|(last_played, _)| *last_played),
+                playtime: entry.playtime,
+                core: entry.core_path,
+                crc32: Some(entry.crc32),
+                path: entry.path,
+            })
+            .collect();
+    }
+}
+
+/// Time played in the game loaded, added to the history's and the playlists' playtime when it
+/// stops, and its auto state.
+#[derive(Resource, Default)]
+pub struct Session {
+    /// Playlists given with `--playlist`.
+    playlists: Vec<PathBuf>,
+    game: Option<(Entry, Instant)>,
+    /// Whether the auto state is written when the game ends.
+    auto_state: bool,
+    /// Time between auto states while playing, given with `--auto-state-every`.
+    auto_state_every: Option<Duration>,
+    auto_saved: Option<Instant>,
+}
+
+impl Session {
+    pub fn new(args: &Args) -> Session {
+        Session {
+            playlists: args.playlists.iter().map(PathBuf::from).collect(),
+            game: None,
+            auto_state: args.auto_state,
+            auto_state_every: args
+                .auto_state_every
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+            auto_saved: None,
+        }
+    }
+
+    /// Start counting the time played in the game loaded, named `label`.
+    pub fn start(&mut self, label: &str) {
+        let Some((path, crc32)) = EMU.content() else {
+            return;
+        };
+
+        let entry = Entry {
+            path,
+            core_path: EMU.core_path(),
+            label: label.to_string(),
+            crc32,
+            playtime: 0,
+            last_played: None,
+        };
+        self.game = Some((entry, Instant::now()));
+        self.auto_saved = Some(Instant::now());
+    }
+
+    /// The game is about to be unloaded: write its auto state and count the time played.
+    pub fn end(&mut self) {
+        if self.game.is_some() && self.auto_state {
+            EMU.save_auto_state();
+        }
+
+        self.stop();
+    }
+
+    pub fn stop(&mut self) {
+        if let Some((entry, started)) = self.game.take() {
+            let seconds = started.elapsed().as_secs();
+
+            playlist::record_play(&entry, seconds, &self.playlists);
+        }
+    }
+}
+
+pub fn launcher(
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut launcher: ResMut<Launcher>,
+    mut session: ResMut<Session>,
+) {
+    if !launcher.active {
+        return;
+    }
+
+    let gamepad = |button| gamepad_just_pressed(&gamepads, &gamepad_buttons, button);
+
+    if keys.just_pressed(KeyCode::Tab) || gamepad(GamepadButtonType::RightTrigger) {
+        let step = if keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight) {
+            -1
+        } else {
+            1
+        };
+        launcher.switch_list(step);
+        return;
+    } else if gamepad(GamepadButtonType::LeftTrigger) {
+        launcher.switch_list(-1);
+        return;
+    }
+
+    let count = launcher.games().len();
+    if count == 0 {
+        return;
+    }
+
+    let page = visible_rows();
+    let launched = if keys.just_pressed(KeyCode::Up) || gamepad(GamepadButtonType::DPadUp) {
+        launcher.cursor = (launcher.cursor + count - 1) % count;
+        None
+    } else if keys.just_pressed(KeyCode::Down) || gamepad(GamepadButtonType::DPadDown) {
+        launcher.cursor = (launcher.cursor + 1) % count;
+        None
+    } else if keys.just_pressed(KeyCode::PageUp) || gamepad(GamepadButtonType::DPadLeft) {
+        launcher.cursor = launcher.cursor.saturating_sub(page);
+        None
+    } else if keys.just_pressed(KeyCode::PageDown) || gamepad(GamepadButtonType::DPadRight) {
+        launcher.cursor = (launcher.cursor + page).min(count - 1);
+        None
+    } else if keys.just_pressed(KeyCode::P) || gamepad(GamepadButtonType::North) {
+        launcher.add_to_playlist();
+        None
+    } else if keys.just_pressed(KeyCode::R) || gamepad(GamepadButtonType::West) {
+        let resume = !launcher.resume;
+        launcher.launch(resume)
+    } else if keys.just_pressed(KeyCode::Return) || gamepad(GamepadButtonType::South) {
+        let resume = launcher.resume;
+        launcher.launch(resume)
+    } else {
+        None
+    };
+
+    if let Some(title) = launched {
+        session.stop();
+        session.start(&title);
+    }
+}
+
+/// End the game when the window closes, before it is unloaded.
+pub fn end_session_on_exit(mut exits: EventReader<AppExit>, mut session: ResMut<Session>) {
+    if exits.iter().next().is_some() {
+        session.end();
+    }
+}
+
+/// Write the auto state every `--auto-state-every` minutes while a game runs.
+pub fn auto_save(mut session: ResMut<Session>) {
+    let (Some(every), Some(saved)) = (session.auto_state_every, session.auto_saved) else {
+        return;
+    };
+
+    if session.game.is_some() && saved.elapsed() >= every {
+        EMU.save_auto_state();
+        session.auto_saved = Some(Instant::now());
+    }
+}
+
+/// Rows of games, under the title and over the keys.
+fn visible_rows() -> usize {
+    (HEIGHT - 16 - font::LINE_HEIGHT - 4) / font::LINE_HEIGHT
+}
+
+pub fn draw_launcher(mut wrapper_query: Query<&mut PixelsWrapper>, mut launcher: ResMut<Launcher>) {
+    if !launcher.active {
+        return;
+    }
+
+    let Ok(mut wrapper) = wrapper_query.get_single_mut() else {
+        return;
+    };
+
+    wrapper
+        .pixels
+        .resize_buffer(WIDTH as u32, HEIGHT as u32)
+        .unwrap();
+    let mut canvas = Canvas::new(wrapper.pixels.frame_mut(), WIDTH, HEIGHT);
+    canvas.fill_rect(0, 0, WIDTH, HEIGHT, [0x00, 0x00, 0x00, 0xFF]);
+
+    let Some(list) = launcher.lists.get(launcher.list) else {
+        return;
+    };
+
+    let title = format!("{} - {} games", list.name, list.games.len());
+    canvas.text(4, 3, &title, overlay::ACCENT);
+    if launcher.lists.len() > 1 {
+        let hint = "Tab: next list";
+        let x = WIDTH - 4 - hint.len() * font::GLYPH_WIDTH;
+        canvas.text(x, 3, hint, overlay::DIM);
+    }
+
+    let keys = if launcher.resume {
+        "Return: resume  R: restart  P: add to playlist"
+    } else {
+        "Return: play  R: resume  P: add to playlist"
+    };
+    canvas.text(4, HEIGHT - font::LINE_HEIGHT - 2, keys, overlay::DIM);
+
+    if list.games.is_empty() {
+        let message = match list.source {
+            Source::Library => "No games in the library directories.",
+            Source::History => "No game was played yet.",
+            Source::Playlist(_) => "The playlist is empty, P adds the selected game.",
+        };
+        canvas.text(4, 16, message, overlay::DIM);
+        return;
+    }
+
+    let rows = visible_rows();
+    let first = (launcher.cursor + 1).saturating_sub(rows);
+    let columns = (LIST_WIDTH - 4) / font::GLYPH_WIDTH - 2;
+
+    for (line, (index, game)) in list
+        .games
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(rows)
+        .enumerate()
+    {
+        let y = 16 + line * font::LINE_HEIGHT;
+        let selected = index == launcher.cursor;
+
+        if selected {
+            canvas.text(4, y, ">", overlay::ACCENT);
+        }
+
+        let title: String = game.title.chars().take(columns).collect();
+        let color = if selected {
+            overlay::TEXT
+        } else {
+            overlay::DIM
+        };
+        canvas.text(4 + 2 * font::GLYPH_WIDTH, y, &title, color);
+    }
+
+    // The selected game, on the right.
+    let x = LIST_WIDTH + 4;
+    let mut y = 16;
+
+    if let Some((rgba, width, height)) = launcher.thumbnail() {
+        canvas.image(x, y, rgba, *width, *height);
+        y += height + 4;
+    }
+
+    let Some(game) = launcher.selected() else {
+        return;
+    };
+    let last_played = match game.last_played {
+        Some(seconds) => {
+            let [year, month, day, ..] = screenshot::civil(seconds as i64);
+            format!("{:04}-{:02}-{:02}", year, month, day)
+        }
+        None => String::from("never"),
+    };
+    let playtime = format!("{}h {:02}m", game.playtime / 3600, game.playtime / 60 % 60);
+    let core = launcher
+        .core(game)
+        .map(|core| format!("{} {}", core.name, core.version))
+        .unwrap_or_else(|| String::from("none"));
+
+    let details = [
+        ("Last played", last_played),
+        ("Playtime", playtime),
+        ("Core", core),
+        match (&launcher.lists[launcher.list].source, game.crc32) {
+            (Source::Library, _) => (
+                "DAT",
+                String::from(if game.known { "matched" } else { "unknown" }),
+            ),
+            (_, crc32) => (
+                "CRC-32",
+                crc32
+                    .map(|crc32| format!("{:08x}", crc32))
+                    .unwrap_or_default(),
+            ),
+        },
+    ];
+
+    let columns = (WIDTH - x) / font::GLYPH_WIDTH;
+    for (label, value) in details {
+        let value: String = value.chars().take(columns).collect();
+
+        canvas.text(x, y, label, overlay::DIM);
+        canvas.text(x, y + font::LINE_HEIGHT, &value, overlay::TEXT);
+        y += 2 * font::LINE_HEIGHT + 2;
+    }
+}
+
+/// Decode a PNG into RGBA, scaled down with nearest neighbour to fit the thumbnail's box.
+fn decode_png(path: &Path) -> Option<(Vec<u8>, usize, usize)> {
+    let mut decoder = png::Decoder::new(std::fs::File::open(path).ok()?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().ok()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).ok()?;
+    buffer.truncate(info.buffer_size());
+    let (width, height) = (info.width as usize, info.height as usize);
+
+    let rgba: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|p| [*p, *p, *p, 0xFF]).collect(),
+        png::ColorType::Indexed => return None,
+    };
+
+    // Fit the box, never scaling up.
+    let scale = (width as f32 / THUMBNAIL_WIDTH as f32)
+        .max(height as f32 / THUMBNAIL_HEIGHT as f32)
+        .max(1.0);
+    let (out_width, out_height) = (
+        ((width as f32 / scale) as usize).max(1),
+        ((height as f32 / scale) as usize).max(1),
+    );
+
+    let mut out = Vec::with_capacity(out_width * out_height * 4);
+    for y in 0..out_height {
+        let source_y = (y as f32 * scale) as usize;
+
+        for x in 0..out_width {
+            let offset = (source_y * width + (x as f32 * scale) as usize) * 4;
+            out.extend_from_slice(&rgba[offset..offset + 4]);
+        }
+    }
+
+    Some((out, out_width, out_height))
+}
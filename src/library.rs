@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::emulator::hash;
+use crate::playlist;
+
+/// The CRC-32 and SHA-1 of the games matched against a DAT, kept in the working directory.
+const HASHES_FILE: &str = "library.hashes";
+
+/// A game found in the library directories.
+pub struct Game {
+    pub path: PathBuf,
+    /// Name of the game in the DAT file, or its file name.
+    pub title: String,
+    /// Whether the game was found in the DAT file.
+    pub known: bool,
+    /// Seconds since the epoch.
+    pub last_played: Option<u64>,
+    /// Seconds played.
+    pub playtime: u64,
+    /// Shared library of the core it runs on, `None` to pick one by its extension.
+    pub core: Option<PathBuf>,
+    /// CRC-32 of the ROM, when it's known without reading it.
+    pub crc32: Option<u32>,
+}
+
+/// Games of a No-Intro style DAT file, by CRC-32.
+#[derive(Default)]
+pub struct Dat {
+    /// Name and SHA-1 of the games.
+    games: HashMap<u32, Vec<(String, Option<String>)>>,
+}
+
+impl Dat {
+    /// Read the `<game name="...">` entries of a Logiqx XML DAT and the `<rom crc="..." sha1="...">`
+    /// they hold.
+    pub fn parse(text: &str) -> Dat {
+        let mut dat = Dat::default();
+        let mut game = None;
+
+        for tag in text.split('<').skip(1) {
+            let tag = tag.split('>').next().unwrap_or_default();
+
+            if tag.starts_with("game ") || tag.starts_with("machine ") {
+                game = attribute(tag, "name").map(unescape);
+            } else if tag.starts_with("rom ") {
+                let (Some(name), Some(crc)) = (&game, attribute(tag, "crc")) else {
+                    continue;
+                };
+                let Ok(crc) = u32::from_str_radix(crc, 16) else {
+                    continue;
+                };
+
+                let sha1 = attribute(tag, "sha1").map(|sha1| sha1.to_ascii_lowercase());
+                dat.games.entry(crc).or_default().push((name.clone(), sha1));
+            }
+        }
+
+        dat
+    }
+
+    /// The name of the game with these hashes. The SHA-1 settles CRC-32 collisions when the DAT
+    /// has them.
+    pub fn identify(&self, crc32: u32, sha1: &str) -> Option<&str> {
+        let candidates = self.games.get(&crc32)?;
+
+        if candidates.iter().all(|(_, sha1)| sha1.is_none()) {
+            return candidates.first().map(|(name, _)| name.as_str());
+        }
+
+        candidates
+            .iter()
+            .find(|(_, candidate)| candidate.as_deref() == Some(sha1))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// The value of `name="..."` in the tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+
+    Some(&tag[start..start + len])
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&amp;", "&")
+        .replace("&apos;", "'")
+        .replace("&quot;", "\"")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+}
+
+/// Find the games with one of `extensions` in `dirs` and their subdirectories, sorted by title.
+pub fn scan(dirs: &[String], extensions: &[String], dat: Option<&Dat>) -> Vec<Game> {
+    let mut paths = Vec::new();
+    for dir in dirs {
+        walk(Path::new(dir), &mut paths);
+    }
+
+    paths.retain(|path| {
+        path.extension().is_some_and(|extension| {
+            extensions.contains(&extension.to_string_lossy().to_ascii_lowercase())
+        })
+    });
+
+    let stats = playlist::stats();
+    let mut hashes = dat.map(|_| HashCache::load(Path::new(HASHES_FILE)));
+    let mut games: Vec<Game> = paths
+        .into_iter()
+        .map(|path| {
+            let hashed = hashes.as_mut().and_then(|hashes| hashes.get(&path));
+            let name = match (dat, &hashed) {
+                (Some(dat), Some((crc32, sha1))) => dat.identify(*crc32, sha1).map(String::from),
+                _ => None,
+            };
+            let (last_played, playtime) = match stats.get(&path) {
+                Some((last_played, playtime)) => (Some(*last_played), *playtime),
+                None => (None, 0),
+            };
+
+            Game {
+                title: name.clone().unwrap_or_else(|| {
+                    path.file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                }),
+                known: name.is_some(),
+                path,
+                last_played,
+                playtime,
+                core: None,
+                crc32: hashed.map(|(crc32, _)| crc32),
+            }
+        })
+        .collect();
+
+    if let Some(hashes) = hashes {
+        hashes.save();
+    }
+
+    games.sort_by_key(|game| game.title.to_lowercase());
+    games
+}
+
+/// The hashes of the ROMs, so that scanning a library against a DAT only reads the files added or
+/// changed since the last scan.
+///
+/// Files are known by their path, size and modification time, one per line of `HASHES_FILE`.
+struct HashCache {
+    /// Where the cache is kept.
+    file: PathBuf,
+    /// Size, modification time in seconds since the epoch, CRC-32 and SHA-1, by path.
+    entries: HashMap<PathBuf, (u64, u64, u32, String)>,
+    changed: bool,
+}
+
+impl HashCache {
+    fn load(file: &Path) -> HashCache {
+        let text = std::fs::read_to_string(file).unwrap_or_default();
+
+        let entries = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(5, '\t');
+                let size = fields.next()?.parse().ok()?;
+                let modified = fields.next()?.parse().ok()?;
+                let crc32 = u32::from_str_radix(fields.next()?, 16).ok()?;
+                let sha1 = fields.next()?.to_string();
+
+                Some((PathBuf::from(fields.next()?), (size, modified, crc32, sha1)))
+            })
+            .collect();
+
+        HashCache {
+            file: file.to_path_buf(),
+            entries,
+            changed: false,
+        }
+    }
+
+    /// The CRC-32 and SHA-1 of the file at `path`, read again only when it changed.
+    fn get(&mut self, path: &Path) -> Option<(u32, String)> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        if let Some((size, cached_modified, crc32, sha1)) = self.entries.get(path) {
+            if *size == metadata.len() && *cached_modified == modified {
+                return Some((*crc32, sha1.clone()));
+            }
+        }
+
+        let data = std::fs::read(path).ok()?;
+        let (crc32, sha1) = (hash::crc32(&data), hash::sha1(&data));
+
+        self.entries.insert(
+            path.to_path_buf(),
+            (metadata.len(), modified, crc32, sha1.clone()),
+        );
+        self.changed = true;
+
+        Some((crc32, sha1))
+    }
+
+    /// Write the entries down when some were added, forgetting the files that are gone.
+    fn save(mut self) {
+        if !self.changed {
+            return;
+        }
+        self.entries.retain(|path, _| path.is_file());
+
+        let text: String = self
+            .entries
+            .iter()
+            .map(|(path, (size, modified, crc32, sha1))| {
+                format!(
+                    "{}\t{}\t{:08x}\t{}\t{}\n",
+                    size,
+                    modified,
+                    crc32,
+                    sha1,
+                    path.display()
+                )
+            })
+            .collect();
+
+        if let Err(err) = std::fs::write(&self.file, text) {
+            println!("could not save the hashes of the library: {}", err);
+        }
+    }
+}
+
+fn walk(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    // Links are only followed to files, a link to a parent directory would recurse forever.
+    for entry in entries.filter_map(Result::ok) {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+
+        if file_type.is_dir() {
+            walk(&path, paths);
+        } else if path.is_file() {
+            paths.push(path);
+        }
+    }
+}
+
+/// The box art of a game in `dir`, named after its title as libretro's thumbnails are, or after
+/// its file.
+pub fn thumbnail_path(dir: &Path, game: &Game) -> Option<PathBuf> {
+    // libretro-thumbnails replaces the characters file systems don't like.
+    let title: String = game
+        .title
+        .chars()
+        .map(|c| if "&*/:`<>?\\|\"".contains(c) { '_' } else { c })
+        .collect();
+    let stem = game.path.file_stem()?.to_string_lossy().into_owned();
+
+    [title, stem]
+        .iter()
+        .map(|name| dir.join(format!("{}.png", name)))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAT: &str = r#"<?xml version="1.0"?>
+<datafile>
+    <game name="Alpha &amp; Omega (World)">
+        <rom name="alpha.gba" size="3" crc="352441c2" sha1="a9993e364706816aba3e25717850c26c9cd0d89d"/>
+    </game>
+    <game name="Alpha (Beta)">
+        <rom name="beta.gba" size="3" crc="352441C2" sha1="0000000000000000000000000000000000000000"/>
+    </game>
+    <machine name="Empty (World)">
+        <rom name="empty.gba" size="0" crc="00000000"/>
+    </machine>
+</datafile>"#;
+
+    /// A directory of its own under the temporary one, empty.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("advanced-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn identifies_games_by_crc32_and_sha1() {
+        let dat = Dat::parse(DAT);
+
+        // Both games share a CRC-32, the SHA-1 tells them apart.
+        assert_eq!(
+            dat.identify(hash::crc32(b"abc"), &hash::sha1(b"abc")),
+            Some("Alpha & Omega (World)")
+        );
+        assert_eq!(
+            dat.identify(0x352441C2, "0000000000000000000000000000000000000000"),
+            Some("Alpha (Beta)")
+        );
+        assert_eq!(dat.identify(0x352441C2, "ffff"), None);
+
+        // Without a SHA-1 in the DAT the CRC-32 is enough.
+        assert_eq!(dat.identify(0, "anything"), Some("Empty (World)"));
+        assert_eq!(dat.identify(0x1234, ""), None);
+    }
+
+    #[test]
+    fn caches_hashes_until_the_file_changes() {
+        let dir = temp_dir("hashes");
+        let (rom, file) = (dir.join("game.gba"), dir.join("library.hashes"));
+        std::fs::write(&rom, b"abc").unwrap();
+
+        let mut cache = HashCache::load(&file);
+        let hashes = (hash::crc32(b"abc"), hash::sha1(b"abc"));
+        assert_eq!(cache.get(&rom), Some(hashes.clone()));
+        assert!(cache.changed);
+        cache.save();
+
+        let mut cache = HashCache::load(&file);
+        assert_eq!(cache.get(&rom), Some(hashes));
+        assert!(!cache.changed);
+
+        std::fs::write(&rom, b"abcd").unwrap();
+        assert_eq!(
+            cache.get(&rom),
+            Some((hash::crc32(b"abcd"), hash::sha1(b"abcd")))
+        );
+        assert!(cache.changed);
+        assert_eq!(cache.get(&dir.join("missing.gba")), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_follow_links_to_directories() {
+        let dir = temp_dir("walk");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub").join("game.gba"), b"").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub").join("loop")).unwrap();
+        std::os::unix::fs::symlink(dir.join("sub").join("game.gba"), dir.join("link.gba")).unwrap();
+
+        let mut paths = Vec::new();
+        walk(&dir, &mut paths);
+        paths.sort();
+
+        assert_eq!(
+            paths,
+            [dir.join("link.gba"), dir.join("sub").join("game.gba")]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
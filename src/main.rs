@@ -1,11 +1,41 @@
 use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
 use bevy_pixels::prelude::*;
 
+use emulator::rotation::Rotation;
+
+mod bindings;
+mod cli;
+mod console;
 mod emulator;
+mod filter;
+mod font;
+mod headless;
+mod launcher;
+mod library;
+mod menu;
+mod osd;
+mod overlay;
+mod playlist;
+mod recorder;
+mod screenshot;
+mod watch;
+mod wav;
 
 static EMU: emulator::Emualtor = emulator::Emualtor {};
 
-fn draw(mut wrapper_query: Query<&mut PixelsWrapper>) {
+fn draw(
+    mut wrapper_query: Query<&mut PixelsWrapper>,
+    mut recording: ResMut<recorder::Recording>,
+    mut filters: ResMut<filter::Filters>,
+    menu: Res<menu::Menu>,
+    launcher: Res<launcher::Launcher>,
+) {
+    // No game is loaded yet, the launcher draws instead.
+    if launcher.active {
+        return;
+    }
+
     // Query the `PixelsWrapper` component that owns an instance of `Pixels` for the given window.
     let Ok(mut wrapper) = wrapper_query.get_single_mut() else {
         return;
@@ -13,77 +43,273 @@ fn draw(mut wrapper_query: Query<&mut PixelsWrapper>) {
 
     let _ = wrapper.pixels.surface_texture_format();
 
-    // set the default resolution of the GBA screen.
-    wrapper.pixels.resize_buffer(240, 160).unwrap();
-
-    // Get a mutable slice for the pixel buffer.
-    let frame: &mut [u8] = wrapper.pixels.frame_mut();
+    // The game is frozen while the menu is open, its last frame stays under the menu.
+    let video = if menu.is_open() {
+        EMU.frame()
+    } else {
+        let (video, _) = EMU.run();
+        recording.push(video, &EMU.take_audio());
 
-    let (video, _) = EMU.run();
+        // A duped frame is drawn again from the core's last one: the overlays are blended over
+        // the pixels buffer, so keeping it as is would blend them a second time.
+        video.or_else(|| EMU.frame())
+    };
 
     match video {
         Some(buffer) => unsafe {
-            let slice = std::slice::from_raw_parts(buffer.as_ptr() as *const u8, buffer.len() * 4);
+            let (width, height) = EMU.frame_size();
+            let rotation = EMU.rotation();
+
+            // Without filters nor rotation the frame is copied as is, without going through a new
+            // buffer. Filters run on the rotated frame, as it is shown.
+            let filtered;
+            let (pixels, width, height) = if filters.is_empty() && rotation == Rotation::None {
+                (buffer, width, height)
+            } else {
+                let (pixels, width, height) = rotation.rotate(buffer, width, height);
+                filtered = filters.apply(filter::Image {
+                    pixels,
+                    width,
+                    height,
+                });
+
+                (filtered.pixels.as_slice(), filtered.width, filtered.height)
+            };
+
+            // The buffer follows the size of the filtered frame, the GBA screen without filters.
+            wrapper
+                .pixels
+                .resize_buffer(width as u32, height as u32)
+                .unwrap();
+
+            // Get a mutable slice for the pixel buffer.
+            let frame: &mut [u8] = wrapper.pixels.frame_mut();
+            let slice = std::slice::from_raw_parts(pixels.as_ptr() as *const u8, pixels.len() * 4);
 
             frame.copy_from_slice(slice);
         },
-        None => {
-            println!("We don't have a buffer to display");
-        }
+        // The core hasn't sent a frame yet.
+        None => {}
     }
 }
 
-fn input(keys: Res<Input<KeyCode>>) {
+fn input(
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    bindings: Res<bindings::Bindings>,
+    watches: Res<watch::Watches>,
+) {
     let mut buttons = [0 as i16; 16];
 
+    // The keyboard is typing a watch in.
+    if watches.is_editing() {
+        EMU.push_buttons(Vec::from(buttons));
+        return;
+    }
+
     //   BY SELECT START   UDLR AXLR L2 R2 L3 R3
     //  [00 0      0       0000 0000 0  0  0  0]
 
-    if keys.pressed(KeyCode::A) {
-        buttons[emulator::DEVICE_ID_JOYPAD_A as usize] = 1;
+    for binding in &bindings.buttons {
+        let on_gamepad = gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(gamepad, binding.gamepad)));
+
+        if on_gamepad || binding.keys.iter().any(|key| keys.pressed(*key)) {
+            buttons[binding.id as usize] = 1;
+        }
     }
-    if keys.pressed(KeyCode::S) {
-        buttons[emulator::DEVICE_ID_JOYPAD_B as usize] = 1;
+
+    EMU.push_buttons(Vec::from(buttons));
+}
+
+/// Toggle the first nine cheats of the game with the number keys.
+fn cheat_hotkeys(keys: Res<Input<KeyCode>>, watches: Res<watch::Watches>) {
+    const CHEAT_KEYS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+
+    for (index, key) in CHEAT_KEYS.iter().enumerate() {
+        if !keys.just_pressed(*key) || watches.is_editing() {
+            continue;
+        }
+
+        if let Some(enabled) = EMU.toggle_cheat(index) {
+            let Some(cheat) = EMU.cheat(index) else {
+                continue;
+            };
+            println!(
+                "cheat {} ({:?}) {}: {}",
+                index + 1,
+                cheat.kind(),
+                if enabled { "enabled" } else { "disabled" },
+                cheat.description
+            );
+        }
     }
-    if keys.pressed(KeyCode::Return) {
-        buttons[emulator::DEVICE_ID_JOYPAD_START as usize] = 1;
+}
+
+/// Save the state with F5 and load it back with F9.
+fn state_hotkeys(keys: Res<Input<KeyCode>>) {
+    if keys.just_pressed(KeyCode::F5) {
+        EMU.save_state();
     }
-    if keys.pressed(KeyCode::Space) {
-        buttons[emulator::DEVICE_ID_JOYPAD_SELECT as usize] = 1;
+    if keys.just_pressed(KeyCode::F9) {
+        EMU.load_state();
     }
-    if keys.pressed(KeyCode::Up) {
-        buttons[emulator::DEVICE_ID_JOYPAD_UP as usize] = 1;
+}
+
+/// Write the movie and the video being recorded before the window goes away.
+fn stop_recordings_on_exit(
+    mut exits: EventReader<bevy::app::AppExit>,
+    mut recording: ResMut<recorder::Recording>,
+) {
+    if exits.iter().next().is_some() {
+        EMU.stop_movie();
+        recording.stop();
+        // Writes the save RAM of the game.
+        EMU.unload_core();
     }
-    if keys.pressed(KeyCode::Down) {
-        buttons[emulator::DEVICE_ID_JOYPAD_DOWN as usize] = 1;
+}
+
+/// Go through the color profiles with F4.
+fn color_hotkeys(keys: Res<Input<KeyCode>>) {
+    if keys.just_pressed(KeyCode::F4) {
+        let (profile, gamma) = EMU.color_profile();
+
+        EMU.set_color_profile(profile.next(), gamma);
+        EMU.notify(format!("color profile: {}", profile.next().name()));
     }
-    if keys.pressed(KeyCode::Left) {
-        buttons[emulator::DEVICE_ID_JOYPAD_LEFT as usize] = 1;
+}
+
+/// Hand the mouse to the core as a pointer and a lightgun, on the screen as it is shown.
+fn pointer(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    wrapper_query: Query<&PixelsWrapper>,
+    buttons: Res<Input<MouseButton>>,
+) {
+    let (Ok(window), Ok(wrapper)) = (windows.get_single(), wrapper_query.get_single()) else {
+        return;
+    };
+
+    let size = wrapper.pixels.context().texture_extent;
+    let position = window
+        .physical_cursor_position()
+        .and_then(|cursor| wrapper.pixels.window_pos_to_pixel(cursor.into()).ok())
+        .map(|(x, y)| {
+            // From the pixel of the buffer to -0x7FFF at the top left and 0x7FFF at the bottom
+            // right, as libretro counts.
+            let scale = |position: usize, size: u32| {
+                ((position as f32 + 0.5) / size as f32 * 2.0 - 1.0) * 0x7FFF as f32
+            };
+
+            (scale(x, size.width) as i16, scale(y, size.height) as i16)
+        });
+
+    EMU.set_pointer(position, buttons.pressed(MouseButton::Left));
+}
+
+/// Swap the width and height of the window when the screen turns a quarter.
+fn rotate_window(mut windows: Query<&mut Window, With<PrimaryWindow>>, mut shown: Local<Rotation>) {
+    let rotation = EMU.rotation();
+    if rotation == *shown {
+        return;
+    }
+
+    if rotation.swaps_axes() != shown.swaps_axes() {
+        if let Ok(mut window) = windows.get_single_mut() {
+            let (width, height) = (window.resolution.width(), window.resolution.height());
+            window.resolution.set(height, width);
+        }
     }
-    if keys.pressed(KeyCode::Right) {
-        buttons[emulator::DEVICE_ID_JOYPAD_RIGHT as usize] = 1;
+
+    println!("screen rotated {} degrees", rotation.degrees());
+    *shown = rotation;
+}
+
+fn main() {
+    let args = cli::Args::parse();
+
+    match &args.core {
+        Some(path) => {
+            if let Err(err) = EMU.load_core(Some(std::path::Path::new(path))) {
+                eprintln!("could not load {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+        None => EMU.init(),
+    }
+
+    // Headless runs start from the same save RAM every time, whatever the last one left.
+    EMU.set_sram_files(!args.headless);
+
+    if !args.launcher {
+        if let Err(err) = EMU.load_rom(&args.rom, &args.patches) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+
+        // Movies start from their own state, and headless runs from power-on.
+        if args.auto_state && !args.headless && args.movie.is_none() && args.record.is_none() {
+            EMU.load_auto_state();
+        }
     }
+    EMU.set_color_profile(args.color, args.gamma);
+    EMU.set_rotation(args.rotation);
 
-    // vim buttons to arrow keys
-    if keys.pressed(KeyCode::J) {
-        buttons[emulator::DEVICE_ID_JOYPAD_DOWN as usize] = 1;
+    for path in &args.cheat_files {
+        if let Err(err) = EMU.load_cheats(path) {
+            eprintln!("could not read {}: {}", path, err);
+            std::process::exit(1);
+        }
     }
-    if keys.pressed(KeyCode::K) {
-        buttons[emulator::DEVICE_ID_JOYPAD_UP as usize] = 1;
+    for code in &args.cheat_codes {
+        EMU.add_cheat("", code);
     }
-    if keys.pressed(KeyCode::H) {
-        buttons[emulator::DEVICE_ID_JOYPAD_LEFT as usize] = 1;
+
+    if let Some(path) = &args.movie {
+        if let Err(message) = EMU.play_movie(path, true) {
+            eprintln!("could not play {}: {}", path, message);
+            std::process::exit(1);
+        }
+    } else if let Some(path) = &args.record {
+        if let Err(message) = EMU.record_movie(path, false) {
+            eprintln!("could not record {}: {}", path, message);
+            std::process::exit(1);
+        }
     }
-    if keys.pressed(KeyCode::L) {
-        buttons[emulator::DEVICE_ID_JOYPAD_RIGHT as usize] = 1;
+
+    let filters = match filter::Filters::parse(&args.filter) {
+        Ok(filters) => filters,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+
+    if args.headless {
+        let code = headless::run(&args);
+        EMU.unload_core();
+        std::process::exit(code);
     }
 
-    EMU.push_buttons(Vec::from(buttons));
-}
+    EMU.set_run_ahead(args.run_ahead, args.run_ahead_instance);
 
-fn main() {
-    EMU.init();
-    EMU.load_rom("test.gba");
+    // The ROM given on the command line is played like one from the library.
+    let mut session = launcher::Session::new(&args);
+    if !args.launcher {
+        let path = std::path::Path::new(&args.rom);
+        session.start(&path.file_stem().unwrap_or_default().to_string_lossy());
+    }
 
     App::new()
         .add_plugins((
@@ -98,7 +324,66 @@ fn main() {
         ))
         // Add systems that draw to the buffer to `Draw` schedule
         // to ensure they are rendered in the current frame.
-        .add_systems(Draw, draw)
-        .add_systems(Update, input)
+        .add_systems(
+            Draw,
+            (
+                draw,
+                screenshot::screenshot_output
+                    .after(draw)
+                    .before(watch::draw_watches),
+                watch::draw_watches.after(draw),
+                osd::draw_osd.after(watch::draw_watches),
+                menu::draw_menu.after(osd::draw_osd),
+                launcher::draw_launcher.before(osd::draw_osd),
+            ),
+        )
+        .insert_resource(console::Console::spawn())
+        .insert_resource(filters)
+        .insert_resource(osd::Osd::default())
+        .insert_resource(menu::Menu::default())
+        .insert_resource(bindings::Bindings::default())
+        .insert_resource(watch::Watches::load())
+        .insert_resource(
+            if !args.libraries.is_empty() || !args.playlists.is_empty() {
+                launcher::Launcher::new(&args)
+            } else {
+                launcher::Launcher::default()
+            },
+        )
+        .insert_resource(session)
+        .insert_resource(screenshot::Screenshots::new(&args.screenshots))
+        .insert_resource(match &args.record_video {
+            Some(path) => recorder::Recording::start(std::path::Path::new(path)),
+            None => recorder::Recording::default(),
+        })
+        .add_systems(
+            Update,
+            (
+                input,
+                cheat_hotkeys,
+                state_hotkeys,
+                color_hotkeys,
+                pointer,
+                rotate_window,
+                screenshot::screenshot_hotkeys,
+                recorder::recording_hotkeys,
+                console::console,
+                menu::menu,
+                launcher::launcher,
+                launcher::auto_save,
+                watch::toggle_watches,
+                watch::edit_watches
+                    .after(input)
+                    .after(cheat_hotkeys)
+                    .after(menu::menu),
+            ),
+        )
+        .add_systems(
+            Last,
+            (
+                stop_recordings_on_exit,
+                launcher::end_session_on_exit.before(stop_recordings_on_exit),
+            ),
+        )
         .run();
 }
@@ -0,0 +1,412 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_pixels::prelude::*;
+
+use crate::bindings::{gamepad_just_pressed, Bindings};
+use crate::font;
+use crate::launcher::{Launcher, Session};
+use crate::overlay::{self, Canvas};
+use crate::recorder::Recording;
+use crate::watch::Watches;
+use crate::EMU;
+
+/// Slots the menu goes through, 0 being the default `state` file.
+const SLOTS: u32 = 10;
+/// Frames of run-ahead the menu goes through, 0 being off.
+const RUN_AHEAD_FRAMES: u32 = 5;
+
+/// The pause menu, opened with Escape or Select and Start together on a gamepad.
+///
+/// The game is frozen while it is open. Everything works with the arrows, Return and Escape, or
+/// the d-pad, South to confirm and East to go back.
+///
+/// It's drawn into the pixels buffer like the other overlays rather than with Bevy UI: pixels
+/// owns the window's surface, and Bevy's renderer, which Bevy UI needs, would claim it as well.
+#[derive(Resource, Default)]
+pub struct Menu {
+    open: bool,
+    page: Page,
+    cursor: usize,
+    /// Whether the next key or gamepad button pressed goes to the selected binding.
+    rebinding: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Page {
+    #[default]
+    Main,
+    Options,
+    Bindings,
+    Cheats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    Resume,
+    Reset,
+    SaveState,
+    LoadState,
+    Slot,
+    RunAhead,
+    Options,
+    Bindings,
+    Cheats,
+    CloseGame,
+    Quit,
+}
+
+const ENTRIES: [Entry; 11] = [
+    Entry::Resume,
+    Entry::Reset,
+    Entry::SaveState,
+    Entry::LoadState,
+    Entry::Slot,
+    Entry::RunAhead,
+    Entry::Options,
+    Entry::Bindings,
+    Entry::Cheats,
+    Entry::CloseGame,
+    Entry::Quit,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+}
+
+impl Menu {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn go_to(&mut self, page: Page) {
+        self.page = page;
+        self.cursor = 0;
+    }
+
+    /// Label and value of every line of the page.
+    fn rows(&self, bindings: &Bindings) -> Vec<(String, String)> {
+        match self.page {
+            Page::Main => ENTRIES
+                .iter()
+                .map(|entry| {
+                    let label = match entry {
+                        Entry::Resume => "Resume",
+                        Entry::Reset => "Reset",
+                        Entry::SaveState => "Save state",
+                        Entry::LoadState => "Load state",
+                        Entry::Slot => "State slot",
+                        Entry::RunAhead => "Run-ahead",
+                        Entry::Options => "Core options",
+                        Entry::Bindings => "Bindings",
+                        Entry::Cheats => "Cheats",
+                        Entry::CloseGame => "Close game",
+                        Entry::Quit => "Quit",
+                    };
+                    let value = match entry {
+                        Entry::Slot => format!("< {} >", EMU.state_slot()),
+                        Entry::RunAhead => match EMU.run_ahead_frames() {
+                            (0, _) => String::from("< off >"),
+                            (frames, _) => format!("< {} >", frames),
+                        },
+                        _ => String::new(),
+                    };
+
+                    (label.to_string(), value)
+                })
+                .collect(),
+            Page::Options => EMU
+                .core_options()
+                .iter()
+                .map(|option| {
+                    (
+                        option.description.clone(),
+                        format!("< {} >", option.value().to_string_lossy()),
+                    )
+                })
+                .collect(),
+            Page::Bindings => bindings
+                .buttons
+                .iter()
+                .enumerate()
+                .map(|(index, binding)| {
+                    let value = if self.rebinding && index == self.cursor {
+                        String::from("press a key or a button...")
+                    } else {
+                        binding.describe()
+                    };
+
+                    (binding.name.to_string(), value)
+                })
+                .collect(),
+            Page::Cheats => EMU
+                .cheats()
+                .iter()
+                .map(|cheat| {
+                    let value = if cheat.enabled { "on" } else { "off" };
+
+                    (cheat.description.clone(), value.to_string())
+                })
+                .collect(),
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self.page {
+            Page::Main => "Paused",
+            Page::Options => "Core options",
+            Page::Bindings => "Bindings",
+            Page::Cheats => "Cheats",
+        }
+    }
+}
+
+pub fn menu(
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut menu: ResMut<Menu>,
+    mut bindings: ResMut<Bindings>,
+    mut exit: EventWriter<AppExit>,
+    mut launcher: ResMut<Launcher>,
+    mut session: ResMut<Session>,
+    mut recording: ResMut<Recording>,
+    watches: Res<Watches>,
+) {
+    if launcher.active || watches.is_editing() {
+        return;
+    }
+
+    if menu.rebinding {
+        rebind(&keys, &gamepads, &gamepad_buttons, &mut menu, &mut bindings);
+        return;
+    }
+
+    let gamepad = |button| gamepad_just_pressed(&gamepads, &gamepad_buttons, button);
+    let held = |button| {
+        gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(gamepad, button)))
+    };
+    let chord = (gamepad(GamepadButtonType::Start) && held(GamepadButtonType::Select))
+        || (gamepad(GamepadButtonType::Select) && held(GamepadButtonType::Start));
+
+    if !menu.open {
+        if keys.just_pressed(KeyCode::Escape) || chord {
+            menu.open = true;
+            menu.go_to(Page::Main);
+        }
+        return;
+    }
+
+    if chord {
+        menu.open = false;
+        return;
+    }
+
+    let action = if keys.just_pressed(KeyCode::Up) || gamepad(GamepadButtonType::DPadUp) {
+        Action::Up
+    } else if keys.just_pressed(KeyCode::Down) || gamepad(GamepadButtonType::DPadDown) {
+        Action::Down
+    } else if keys.just_pressed(KeyCode::Left) || gamepad(GamepadButtonType::DPadLeft) {
+        Action::Left
+    } else if keys.just_pressed(KeyCode::Right) || gamepad(GamepadButtonType::DPadRight) {
+        Action::Right
+    } else if keys.just_pressed(KeyCode::Return) || gamepad(GamepadButtonType::South) {
+        Action::Confirm
+    } else if keys.just_pressed(KeyCode::Escape) || gamepad(GamepadButtonType::East) {
+        Action::Back
+    } else {
+        return;
+    };
+
+    let rows = menu.rows(&bindings).len();
+    match action {
+        Action::Up if rows > 0 => menu.cursor = (menu.cursor + rows - 1) % rows,
+        Action::Down if rows > 0 => menu.cursor = (menu.cursor + 1) % rows,
+        Action::Back if menu.page == Page::Main => menu.open = false,
+        Action::Back => menu.go_to(Page::Main),
+        _ => match menu.page {
+            Page::Main => {
+                if ENTRIES[menu.cursor] == Entry::CloseGame && action == Action::Confirm {
+                    if launcher.close_game(&mut session, &mut recording) {
+                        menu.open = false;
+                    } else {
+                        EMU.notify("no library to go back to, start with --library");
+                    }
+                } else {
+                    main_page(action, &mut menu, &mut exit);
+                }
+            }
+            Page::Options => options_page(action, menu.cursor),
+            Page::Bindings => menu.rebinding = action == Action::Confirm,
+            Page::Cheats => {
+                if action == Action::Confirm {
+                    EMU.toggle_cheat(menu.cursor);
+                }
+            }
+        },
+    }
+}
+
+fn main_page(action: Action, menu: &mut Menu, exit: &mut EventWriter<AppExit>) {
+    let entry = ENTRIES[menu.cursor];
+
+    match (entry, action) {
+        (Entry::Slot, Action::Left) => EMU.set_state_slot((EMU.state_slot() + SLOTS - 1) % SLOTS),
+        (Entry::Slot, Action::Right | Action::Confirm) => {
+            EMU.set_state_slot((EMU.state_slot() + 1) % SLOTS)
+        }
+        (Entry::RunAhead, Action::Left) => {
+            let (frames, second_instance) = EMU.run_ahead_frames();
+            EMU.set_run_ahead(
+                (frames + RUN_AHEAD_FRAMES - 1) % RUN_AHEAD_FRAMES,
+                second_instance,
+            )
+        }
+        (Entry::RunAhead, Action::Right | Action::Confirm) => {
+            let (frames, second_instance) = EMU.run_ahead_frames();
+            EMU.set_run_ahead((frames + 1) % RUN_AHEAD_FRAMES, second_instance)
+        }
+        (_, Action::Confirm) => match entry {
+            Entry::Resume => menu.open = false,
+            Entry::Reset => {
+                EMU.reset();
+                EMU.notify("game reset");
+                menu.open = false;
+            }
+            Entry::SaveState => {
+                EMU.save_state();
+                menu.open = false;
+            }
+            Entry::LoadState => {
+                EMU.load_state();
+                menu.open = false;
+            }
+            Entry::Options if EMU.core_options().is_empty() => {
+                EMU.notify("the core has no options")
+            }
+            Entry::Options => menu.go_to(Page::Options),
+            Entry::Bindings => menu.go_to(Page::Bindings),
+            Entry::Cheats if EMU.cheat_count() == 0 => EMU.notify("the game has no cheats"),
+            Entry::Cheats => menu.go_to(Page::Cheats),
+            Entry::Quit => exit.send(AppExit),
+            Entry::Slot | Entry::RunAhead | Entry::CloseGame => {}
+        },
+        _ => {}
+    }
+}
+
+/// Go through the values of the selected option with left and right.
+fn options_page(action: Action, index: usize) {
+    let options = EMU.core_options();
+    let Some(option) = options.get(index) else {
+        return;
+    };
+
+    let count = option.values.len();
+    let value = match action {
+        Action::Left => (option.selected + count - 1) % count,
+        Action::Right | Action::Confirm => (option.selected + 1) % count,
+        _ => return,
+    };
+
+    EMU.set_core_option(index, value);
+}
+
+/// Give the selected binding the next key or gamepad button pressed, Escape cancelling.
+fn rebind(
+    keys: &Input<KeyCode>,
+    gamepads: &Gamepads,
+    gamepad_buttons: &Input<GamepadButton>,
+    menu: &mut Menu,
+    bindings: &mut Bindings,
+) {
+    let binding = &mut bindings.buttons[menu.cursor];
+
+    if let Some(key) = keys.get_just_pressed().next() {
+        if *key != KeyCode::Escape {
+            binding.keys = vec![*key];
+        }
+        menu.rebinding = false;
+    } else if let Some(button) = gamepad_buttons
+        .get_just_pressed()
+        .find(|button| gamepads.contains(button.gamepad))
+    {
+        binding.gamepad = button.button_type;
+        menu.rebinding = false;
+    }
+}
+
+pub fn draw_menu(
+    mut wrapper_query: Query<&mut PixelsWrapper>,
+    menu: Res<Menu>,
+    bindings: Res<Bindings>,
+) {
+    if !menu.open {
+        return;
+    }
+
+    let Ok(mut wrapper) = wrapper_query.get_single_mut() else {
+        return;
+    };
+
+    let size = wrapper.pixels.texture().size();
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mut canvas = Canvas::new(wrapper.pixels.frame_mut(), width, height);
+
+    canvas.fill_rect(0, 0, width, height, overlay::BACKGROUND);
+    canvas.text(4, 3, menu.title(), overlay::ACCENT);
+
+    let rows = menu.rows(&bindings);
+    let columns = width.saturating_sub(8) / font::GLYPH_WIDTH;
+    let label_columns = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(columns / 2);
+
+    // Scroll so the cursor stays on screen.
+    let visible = (height.saturating_sub(14) / font::LINE_HEIGHT).max(1);
+    let first = (menu.cursor + 1).saturating_sub(visible);
+
+    for (line, (index, (label, value))) in rows
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(visible)
+        .enumerate()
+    {
+        let y = 14 + line * font::LINE_HEIGHT;
+        let selected = index == menu.cursor;
+        let color = if selected {
+            overlay::TEXT
+        } else {
+            overlay::DIM
+        };
+
+        if selected {
+            canvas.text(4, y, ">", overlay::ACCENT);
+        }
+
+        let label: String = label.chars().take(label_columns).collect();
+        let value: String = value
+            .chars()
+            .take(columns.saturating_sub(label_columns + 3))
+            .collect();
+
+        canvas.text(4 + 2 * font::GLYPH_WIDTH, y, &label, color);
+        canvas.text(
+            4 + (label_columns + 3) * font::GLYPH_WIDTH,
+            y,
+            &value,
+            color,
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use bevy::prelude::*;
+use bevy_pixels::prelude::*;
+
+use crate::emulator::message::{Message, MessageKind};
+use crate::font;
+use crate::overlay::{self, Canvas};
+use crate::EMU;
+
+/// How many notifications are shown at once, the others wait for a free line.
+const NOTIFICATION_LINES: usize = 3;
+/// Width of a progress bar, in pixels.
+const PROGRESS_WIDTH: usize = 40;
+
+/// Messages of the core and the front-end shown over the game.
+///
+/// Notifications stack at the bottom left, the most important and the newest ones first. The
+/// status line and the progress of the core sit at the top right, each replaced by the next one.
+///
+/// Durations are in frames of the core, as libretro counts them, so messages hold while the
+/// game is paused and go faster with it. Without a game they count the frames of the display.
+#[derive(Resource, Default)]
+pub struct Osd {
+    /// Messages on screen, with the frames they have left.
+    messages: Vec<(Message, u32)>,
+    /// `EMU.frame_count()` when the messages were last counted down.
+    frame_count: u64,
+}
+
+impl Osd {
+    fn push(&mut self, message: Message) {
+        if message.kind != MessageKind::Notification {
+            let same_kind = |kind: MessageKind| {
+                std::mem::discriminant(&kind) == std::mem::discriminant(&message.kind)
+            };
+            self.messages.retain(|(shown, _)| !same_kind(shown.kind));
+        }
+
+        let frames = message.frames;
+        self.messages.push((message, frames));
+    }
+
+    /// Count the frames the core ran since the last call, dropping the messages that are over.
+    fn tick(&mut self) {
+        let frame_count = EMU.frame_count();
+        let ran = match EMU.content() {
+            // The count starts over with every game.
+            Some(_) => frame_count
+                .saturating_sub(self.frame_count)
+                .min(u32::MAX as u64) as u32,
+            None => 1,
+        };
+        self.frame_count = frame_count;
+
+        for (_, frames) in self.messages.iter_mut() {
+            *frames = frames.saturating_sub(ran);
+        }
+        self.messages.retain(|(_, frames)| *frames > 0);
+    }
+}
+
+pub fn draw_osd(mut wrapper_query: Query<&mut PixelsWrapper>, mut osd: ResMut<Osd>) {
+    for message in EMU.take_messages() {
+        osd.push(message);
+    }
+
+    let Ok(mut wrapper) = wrapper_query.get_single_mut() else {
+        return;
+    };
+
+    let size = wrapper.pixels.texture().size();
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mut canvas = Canvas::new(wrapper.pixels.frame_mut(), width, height);
+    let columns = width.saturating_sub(4) / font::GLYPH_WIDTH;
+
+    let mut notifications: Vec<&Message> = osd
+        .messages
+        .iter()
+        .map(|(message, _)| message)
+        .filter(|message| message.kind == MessageKind::Notification)
+        .collect();
+    // Newest first within a priority, the sort is stable.
+    notifications.reverse();
+    notifications.sort_by(|a, b| b.priority.cmp(&a.priority));
+
+    for (line, message) in notifications.iter().take(NOTIFICATION_LINES).enumerate() {
+        let text: String = message.text.chars().take(columns).collect();
+        let y = height.saturating_sub((line + 1) * font::LINE_HEIGHT + 2);
+
+        canvas.fill_rect(
+            0,
+            y,
+            text.chars().count() * font::GLYPH_WIDTH + 3,
+            font::LINE_HEIGHT + 1,
+            overlay::BACKGROUND,
+        );
+        canvas.text(2, y + 1, &text, overlay::TEXT);
+    }
+
+    let mut y = 0;
+    for (message, _) in osd.messages.iter() {
+        let progress = match message.kind {
+            MessageKind::Notification => continue,
+            MessageKind::Status => None,
+            MessageKind::Progress(progress) => Some(progress),
+        };
+
+        let bar = if progress.is_some() {
+            PROGRESS_WIDTH + 3
+        } else {
+            0
+        };
+        let text: String = message
+            .text
+            .chars()
+            .take(columns.saturating_sub(bar / font::GLYPH_WIDTH + 1))
+            .collect();
+        let box_width = text.chars().count() * font::GLYPH_WIDTH + bar + 3;
+        let x = width.saturating_sub(box_width);
+
+        canvas.fill_rect(x, y, box_width, font::LINE_HEIGHT + 1, overlay::BACKGROUND);
+        let text_width = canvas.text(x + 2, y + 1, &text, overlay::TEXT);
+
+        if let Some(progress) = progress {
+            let left = x + 2 + text_width + 1;
+
+            canvas.fill_rect(left, y + 2, PROGRESS_WIDTH, 5, overlay::DIM);
+            // Without a known progress the bar stays dim.
+            if progress >= 0 {
+                let done = PROGRESS_WIDTH * progress.min(100) as usize / 100;
+                canvas.fill_rect(left, y + 2, done, 5, overlay::ACCENT);
+            }
+        }
+
+        y += font::LINE_HEIGHT + 1;
+    }
+
+    osd.tick();
+}
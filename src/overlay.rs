@@ -0,0 +1,81 @@
+use crate::font;
+
+/// Colors of the overlays, as RGBA.
+pub const TEXT: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+pub const DIM: [u8; 4] = [0xA0, 0xA0, 0xA0, 0xFF];
+pub const ACCENT: [u8; 4] = [0x60, 0xD0, 0x60, 0xFF];
+pub const BACKGROUND: [u8; 4] = [0x00, 0x00, 0x00, 0xB0];
+
+/// Software drawing on top of the game image, straight into the RGBA frame of `pixels`.
+///
+/// Everything is drawn at the core's native resolution, so it is scaled along with the game.
+pub struct Canvas<'a> {
+    frame: &'a mut [u8],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(frame: &'a mut [u8], width: usize, height: usize) -> Canvas<'a> {
+        Canvas {
+            frame,
+            width,
+            height,
+        }
+    }
+
+    /// Blend a color over a pixel, using the alpha of the color.
+    pub fn pixel(&mut self, x: usize, y: usize, color: [u8; 4]) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let offset = (y * self.width + x) * 4;
+        let alpha = color[3] as u32;
+
+        for channel in 0..3 {
+            let under = self.frame[offset + channel] as u32;
+            self.frame[offset + channel] =
+                ((color[channel] as u32 * alpha + under * (255 - alpha)) / 255) as u8;
+        }
+        self.frame[offset + 3] = 0xFF;
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 4]) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                self.pixel(column, row, color);
+            }
+        }
+    }
+
+    /// Draw an RGBA image of `width` by `height` pixels with its top left corner at `x`, `y`.
+    pub fn image(&mut self, x: usize, y: usize, rgba: &[u8], width: usize, height: usize) {
+        for (row, line) in rgba.chunks_exact(width * 4).take(height).enumerate() {
+            for (column, pixel) in line.chunks_exact(4).enumerate() {
+                self.pixel(
+                    x + column,
+                    y + row,
+                    [pixel[0], pixel[1], pixel[2], pixel[3]],
+                );
+            }
+        }
+    }
+
+    /// Draw a line of text with its top left corner at `x`, `y`, returning the width it took.
+    pub fn text(&mut self, x: usize, y: usize, text: &str, color: [u8; 4]) -> usize {
+        for (index, c) in text.chars().enumerate() {
+            let left = x + index * font::GLYPH_WIDTH;
+
+            for (row, bits) in font::glyph(c).iter().enumerate() {
+                for column in 0..font::GLYPH_WIDTH {
+                    if bits & (0x80 >> column) != 0 {
+                        self.pixel(left + column, y + row, color);
+                    }
+                }
+            }
+        }
+
+        text.chars().count() * font::GLYPH_WIDTH
+    }
+}
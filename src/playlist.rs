@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Every game played, most recent first, with when it was last played and for how long overall,
+/// kept in the working directory.
+pub const HISTORY_FILE: &str = "history.json";
+/// Games the history lists, the others are only kept for their playtime.
+pub const HISTORY_LENGTH: usize = 50;
+const VERSION: u32 = 1;
+
+/// A game of a playlist, with the core it runs on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// Shared library of the core, `None` to pick one by the extension of the ROM.
+    #[serde(default)]
+    pub core_path: Option<PathBuf>,
+    pub label: String,
+    /// CRC-32 of the ROM.
+    #[serde(default)]
+    pub crc32: u32,
+    /// Seconds played.
+    #[serde(default)]
+    pub playtime: u64,
+    /// Seconds since the epoch, only kept in the history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_played: Option<u64>,
+}
+
+/// A list of games in a JSON file, one given with `--playlist` or the history.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Playlist {
+    #[serde(default)]
+    pub version: u32,
+    pub items: Vec<Entry>,
+}
+
+impl Playlist {
+    /// Read the playlist at `path`. One that doesn't exist yet is empty.
+    pub fn load(path: &Path) -> Result<Playlist, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|err| err.to_string()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Playlist::default()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<(), String> {
+        self.version = VERSION;
+
+        let text = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        std::fs::write(path, text).map_err(|err| err.to_string())
+    }
+
+    /// Append a game, unless it's already in the playlist. Returns whether it was added.
+    pub fn add(&mut self, entry: Entry) -> bool {
+        if self.items.iter().any(|item| item.path == entry.path) {
+            return false;
+        }
+
+        self.items.push(entry);
+        true
+    }
+
+    /// Move a game to the top of the history, played until now, adding the `seconds` of the
+    /// session to its playtime.
+    pub fn played(&mut self, mut entry: Entry, seconds: u64) {
+        if let Some(index) = self.items.iter().position(|item| item.path == entry.path) {
+            entry.playtime = self.items.remove(index).playtime;
+        }
+
+        entry.playtime += seconds;
+        entry.last_played = Some(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+        );
+        self.items.insert(0, entry);
+    }
+
+    /// Add `seconds` to the playtime of the game at `path`, returning whether it's in the
+    /// playlist.
+    pub fn add_playtime(&mut self, path: &Path, seconds: u64) -> bool {
+        let mut found = false;
+
+        for item in self.items.iter_mut().filter(|item| item.path == path) {
+            item.playtime += seconds;
+            found = true;
+        }
+
+        found
+    }
+}
+
+/// Last played time and playtime of the games in the history, by path.
+pub fn stats() -> HashMap<PathBuf, (u64, u64)> {
+    let history = match Playlist::load(Path::new(HISTORY_FILE)) {
+        Ok(history) => history,
+        Err(err) => {
+            println!("could not read {}: {}", HISTORY_FILE, err);
+            return HashMap::new();
+        }
+    };
+
+    history
+        .items
+        .into_iter()
+        .map(|item| (item.path, (item.last_played.unwrap_or(0), item.playtime)))
+        .collect()
+}
+
+/// Count a session of `seconds` in the history and in the `playlists` holding the game.
+pub fn record_play(entry: &Entry, seconds: u64, playlists: &[PathBuf]) {
+    let history = Path::new(HISTORY_FILE);
+    let result = Playlist::load(history).and_then(|mut playlist| {
+        playlist.played(entry.clone(), seconds);
+        playlist.save(history)
+    });
+    if let Err(err) = result {
+        println!("could not update {}: {}", HISTORY_FILE, err);
+    }
+
+    for path in playlists {
+        let result = Playlist::load(path).and_then(|mut playlist| {
+            if playlist.add_playtime(&entry.path, seconds) {
+                playlist.save(path)
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(err) = result {
+            println!("could not update {}: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str) -> Entry {
+        Entry {
+            path: PathBuf::from(path),
+            core_path: None,
+            label: String::from(path),
+            crc32: 0x1234_5678,
+            playtime: 0,
+            last_played: None,
+        }
+    }
+
+    #[test]
+    fn round_trips_through_its_file() {
+        let file =
+            std::env::temp_dir().join(format!("advanced-playlist-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+
+        // A playlist that doesn't exist yet is empty.
+        let mut playlist = Playlist::load(&file).unwrap();
+        assert!(playlist.items.is_empty());
+
+        assert!(playlist.add(entry("a.gba")));
+        assert!(playlist.add(entry("b.gba")));
+        assert!(!playlist.add(entry("a.gba")));
+        assert!(playlist.add_playtime(Path::new("b.gba"), 30));
+        assert!(!playlist.add_playtime(Path::new("c.gba"), 30));
+        playlist.save(&file).unwrap();
+
+        let mut loaded = Playlist::load(&file).unwrap();
+        assert_eq!(loaded.version, VERSION);
+        let paths: Vec<&Path> = loaded
+            .items
+            .iter()
+            .map(|item| item.path.as_path())
+            .collect();
+        assert_eq!(paths, [Path::new("a.gba"), Path::new("b.gba")]);
+        assert_eq!(loaded.items[1].crc32, 0x1234_5678);
+        assert_eq!(loaded.items[1].playtime, 30);
+
+        // Playing moves the game to the top and keeps its playtime.
+        loaded.played(entry("b.gba"), 15);
+        loaded.save(&file).unwrap();
+
+        let loaded = Playlist::load(&file).unwrap();
+        assert_eq!(loaded.items[0].path, Path::new("b.gba"));
+        assert_eq!(loaded.items[0].playtime, 45);
+        assert!(loaded.items[0].last_played.is_some());
+        assert_eq!(loaded.items.len(), 2);
+
+        std::fs::write(&file, "not json").unwrap();
+        assert!(Playlist::load(&file).is_err());
+
+        let _ = std::fs::remove_file(&file);
+    }
+}
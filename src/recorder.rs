@@ -0,0 +1,292 @@
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use bevy::prelude::*;
+
+use crate::wav::WavWriter;
+use crate::EMU;
+
+/// Where the frames of a recording go.
+enum Output {
+    /// Raw frames piped into `ffmpeg`, which keeps them losslessly in a temporary file. The
+    /// audio and the video are muxed into the final file when the recording stops.
+    Ffmpeg {
+        child: Child,
+        stdin: ChildStdin,
+        video: PathBuf,
+    },
+    /// Without `ffmpeg`, raw RGB frames one after the other, next to a WAV with the audio.
+    Raw { video: BufWriter<std::fs::File> },
+}
+
+/// A recording of the game's video and audio, at the core's resolution and frame rate.
+///
+/// One frame is written per `retro_run`, whatever the refresh rate of the display is.
+pub struct Recorder {
+    path: PathBuf,
+    output: Output,
+    audio: WavWriter,
+    audio_path: PathBuf,
+    width: usize,
+    height: usize,
+    fps: f64,
+    /// RGB of the last frame, written again when the core doesn't send a new one.
+    frame: Vec<u8>,
+    frames: u64,
+}
+
+impl Recorder {
+    /// Start recording into `path`, which ffmpeg encodes according to its extension.
+    pub fn start(path: &Path) -> Result<Recorder, String> {
+        let (width, height) = EMU.frame_size();
+        if width == 0 || height == 0 {
+            return Err(String::from("the core didn't send a frame yet"));
+        }
+
+        let (fps, sample_rate) = EMU.timing();
+
+        let audio_path = path.with_extension("audio.wav");
+        let audio = WavWriter::create(&audio_path, sample_rate.round() as u32)
+            .map_err(|err| format!("could not create {}: {}", audio_path.display(), err))?;
+
+        let output = match spawn_ffmpeg(path, width, height, fps) {
+            Some(output) => output,
+            None => {
+                let video = path.with_extension("rgb");
+                let file = std::fs::File::create(&video)
+                    .map_err(|err| format!("could not create {}: {}", video.display(), err))?;
+
+                println!("ffmpeg was not found, recording raw frames and audio instead");
+
+                Output::Raw {
+                    video: BufWriter::new(file),
+                }
+            }
+        };
+
+        EMU.capture_audio(true);
+
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            output,
+            audio,
+            audio_path,
+            width,
+            height,
+            fps,
+            frame: vec![0; width * height * 3],
+            frames: 0,
+        })
+    }
+
+    /// Write the frame `retro_run` produced, `None` when the core duped the previous one, and the
+    /// audio of that frame.
+    pub fn push(
+        &mut self,
+        video: Option<&[u32]>,
+        (width, height): (usize, usize),
+        audio: &[i16],
+    ) -> std::io::Result<()> {
+        if let Some(pixels) = video {
+            self.convert(pixels, width, height);
+        }
+
+        match &mut self.output {
+            Output::Ffmpeg { stdin, .. } => stdin.write_all(&self.frame)?,
+            Output::Raw { video } => video.write_all(&self.frame)?,
+        }
+        self.frames += 1;
+
+        self.audio.write(audio)
+    }
+
+    /// Copy a frame as RGB, cropping or padding it when the core changed its resolution since
+    /// the recording started.
+    fn convert(&mut self, pixels: &[u32], width: usize, height: usize) {
+        let format = EMU.pixel_format();
+        self.frame.fill(0);
+
+        for y in 0..height.min(self.height) {
+            let row = &pixels[y * width..y * width + width.min(self.width)];
+            let out = &mut self.frame[y * self.width * 3..];
+
+            for (x, pixel) in row.iter().enumerate() {
+                out[x * 3..x * 3 + 3].copy_from_slice(&format.rgb(*pixel));
+            }
+        }
+    }
+
+    /// Stop the recording, muxing the video and the audio into the final file.
+    pub fn finish(self) -> Result<PathBuf, String> {
+        EMU.capture_audio(false);
+
+        self.audio
+            .finish()
+            .map_err(|err| format!("could not write {}: {}", self.audio_path.display(), err))?;
+
+        match self.output {
+            Output::Ffmpeg {
+                mut child,
+                stdin,
+                video,
+            } => {
+                drop(stdin);
+
+                let encoded = child.wait().map(|status| status.success());
+                if !matches!(encoded, Ok(true)) {
+                    return Err(String::from("ffmpeg failed to encode the video"));
+                }
+
+                let muxed = Command::new("ffmpeg")
+                    .args(["-loglevel", "error", "-y", "-i"])
+                    .arg(&video)
+                    .arg("-i")
+                    .arg(&self.audio_path)
+                    .args(["-map", "0:v", "-map", "1:a"])
+                    .arg(&self.path)
+                    .status()
+                    .map(|status| status.success());
+
+                if !matches!(muxed, Ok(true)) {
+                    return Err(format!(
+                        "ffmpeg failed to mux {} and {}",
+                        video.display(),
+                        self.audio_path.display()
+                    ));
+                }
+
+                let _ = std::fs::remove_file(&video);
+                let _ = std::fs::remove_file(&self.audio_path);
+
+                Ok(self.path)
+            }
+            Output::Raw { mut video } => {
+                video
+                    .flush()
+                    .map_err(|err| format!("could not write the frames: {}", err))?;
+
+                let video = self.path.with_extension("rgb");
+                println!(
+                    "{} frames of {}x{} rgb24 at {} fps, convert them with:\n  ffmpeg -f rawvideo -pixel_format rgb24 -video_size {}x{} -framerate {} -i {} -i {} {}",
+                    self.frames,
+                    self.width,
+                    self.height,
+                    self.fps,
+                    self.width,
+                    self.height,
+                    self.fps,
+                    video.display(),
+                    self.audio_path.display(),
+                    self.path.display()
+                );
+
+                Ok(video)
+            }
+        }
+    }
+}
+
+/// Start `ffmpeg` reading raw frames from its stdin, or `None` when it can't be run.
+fn spawn_ffmpeg(path: &Path, width: usize, height: usize, fps: f64) -> Option<Output> {
+    let video = path.with_extension("video.mkv");
+
+    let mut child = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-y", "-f", "rawvideo"])
+        .args(["-pixel_format", "rgb24"])
+        .args(["-video_size", &format!("{}x{}", width, height)])
+        .args(["-framerate", &fps.to_string()])
+        .args(["-i", "-", "-c:v", "ffv1"])
+        .arg(&video)
+        .stdin(Stdio::piped())
+        .spawn()
+        .ok()?;
+
+    let stdin = child.stdin.take()?;
+
+    Some(Output::Ffmpeg {
+        child,
+        stdin,
+        video,
+    })
+}
+
+/// The recording toggled with F10 into `recordings/`, or asked for with `--record-video`.
+#[derive(Resource, Default)]
+pub struct Recording {
+    pub recorder: Option<Recorder>,
+    /// Where to start recording once the core sent its first frame.
+    pending: Option<PathBuf>,
+}
+
+impl Recording {
+    /// Record into `path` from the next frame on.
+    pub fn start(path: &Path) -> Recording {
+        Recording {
+            recorder: None,
+            pending: Some(path.to_path_buf()),
+        }
+    }
+
+    /// Feed the recorder with the frame `EMU.run` just returned and its audio.
+    pub fn push(&mut self, video: Option<&[u32]>, audio: &[i16]) {
+        if let (Some(path), Some(_)) = (&self.pending, video) {
+            match Recorder::start(path) {
+                Ok(recorder) => {
+                    println!("recording to {}", path.display());
+                    self.recorder = Some(recorder);
+                }
+                Err(message) => println!("{}", message),
+            }
+            self.pending = None;
+        }
+
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+
+        if let Err(err) = recorder.push(video, EMU.frame_size(), audio) {
+            println!("recording stopped: {}", err);
+            self.stop();
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.pending = None;
+
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(path) => println!("recording saved to {}", path.display()),
+                Err(message) => println!("{}", message),
+            }
+        }
+    }
+}
+
+pub fn recording_hotkeys(keys: Res<Input<KeyCode>>, mut recording: ResMut<Recording>) {
+    if !keys.just_pressed(KeyCode::F10) {
+        return;
+    }
+
+    if recording.recorder.is_some() || recording.pending.is_some() {
+        recording.stop();
+        return;
+    }
+
+    let game = EMU
+        .content()
+        .and_then(|(rom, _)| {
+            rom.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| String::from("recording"));
+    let dir = Path::new("recordings");
+    let path = dir.join(format!("{}-{}.mkv", game, crate::screenshot::timestamp()));
+
+    if let Err(err) = std::fs::create_dir_all(dir) {
+        println!("could not create {}: {}", dir.display(), err);
+        return;
+    }
+
+    *recording = Recording::start(&path);
+}
@@ -0,0 +1,231 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_pixels::prelude::*;
+
+use crate::EMU;
+
+/// Where screenshots go and how they're named.
+///
+/// Files are named after the game and the time they were taken, `game-20240131-235959.png`,
+/// and carry the core, the ROM and the frame number as PNG text chunks.
+#[derive(Resource)]
+pub struct Screenshots {
+    pub dir: PathBuf,
+    /// A screenshot of the frame as shown was asked for, it's taken once the frame is drawn.
+    output_requested: bool,
+}
+
+impl Screenshots {
+    pub fn new(dir: &str) -> Screenshots {
+        Screenshots {
+            dir: PathBuf::from(dir),
+            output_requested: false,
+        }
+    }
+
+    /// Save the last frame of the core, each pixel scaled up to `scale` by `scale` pixels.
+    pub fn take(&self, scale: usize) -> Result<PathBuf, String> {
+        let Some((frame, width, height)) = EMU.last_frame() else {
+            return Err(String::from("the core didn't send a frame yet"));
+        };
+
+        let scale = scale.max(1);
+        self.save(
+            &rgb(&frame, width, height, scale),
+            width * scale,
+            height * scale,
+        )
+    }
+
+    /// Write RGB `pixels` to a new file of the directory.
+    fn save(&self, pixels: &[u8], width: usize, height: usize) -> Result<PathBuf, String> {
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|err| format!("could not create {}: {}", self.dir.display(), err))?;
+
+        let (rom, crc32) = EMU.content().unwrap_or_default();
+        let game = rom
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("screenshot"));
+
+        let stamp = timestamp();
+        let mut path = self.dir.join(format!("{}-{}.png", game, stamp));
+        let mut copy = 1;
+        while path.exists() {
+            copy += 1;
+            path = self.dir.join(format!("{}-{}-{}.png", game, stamp, copy));
+        }
+
+        let (core_name, core_version) = EMU.core_info();
+        let text = [
+            ("Software", String::from("advanced")),
+            ("Core", format!("{} {}", core_name, core_version)),
+            ("ROM", rom.display().to_string()),
+            ("ROM CRC32", format!("{:08x}", crc32)),
+            ("Frame", EMU.frame_count().to_string()),
+        ];
+
+        write_png(&path, pixels, width, height, &text)
+            .map_err(|err| format!("could not write {}: {}", path.display(), err))?;
+
+        Ok(path)
+    }
+}
+
+/// Take a screenshot of the core's frame with F12, or of the frame as shown with Shift+F12.
+pub fn screenshot_hotkeys(keys: Res<Input<KeyCode>>, mut screenshots: ResMut<Screenshots>) {
+    if !keys.just_pressed(KeyCode::F12) {
+        return;
+    }
+
+    if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        screenshots.output_requested = true;
+        return;
+    }
+
+    notify(screenshots.take(1));
+}
+
+/// Save the frame as it's shown, rotated, filtered and scaled to the window, once `draw` put it
+/// in the buffer and before anything is drawn over it.
+pub fn screenshot_output(
+    mut screenshots: ResMut<Screenshots>,
+    wrapper_query: Query<&PixelsWrapper>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+) {
+    if !std::mem::take(&mut screenshots.output_requested) {
+        return;
+    }
+    let Ok(wrapper) = wrapper_query.get_single() else {
+        return;
+    };
+
+    let size = wrapper.pixels.texture().size();
+    let (width, height) = (size.width as usize, size.height as usize);
+    let scale = output_scale(&windows, width, height);
+
+    // The buffer is RGBA, with the pixels as the filters left them.
+    let mut pixels = Vec::with_capacity(width * height * scale * scale * 3);
+    for row in wrapper.pixels.frame().chunks_exact(width * 4) {
+        let start = pixels.len();
+
+        for pixel in row.chunks_exact(4) {
+            for _ in 0..scale {
+                pixels.extend_from_slice(&pixel[..3]);
+            }
+        }
+
+        for _ in 1..scale {
+            pixels.extend_from_within(start..start + width * scale * 3);
+        }
+    }
+
+    notify(screenshots.save(&pixels, width * scale, height * scale));
+}
+
+fn notify(result: Result<PathBuf, String>) {
+    match result {
+        Ok(path) => EMU.notify(format!("screenshot saved to {}", path.display())),
+        Err(message) => EMU.notify(message),
+    }
+}
+
+/// The largest whole scale of a `width` by `height` buffer that fits in the window, as pixels
+/// draws it.
+fn output_scale(
+    windows: &Query<&Window, With<PrimaryWindow>>,
+    width: usize,
+    height: usize,
+) -> usize {
+    let Ok(window) = windows.get_single() else {
+        return 1;
+    };
+
+    if width == 0 || height == 0 {
+        return 1;
+    }
+
+    let scale_x = window.physical_width() as usize / width;
+    let scale_y = window.physical_height() as usize / height;
+
+    scale_x.min(scale_y).max(1)
+}
+
+/// Turn the core's frame into RGB rows, scaled with nearest neighbour.
+fn rgb(frame: &[u32], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let format = EMU.pixel_format();
+    let mut out = Vec::with_capacity(width * height * scale * scale * 3);
+
+    for y in 0..height {
+        let row = &frame[y * width..(y + 1) * width];
+        let start = out.len();
+
+        for pixel in row {
+            for _ in 0..scale {
+                out.extend_from_slice(&format.rgb(*pixel));
+            }
+        }
+
+        for _ in 1..scale {
+            out.extend_from_within(start..start + width * scale * 3);
+        }
+    }
+
+    out
+}
+
+fn write_png(
+    path: &Path,
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    text: &[(&str, String)],
+) -> Result<(), png::EncodingError> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    for (keyword, value) in text {
+        encoder.add_text_chunk(keyword.to_string(), value.clone())?;
+    }
+
+    encoder.write_header()?.write_image_data(pixels)
+}
+
+/// Current UTC time as `YYYYMMDD-HHMMSS`.
+pub fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+
+    let [year, month, day, hours, minutes, seconds] = civil(seconds);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, hours, minutes, seconds
+    )
+}
+
+/// Year, month, day, hours, minutes and seconds of a UTC time, in seconds since the epoch.
+pub fn civil(seconds: i64) -> [i64; 6] {
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // Days since the epoch to a civil date, from Howard Hinnant's `civil_from_days`.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    [year, month, day, time / 3600, time / 60 % 60, time % 60]
+}
@@ -0,0 +1,402 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy_pixels::prelude::*;
+
+use crate::emulator::search::Width;
+use crate::font;
+use crate::overlay::{self, Canvas};
+use crate::EMU;
+
+/// How many frames of history the graph of a watch shows.
+const HISTORY: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchType {
+    U8,
+    U16,
+    U32,
+    S8,
+    S16,
+    S32,
+}
+
+impl WatchType {
+    pub fn parse(text: &str) -> Option<WatchType> {
+        match text {
+            "u8" => Some(WatchType::U8),
+            "u16" => Some(WatchType::U16),
+            "u32" => Some(WatchType::U32),
+            "s8" => Some(WatchType::S8),
+            "s16" => Some(WatchType::S16),
+            "s32" => Some(WatchType::S32),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            WatchType::U8 => "u8",
+            WatchType::U16 => "u16",
+            WatchType::U32 => "u32",
+            WatchType::S8 => "s8",
+            WatchType::S16 => "s16",
+            WatchType::S32 => "s32",
+        }
+    }
+
+    fn width(&self) -> Width {
+        match self {
+            WatchType::U8 | WatchType::S8 => Width::U8,
+            WatchType::U16 | WatchType::S16 => Width::U16,
+            WatchType::U32 | WatchType::S32 => Width::U32,
+        }
+    }
+
+    /// Interpret the raw bits read from memory, sign extending the signed types.
+    fn value(&self, raw: u32) -> i64 {
+        match self {
+            WatchType::U8 | WatchType::U16 | WatchType::U32 => raw as i64,
+            WatchType::S8 => raw as u8 as i8 as i64,
+            WatchType::S16 => raw as u16 as i16 as i64,
+            WatchType::S32 => raw as i32 as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchFormat {
+    Decimal,
+    Hex,
+}
+
+impl WatchFormat {
+    pub fn parse(text: &str) -> Option<WatchFormat> {
+        match text {
+            "dec" => Some(WatchFormat::Decimal),
+            "hex" => Some(WatchFormat::Hex),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            WatchFormat::Decimal => "dec",
+            WatchFormat::Hex => "hex",
+        }
+    }
+}
+
+/// A game variable shown live on the watch panel.
+pub struct Watch {
+    pub address: usize,
+    pub kind: WatchType,
+    pub format: WatchFormat,
+    pub label: String,
+    /// Values of the last frames, oldest first. Empty while the address can't be read.
+    history: VecDeque<i64>,
+}
+
+impl Watch {
+    /// Parse `ADDRESS TYPE FORMAT LABEL`, the address in decimal or `0x` hexadecimal.
+    pub fn parse(text: &str) -> Result<Watch, String> {
+        let mut words = text.split_whitespace();
+        let (Some(address), Some(kind), Some(format)) = (words.next(), words.next(), words.next())
+        else {
+            return Err(String::from("a watch is ADDRESS TYPE FORMAT LABEL"));
+        };
+
+        let address = match address.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => address.parse(),
+        }
+        .map_err(|_| format!("{} is not a number", address))?;
+        let kind = WatchType::parse(kind).ok_or("type is u8, u16, u32, s8, s16 or s32")?;
+        let format = WatchFormat::parse(format).ok_or("format is dec or hex")?;
+        let label = words.collect::<Vec<&str>>().join(" ");
+
+        Ok(Watch::new(address, kind, format, &label))
+    }
+
+    pub fn new(address: usize, kind: WatchType, format: WatchFormat, label: &str) -> Watch {
+        Watch {
+            address,
+            kind,
+            format,
+            label: label.to_string(),
+            history: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    fn sample(&mut self) {
+        match EMU.read_value(self.address, self.kind.width()) {
+            Ok(raw) => {
+                if self.history.len() == HISTORY {
+                    self.history.pop_front();
+                }
+                self.history.push_back(self.kind.value(raw));
+            }
+            Err(_) => self.history.clear(),
+        }
+    }
+
+    fn formatted(&self) -> String {
+        let Some(value) = self.history.back() else {
+            return String::from("--");
+        };
+
+        match self.format {
+            WatchFormat::Decimal => value.to_string(),
+            WatchFormat::Hex => {
+                let digits = self.kind.width().bytes() * 2;
+                let mask = (1u64 << (digits * 4)) - 1;
+                format!("{:0digits$x}", *value as u64 & mask, digits = digits)
+            }
+        }
+    }
+}
+
+/// The watch panel, toggled with F2.
+///
+/// While it's shown, Insert types a new watch in, `ADDRESS TYPE FORMAT LABEL` as with the
+/// `watch` command, Page Up and Page Down select a watch and Delete removes it.
+///
+/// Watches are kept per game, next to the ROM in `game.wch`, one per line:
+/// `ADDRESS TYPE FORMAT LABEL`.
+#[derive(Resource, Default)]
+pub struct Watches {
+    pub visible: bool,
+    entries: Vec<Watch>,
+    path: Option<PathBuf>,
+    selected: usize,
+    /// The watch being typed in, the keyboard is the panel's until it's done.
+    editing: Option<String>,
+}
+
+impl Watches {
+    pub fn load() -> Watches {
+        let path = EMU.save_path("wch");
+
+        let entries = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|text| text.lines().filter_map(parse_line).collect())
+            .unwrap_or_default();
+
+        Watches {
+            entries,
+            path,
+            ..Default::default()
+        }
+    }
+
+    /// Whether a watch is being typed in, keys shouldn't reach the game nor the hotkeys.
+    pub fn is_editing(&self) -> bool {
+        self.editing.is_some()
+    }
+
+    pub fn entries(&self) -> &[Watch] {
+        &self.entries
+    }
+
+    pub fn add(&mut self, watch: Watch) {
+        self.entries.push(watch);
+        self.visible = true;
+        self.save();
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watch> {
+        if index >= self.entries.len() {
+            return None;
+        }
+
+        let watch = self.entries.remove(index);
+        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+        self.save();
+
+        Some(watch)
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let text: String = self
+            .entries
+            .iter()
+            .map(|watch| {
+                format!(
+                    "{:#x} {} {} {}\n",
+                    watch.address,
+                    watch.kind.name(),
+                    watch.format.name(),
+                    watch.label
+                )
+            })
+            .collect();
+
+        if let Err(err) = std::fs::write(path, text) {
+            EMU.notify(format!("could not save {}: {}", path.display(), err));
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<Watch> {
+    let mut words = line.splitn(4, ' ');
+
+    let address = usize::from_str_radix(words.next()?.trim_start_matches("0x"), 16).ok()?;
+    let kind = WatchType::parse(words.next()?)?;
+    let format = WatchFormat::parse(words.next()?)?;
+    let label = words.next().unwrap_or("");
+
+    Some(Watch::new(address, kind, format, label))
+}
+
+pub fn toggle_watches(keys: Res<Input<KeyCode>>, mut watches: ResMut<Watches>) {
+    // The watches of the game loaded since, or none once it's closed.
+    if EMU.save_path("wch") != watches.path {
+        let visible = watches.visible;
+        *watches = Watches::load();
+        watches.visible = visible;
+    }
+
+    if keys.just_pressed(KeyCode::F2) && !watches.is_editing() {
+        watches.visible = !watches.visible;
+    }
+}
+
+/// Type a watch in, or select and remove one, while the panel is shown.
+pub fn edit_watches(
+    keys: Res<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut watches: ResMut<Watches>,
+) {
+    let typed: String = characters
+        .iter()
+        .map(|event| event.char)
+        .filter(|c| !c.is_control())
+        .collect();
+
+    if let Some(text) = watches.editing.as_mut() {
+        text.push_str(&typed);
+
+        if keys.just_pressed(KeyCode::Back) {
+            text.pop();
+        }
+        if keys.just_pressed(KeyCode::Escape) {
+            watches.editing = None;
+        }
+        if keys.just_pressed(KeyCode::Return) {
+            let text = watches.editing.take().unwrap_or_default();
+            match Watch::parse(&text) {
+                Ok(watch) => {
+                    watches.add(watch);
+                    watches.selected = watches.entries.len() - 1;
+                }
+                Err(message) => EMU.notify(message),
+            }
+        }
+
+        return;
+    }
+
+    if !watches.visible {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Insert) {
+        watches.editing = Some(String::new());
+    }
+    if keys.just_pressed(KeyCode::PageUp) {
+        watches.selected = watches.selected.saturating_sub(1);
+    }
+    if keys.just_pressed(KeyCode::PageDown) {
+        watches.selected = (watches.selected + 1).min(watches.entries.len().saturating_sub(1));
+    }
+    if keys.just_pressed(KeyCode::Delete) {
+        let selected = watches.selected;
+        if let Some(watch) = watches.remove(selected) {
+            EMU.notify(format!("removed the watch of {:#x}", watch.address));
+        }
+    }
+}
+
+/// Sample every watch after the frame ran, and draw the panel over the game when it's visible.
+pub fn draw_watches(mut wrapper_query: Query<&mut PixelsWrapper>, mut watches: ResMut<Watches>) {
+    for watch in watches.entries.iter_mut() {
+        watch.sample();
+    }
+
+    if !watches.is_editing() && (!watches.visible || watches.entries.is_empty()) {
+        return;
+    }
+
+    let Ok(mut wrapper) = wrapper_query.get_single_mut() else {
+        return;
+    };
+
+    let size = wrapper.pixels.texture().size();
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mut canvas = Canvas::new(wrapper.pixels.frame_mut(), width, height);
+
+    let label_width = 10 * font::GLYPH_WIDTH;
+    let value_width = 11 * font::GLYPH_WIDTH;
+    let rows = watches.entries.len() + watches.is_editing() as usize;
+    let panel_height = rows * font::LINE_HEIGHT + 2;
+
+    canvas.fill_rect(
+        0,
+        0,
+        2 + label_width + value_width + HISTORY + 2,
+        panel_height,
+        overlay::BACKGROUND,
+    );
+
+    for (index, watch) in watches.entries.iter().enumerate() {
+        let y = 1 + index * font::LINE_HEIGHT;
+        let label: String = watch.label.chars().take(9).collect();
+
+        let color = if index == watches.selected {
+            overlay::ACCENT
+        } else {
+            overlay::DIM
+        };
+
+        canvas.text(2, y, &label, color);
+        canvas.text(2 + label_width, y, &watch.formatted(), overlay::TEXT);
+        draw_graph(
+            &mut canvas,
+            2 + label_width + value_width,
+            y,
+            &watch.history,
+        );
+    }
+
+    if let Some(text) = &watches.editing {
+        let y = 1 + watches.entries.len() * font::LINE_HEIGHT;
+        let hint = if text.is_empty() {
+            "ADDRESS TYPE FORMAT LABEL"
+        } else {
+            text
+        };
+
+        canvas.text(2, y, &format!("+ {}_", hint), overlay::TEXT);
+    }
+}
+
+/// Draw the history of a watch as a sparkline scaled between its minimum and maximum.
+fn draw_graph(canvas: &mut Canvas, x: usize, y: usize, history: &VecDeque<i64>) {
+    let (Some(min), Some(max)) = (history.iter().min(), history.iter().max()) else {
+        return;
+    };
+
+    let height = font::LINE_HEIGHT - 2;
+    let range = (max - min).max(1);
+
+    for (column, value) in history.iter().enumerate() {
+        let row = ((value - min) * height as i64 / range) as usize;
+        canvas.pixel(x + column, y + height - row, overlay::ACCENT);
+    }
+}
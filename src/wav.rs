@@ -0,0 +1,72 @@
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The most bytes of samples the 32-bit sizes of the header can count, short of 4 GiB.
+const MAX_DATA_LEN: u64 = u32::MAX as u64 - 36;
+
+/// Streams interleaved stereo samples into a 16-bit PCM WAV file.
+///
+/// The sizes in the header are only known at the end, they're filled in by `finish`. They're
+/// 32 bits, so samples past 4 GiB are refused rather than wrapping them.
+pub struct WavWriter {
+    file: BufWriter<std::fs::File>,
+    samples: u64,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32) -> std::io::Result<WavWriter> {
+        let channels: u16 = 2;
+        let mut file = BufWriter::new(std::fs::File::create(path)?);
+
+        file.write_all(b"RIFF")?;
+        file.write_all(&36u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(
+            &sample_rate
+                .saturating_mul(channels as u32 * 2)
+                .to_le_bytes(),
+        )?;
+        file.write_all(&(channels * 2).to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { file, samples: 0 })
+    }
+
+    pub fn write(&mut self, samples: &[i16]) -> std::io::Result<()> {
+        let samples_len = self.samples + samples.len() as u64;
+        if samples_len * 2 > MAX_DATA_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "a WAV file can't hold more than 4 GiB of audio",
+            ));
+        }
+
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples = samples_len;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        // `write` keeps it under `MAX_DATA_LEN`.
+        let data_len = (self.samples * 2) as u32;
+
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&data_len.to_le_bytes())?;
+
+        self.file.flush()
+    }
+}